@@ -0,0 +1,376 @@
+use anyhow::{anyhow, Context, Result};
+use parking_lot::Mutex;
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::config::Config;
+use crate::simulation::{self, Report, Simulation};
+use crate::stats::Distribution;
+use crate::telemetry::csv_field;
+
+// One run of a batch
+pub struct Run {
+    pub index: usize,
+    pub seed: u64,
+    pub result: Result<Report>,
+}
+
+// Run a simulation `runs` times on `jobs` threads; run i uses the configured seed plus i
+pub fn run(name: &str, config: &Config, runs: usize, jobs: usize) -> Vec<Run> {
+    let first_seed = config.seed.unwrap_or_else(rand::random);
+    let next = AtomicUsize::new(0);
+    let finished = Mutex::new(Vec::with_capacity(runs));
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, runs.max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                // A cancelled batch finishes the runs in progress but starts no more
+                if index >= runs || config.cancel.is_cancelled() {
+                    break;
+                }
+
+                let seed = first_seed.wrapping_add(index as u64);
+                let config = Config {
+                    seed: Some(seed),
+                    ..config.clone()
+                };
+                let result = run_one(name, &config);
+
+                let mut finished = finished.lock();
+                finished.push(Run {
+                    index,
+                    seed,
+                    result,
+                });
+                eprint!("\rCompleted {}/{} runs", finished.len(), runs);
+            });
+        }
+    });
+    eprintln!();
+
+    let mut finished = finished.into_inner();
+    finished.sort_by_key(|run| run.index);
+    finished
+}
+
+fn run_one(name: &str, config: &Config) -> Result<Report> {
+    let mut simulation =
+        simulation::create(name).with_context(|| format!("Unknown simulation '{}'", name))?;
+    run_caught(simulation.as_mut(), config)
+}
+
+// A run that panics is counted as failed instead of taking the whole batch down
+fn run_caught(simulation: &mut dyn Simulation, config: &Config) -> Result<Report> {
+    let name = simulation.name();
+    panic::catch_unwind(AssertUnwindSafe(|| simulation::run(simulation, config)))
+        .map_err(|_| anyhow!("{} simulation panicked", name))?
+}
+
+// Aggregate statistics over every successful run of a batch
+pub struct Summary {
+    simulation: String,
+    runs: usize,
+    failures: usize,
+    outcomes: Vec<(&'static str, Distribution)>, // Simulated time to each kind of outcome
+    metrics: Vec<(Cow<'static, str>, Distribution)>, // Ticks, elapsed time and every reported stat
+}
+
+impl Summary {
+    pub fn new(simulation: &str, runs: &[Run]) -> Self {
+        let reports: Vec<&Report> = runs.iter().filter_map(|r| r.result.as_ref().ok()).collect();
+
+        let mut kinds: Vec<&'static str> = reports.iter().map(|r| r.outcome.kind()).collect();
+        kinds.sort_unstable();
+        kinds.dedup();
+        let outcomes = kinds
+            .into_iter()
+            .map(|kind| {
+                let elapsed: Vec<f64> = reports
+                    .iter()
+                    .filter(|r| r.outcome.kind() == kind)
+                    .map(|r| r.elapsed_secs)
+                    .collect();
+                (kind, Distribution::new(&elapsed))
+            })
+            .collect();
+
+        let mut metrics: Vec<(Cow<'static, str>, Distribution)> = vec![
+            (
+                "ticks".into(),
+                Distribution::new(&reports.iter().map(|r| r.ticks as f64).collect::<Vec<_>>()),
+            ),
+            (
+                "elapsed_secs".into(),
+                Distribution::new(&reports.iter().map(|r| r.elapsed_secs).collect::<Vec<_>>()),
+            ),
+        ];
+        for name in stat_names(&reports) {
+            let values: Vec<f64> = reports.iter().filter_map(|r| stat(r, name)).collect();
+            metrics.push((name.to_string().into(), Distribution::new(&values)));
+        }
+
+        Summary {
+            simulation: simulation.to_string(),
+            runs: runs.len(),
+            failures: runs.len() - reports.len(),
+            outcomes,
+            metrics,
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "=== {} batch: {} runs, {} failed ===",
+            self.simulation, self.runs, self.failures
+        )?;
+
+        writeln!(
+            f,
+            "{:<20} {:>6} {:>7} {:>12} {:>10} {:>10} {:>10} {:>10}",
+            "outcome", "runs", "share", "mean_secs", "min", "p50", "p95", "max"
+        )?;
+        for (kind, elapsed) in &self.outcomes {
+            writeln!(
+                f,
+                "{:<20} {:>6} {:>6.1}% {:>12.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2}",
+                kind,
+                elapsed.count,
+                100.0 * elapsed.count as f64 / self.runs.max(1) as f64,
+                elapsed.mean,
+                elapsed.min,
+                elapsed.p50,
+                elapsed.p95,
+                elapsed.max
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<28} {:>12} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "metric", "mean", "std_dev", "min", "p50", "p95", "max"
+        )?;
+        for (name, d) in &self.metrics {
+            writeln!(
+                f,
+                "{:<28} {:>12.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2}",
+                name, d.mean, d.std_dev, d.min, d.p50, d.p95, d.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Write runs.csv (one row per run) and summary.csv into `dir`
+pub fn write_csv(dir: &Path, runs: &[Run], summary: &Summary) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let reports: Vec<&Report> = runs.iter().filter_map(|r| r.result.as_ref().ok()).collect();
+    let stat_names = stat_names(&reports);
+
+    let mut out = create(&dir.join("runs.csv"))?;
+    write!(out, "run,seed,outcome,ticks,elapsed_secs")?;
+    for name in &stat_names {
+        write!(out, ",{}", name)?;
+    }
+    writeln!(out, ",error")?;
+    for run in runs {
+        write!(out, "{},{}", run.index, run.seed)?;
+        match &run.result {
+            Ok(report) => {
+                write!(
+                    out,
+                    ",{},{},{}",
+                    csv_field(&report.outcome.to_string()),
+                    report.ticks,
+                    report.elapsed_secs
+                )?;
+                for name in &stat_names {
+                    match stat(report, name) {
+                        Some(value) => write!(out, ",{}", value)?,
+                        None => write!(out, ",")?,
+                    }
+                }
+                writeln!(out, ",")?;
+            }
+            Err(e) => {
+                write!(out, ",failed,,")?;
+                for _ in &stat_names {
+                    write!(out, ",")?;
+                }
+                writeln!(out, ",{}", csv_field(&format!("{:#}", e)))?;
+            }
+        }
+    }
+    out.flush()?;
+
+    let mut out = create(&dir.join("summary.csv"))?;
+    writeln!(out, "group,metric,count,mean,std_dev,min,p50,p95,max")?;
+    let rows = summary
+        .metrics
+        .iter()
+        .map(|(name, d)| ("all", name.as_ref(), d))
+        .chain(
+            summary
+                .outcomes
+                .iter()
+                .map(|(kind, d)| (*kind, "elapsed_secs", d)),
+        );
+    for (group, metric, d) in rows {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{}",
+            group, metric, d.count, d.mean, d.std_dev, d.min, d.p50, d.p95, d.max
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// Names of the stats reported by a batch's runs, in report order
+fn stat_names<'a>(reports: &[&'a Report]) -> Vec<&'a str> {
+    let mut names: Vec<&str> = Vec::new();
+    for report in reports {
+        for (name, _) in &report.stats {
+            if !names.contains(&name.as_ref()) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+fn stat(report: &Report, name: &str) -> Option<f64> {
+    report
+        .stats
+        .iter()
+        .find(|(stat, _)| *stat == name)
+        .map(|(_, value)| *value)
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>> {
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    Ok(BufWriter::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use crate::simulation::{Outcome, Step};
+
+    fn report(outcome: Outcome, ticks: u64, readings: Option<f64>) -> Result<Report> {
+        Ok(Report {
+            simulation: "nuclear",
+            outcome,
+            seed: 1,
+            ticks,
+            elapsed_secs: ticks as f64 * 2.0,
+            stats: readings
+                .map(|value| vec![("readings".into(), value)])
+                .unwrap_or_default(),
+        })
+    }
+
+    fn runs(results: Vec<Result<Report>>) -> Vec<Run> {
+        results
+            .into_iter()
+            .enumerate()
+            .map(|(index, result)| Run {
+                index,
+                seed: index as u64,
+                result,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_summary_covers_the_successful_runs() {
+        let runs = runs(vec![
+            report(Outcome::Scram, 10, Some(4.0)),
+            report(Outcome::Completed, 30, Some(8.0)),
+            Err(anyhow!("failed to start")),
+            report(Outcome::Scram, 20, None),
+        ]);
+        let summary = Summary::new("nuclear", &runs);
+        assert_eq!((summary.runs, summary.failures), (4, 1));
+
+        let outcomes: Vec<_> = summary
+            .outcomes
+            .iter()
+            .map(|(kind, elapsed)| (*kind, elapsed.count, elapsed.mean))
+            .collect();
+        assert_eq!(outcomes, [("completed", 1, 60.0), ("scram", 2, 30.0)]);
+
+        let metric = |name: &str| summary.metrics.iter().find(|(metric, _)| *metric == name);
+        assert_eq!(metric("ticks").unwrap().1.mean, 20.0);
+        assert_eq!(metric("elapsed_secs").unwrap().1.max, 60.0);
+        // Stats are summarised over the runs that reported them
+        let readings = metric("readings").unwrap().1;
+        assert_eq!((readings.count, readings.mean), (2, 6.0));
+        assert!(metric("missing").is_none());
+    }
+
+    #[test]
+    fn an_empty_batch_has_an_empty_summary() {
+        let summary = Summary::new("nuclear", &[]);
+        assert_eq!((summary.runs, summary.failures), (0, 0));
+        assert!(summary.outcomes.is_empty());
+        assert_eq!(summary.metrics[0].1.count, 0);
+    }
+
+    // Panics on its first step
+    struct Faulty {
+        clock: Clock,
+    }
+
+    impl Simulation for Faulty {
+        fn name(&self) -> &'static str {
+            "faulty"
+        }
+
+        fn clock(&self) -> &Clock {
+            &self.clock
+        }
+
+        fn configure(&mut self, _config: &Config) -> Result<()> {
+            Ok(())
+        }
+
+        fn start(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn step(&mut self) -> Result<Step> {
+            panic!("the faulty simulation broke down");
+        }
+
+        fn stop(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn report(&self) -> Report {
+            report(Outcome::Completed, 0, None).unwrap()
+        }
+    }
+
+    #[test]
+    fn a_run_that_panics_fails_on_its_own() {
+        let mut simulation = Faulty {
+            clock: Clock::default(),
+        };
+        let error = run_caught(&mut simulation, &Config::default()).unwrap_err();
+        assert_eq!(error.to_string(), "faulty simulation panicked");
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    running: AtomicUsize, // Runs currently being driven with this token
+}
+
+// Shared flag asking running simulations to stop at their next step, e.g. on Ctrl-C
+#[derive(Clone, Default)]
+pub struct Cancel {
+    inner: Arc<Inner>,
+}
+
+impl fmt::Debug for Cancel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cancel")
+            .field("cancelled", &self.is_cancelled())
+            .field("running", &self.running())
+            .finish()
+    }
+}
+
+impl Cancel {
+    pub fn new() -> Self {
+        Cancel::default()
+    }
+
+    // Ask every run using this token to stop; returns false if nothing was running to stop
+    pub fn cancel(&self) -> bool {
+        if self.running() == 0 {
+            return false;
+        }
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        true
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    // Clear a previous cancellation before starting new runs
+    pub fn reset(&self) {
+        self.inner.cancelled.store(false, Ordering::SeqCst);
+    }
+
+    pub fn running(&self) -> usize {
+        self.inner.running.load(Ordering::SeqCst)
+    }
+
+    // Count a run as in progress until the guard is dropped
+    pub fn enter(&self) -> RunGuard {
+        self.inner.running.fetch_add(1, Ordering::SeqCst);
+        RunGuard {
+            cancel: self.clone(),
+        }
+    }
+}
+
+pub struct RunGuard {
+    cancel: Cancel,
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        self.cancel.inner.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_running_simulations_can_be_cancelled() {
+        let cancel = Cancel::new();
+        assert!(!cancel.cancel());
+        assert!(!cancel.is_cancelled());
+
+        let run = cancel.enter();
+        let other = cancel.clone().enter();
+        assert_eq!(cancel.running(), 2);
+        assert!(cancel.cancel());
+        assert!(cancel.is_cancelled());
+
+        drop(run);
+        drop(other);
+        assert_eq!(cancel.running(), 0);
+        // The request stands until it is reset for the next runs
+        assert!(cancel.is_cancelled());
+        cancel.reset();
+        assert!(!cancel.is_cancelled());
+    }
+}
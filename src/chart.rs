@@ -0,0 +1,504 @@
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::events::{Event, EventSink, Record};
+use crate::sim_nuclear::control::TARGET_BAND;
+use crate::simulation::Outcome;
+
+// Size of one chart panel, in pixels
+const WIDTH: f64 = 760.0;
+const PANEL_HEIGHT: f64 = 260.0;
+// Space around the plot area of a panel for the title and axis labels
+const MARGIN_LEFT: f64 = 60.0;
+const MARGIN_RIGHT: f64 = 20.0;
+const MARGIN_TOP: f64 = 36.0;
+const MARGIN_BOTTOM: f64 = 40.0;
+// Gridlines and labels along each axis
+const AXIS_TICKS: usize = 5;
+
+// One chart of the report
+enum Panel {
+    Lines(LineChart),
+    Histogram(Histogram),
+}
+
+// A reading over simulation time
+struct LineChart {
+    title: &'static str,
+    y_label: &'static str,
+    points: Vec<(f64, f64)>, // (simulation seconds, reading)
+    colour: &'static str,
+    band: Option<(f64, f64)>, // Target range shaded behind the line
+    markers: Vec<f64>,        // Simulation seconds at which something notable happened
+}
+
+// How often each range of values occurred
+struct Histogram {
+    title: &'static str,
+    x_label: &'static str,
+    values: Vec<f64>,
+    colour: &'static str,
+}
+
+// Readings collected from one run's events
+#[derive(Default)]
+struct Series {
+    simulation: String,
+    temperature: Vec<(f64, f64)>,
+    power: Vec<(f64, f64)>,
+    wind_speed: Vec<(f64, f64)>,
+    structural_health: Vec<(f64, f64)>,
+    repairs: Vec<f64>,
+    inventory: Vec<(f64, f64)>,
+    ordered_at: HashMap<usize, f64>, // Simulation time each unserved cafe ticket was ordered
+    waits: Vec<f64>,
+}
+
+impl Series {
+    fn panels(&mut self) -> Vec<Panel> {
+        let band = (*TARGET_BAND.start() as f64, *TARGET_BAND.end() as f64);
+        let mut panels = Vec::new();
+        if !self.temperature.is_empty() {
+            panels.push(Panel::Lines(LineChart {
+                title: "Reactor temperature against the target band",
+                y_label: "°C",
+                points: std::mem::take(&mut self.temperature),
+                colour: "#d62728",
+                band: Some(band),
+                markers: Vec::new(),
+            }));
+        }
+        if !self.power.is_empty() {
+            panels.push(Panel::Lines(LineChart {
+                title: "Reactor power against the target band",
+                y_label: "W",
+                points: std::mem::take(&mut self.power),
+                colour: "#1f77b4",
+                band: Some(band),
+                markers: Vec::new(),
+            }));
+        }
+        if !self.wind_speed.is_empty() {
+            panels.push(Panel::Lines(LineChart {
+                title: "Wind speed",
+                y_label: "km/h",
+                points: std::mem::take(&mut self.wind_speed),
+                colour: "#2ca02c",
+                band: None,
+                markers: Vec::new(),
+            }));
+        }
+        if !self.structural_health.is_empty() {
+            panels.push(Panel::Lines(LineChart {
+                title: "Structural health (dashed lines mark repairs)",
+                y_label: "%",
+                points: std::mem::take(&mut self.structural_health),
+                colour: "#9467bd",
+                band: None,
+                markers: std::mem::take(&mut self.repairs),
+            }));
+        }
+        if !self.inventory.is_empty() {
+            panels.push(Panel::Lines(LineChart {
+                title: "Bean inventory",
+                y_label: "g",
+                points: std::mem::take(&mut self.inventory),
+                colour: "#8c564b",
+                band: None,
+                markers: Vec::new(),
+            }));
+        }
+        if !self.waits.is_empty() {
+            panels.push(Panel::Histogram(Histogram {
+                title: "Cafe wait times, from ordering to being served",
+                x_label: "seconds",
+                values: std::mem::take(&mut self.waits),
+                colour: "#ff7f0e",
+            }));
+        }
+        panels
+    }
+}
+
+// Collects the key time series of a run from its events and writes them as an SVG report
+// when the run finishes; each run replaces the previous report
+pub struct ChartReport {
+    path: PathBuf,
+    series: Mutex<Series>,
+}
+
+impl ChartReport {
+    pub fn new(path: &Path) -> Self {
+        ChartReport {
+            path: path.to_path_buf(),
+            series: Mutex::new(Series::default()),
+        }
+    }
+
+    fn save(&self, svg: &str) -> Result<()> {
+        fs::write(&self.path, svg)
+            .with_context(|| format!("Failed to write chart report {}", self.path.display()))
+    }
+}
+
+impl EventSink for ChartReport {
+    fn record(&self, record: &Record) {
+        let mut series = self.series.lock();
+        let now = record.sim_time_secs.unwrap_or_default();
+        match &record.event {
+            Event::SimulationStarted => {
+                *series = Series {
+                    simulation: record.simulation.clone(),
+                    ..Series::default()
+                }
+            }
+            Event::SimulationFinished { outcome } => {
+                let panels = series.panels();
+                let svg = render(&series.simulation, outcome, &panels);
+                // A failed report must not take the run down with it
+                if let Err(e) = self.save(&svg) {
+                    eprintln!("{:#}", e);
+                }
+            }
+            Event::ReactorReading {
+                temperature_c,
+                power_w,
+                ..
+            } => {
+                series.temperature.push((now, *temperature_c as f64));
+                series.power.push((now, *power_w as f64));
+            }
+            Event::WeatherReading {
+                wind_speed_kmh,
+                structural_health_pct,
+                ..
+            } => {
+                series.wind_speed.push((now, *wind_speed_kmh as f64));
+                series
+                    .structural_health
+                    .push((now, *structural_health_pct as f64));
+            }
+            Event::Repair { .. } => series.repairs.push(now),
+            Event::InventoryChanged { grams } => series.inventory.push((now, *grams as f64)),
+            Event::OrderPlaced { ticket, .. } => {
+                series.ordered_at.insert(*ticket, now);
+            }
+            Event::OrderServed { ticket, .. } => {
+                if let Some(ordered_at) = series.ordered_at.remove(ticket) {
+                    series.waits.push(now - ordered_at);
+                }
+            }
+            Event::OrderCancelled { ticket, .. } => {
+                series.ordered_at.remove(ticket);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Render the panels one below the other as a standalone SVG document
+fn render(simulation: &str, outcome: &Outcome, panels: &[Panel]) -> String {
+    let height = MARGIN_TOP + PANEL_HEIGHT * panels.len().max(1) as f64;
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="12">"#,
+        w = WIDTH,
+        h = height
+    );
+    let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="24" font-size="18" font-weight="bold">{} run: {}</text>"#,
+        MARGIN_LEFT,
+        escape(simulation),
+        escape(&outcome.to_string())
+    );
+    if panels.is_empty() {
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}">No charts for the {} simulation</text>"#,
+            MARGIN_LEFT,
+            MARGIN_TOP + 40.0,
+            escape(simulation)
+        );
+    }
+    for (i, panel) in panels.iter().enumerate() {
+        let top = MARGIN_TOP + PANEL_HEIGHT * i as f64;
+        match panel {
+            Panel::Lines(chart) => chart.draw(&mut svg, top),
+            Panel::Histogram(chart) => chart.draw(&mut svg, top),
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// Maps data coordinates onto the plot area of one panel
+struct Plot {
+    left: f64,
+    right: f64,
+    top: f64,
+    bottom: f64,
+    x_range: (f64, f64),
+    y_range: (f64, f64),
+}
+
+impl Plot {
+    fn new(top: f64, x_range: (f64, f64), y_range: (f64, f64)) -> Self {
+        Plot {
+            left: MARGIN_LEFT,
+            right: WIDTH - MARGIN_RIGHT,
+            top: top + MARGIN_TOP,
+            bottom: top + PANEL_HEIGHT - MARGIN_BOTTOM,
+            x_range: widen(x_range),
+            y_range: widen(y_range),
+        }
+    }
+
+    fn x(&self, value: f64) -> f64 {
+        let (min, max) = self.x_range;
+        self.left + (value - min) / (max - min) * (self.right - self.left)
+    }
+
+    fn y(&self, value: f64) -> f64 {
+        let (min, max) = self.y_range;
+        self.bottom - (value - min) / (max - min) * (self.bottom - self.top)
+    }
+
+    // Title, frame, gridlines and tick labels
+    fn axes(&self, svg: &mut String, title: &str, x_label: &str, y_label: &str) {
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" font-size="14" font-weight="bold">{}</text>"#,
+            self.left,
+            self.top - 12.0,
+            escape(title)
+        );
+        for i in 0..=AXIS_TICKS {
+            let fraction = i as f64 / AXIS_TICKS as f64;
+            let x_value = self.x_range.0 + fraction * (self.x_range.1 - self.x_range.0);
+            let y_value = self.y_range.0 + fraction * (self.y_range.1 - self.y_range.0);
+            // Rounded so the document is not littered with floating-point noise
+            let x = (self.x(x_value) * 10.0).round() / 10.0;
+            let y = (self.y(y_value) * 10.0).round() / 10.0;
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{l}" y1="{y}" x2="{r}" y2="{y}" stroke="#e0e0e0"/><text x="{tx}" y="{ty}" text-anchor="end">{v}</text>"##,
+                l = self.left,
+                r = self.right,
+                y = y,
+                tx = self.left - 6.0,
+                ty = y + 4.0,
+                v = label(y_value)
+            );
+            let _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+                x,
+                self.bottom + 16.0,
+                label(x_value)
+            );
+        }
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="black"/>"#,
+            self.left,
+            self.top,
+            self.right - self.left,
+            self.bottom - self.top
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+            (self.left + self.right) / 2.0,
+            self.bottom + 32.0,
+            escape(x_label)
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="14" y="{y}" text-anchor="middle" transform="rotate(-90 14 {y})">{}</text>"#,
+            escape(y_label),
+            y = (self.top + self.bottom) / 2.0
+        );
+    }
+}
+
+impl LineChart {
+    fn draw(&self, svg: &mut String, top: f64) {
+        let xs = self.points.iter().map(|(x, _)| *x);
+        let ys = self.points.iter().map(|(_, y)| *y);
+        let band = self.band.into_iter().flat_map(|(low, high)| [low, high]);
+        let x_range = range(xs.chain(self.markers.iter().copied()).chain([0.0]));
+        let plot = Plot::new(top, x_range, range(ys.chain(band)));
+        plot.axes(svg, self.title, "simulation seconds", self.y_label);
+
+        if let Some((low, high)) = self.band {
+            let _ = writeln!(
+                svg,
+                r##"<rect x="{}" y="{}" width="{}" height="{}" fill="#2ca02c" fill-opacity="0.15"/>"##,
+                plot.left,
+                plot.y(high),
+                plot.right - plot.left,
+                plot.y(low) - plot.y(high)
+            );
+        }
+        for marker in &self.markers {
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{x}" y1="{}" x2="{x}" y2="{}" stroke="#ff7f0e" stroke-dasharray="4 3"/>"##,
+                plot.top,
+                plot.bottom,
+                x = plot.x(*marker)
+            );
+        }
+        let path: Vec<String> = self
+            .points
+            .iter()
+            .map(|(x, y)| format!("{:.1},{:.1}", plot.x(*x), plot.y(*y)))
+            .collect();
+        let _ = writeln!(
+            svg,
+            r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="2"/>"#,
+            path.join(" "),
+            self.colour
+        );
+    }
+}
+
+impl Histogram {
+    fn draw(&self, svg: &mut String, top: f64) {
+        // Roughly the square root of the number of values, within reason
+        let bins = ((self.values.len() as f64).sqrt().ceil() as usize).clamp(5, 30);
+        let (min, max) = widen(range(self.values.iter().copied().chain([0.0])));
+        let width = (max - min) / bins as f64;
+        let mut counts = vec![0usize; bins];
+        for value in &self.values {
+            let bin = (((value - min) / width) as usize).min(bins - 1);
+            counts[bin] += 1;
+        }
+
+        let highest = counts.iter().copied().max().unwrap_or_default() as f64;
+        let plot = Plot::new(top, (min, max), (0.0, highest));
+        plot.axes(svg, self.title, self.x_label, "orders");
+        for (bin, count) in counts.iter().enumerate() {
+            let left = plot.x(min + bin as f64 * width);
+            let right = plot.x(min + (bin + 1) as f64 * width);
+            let y = plot.y(*count as f64);
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}" stroke="white"/>"#,
+                left,
+                y,
+                right - left,
+                plot.bottom - y,
+                self.colour
+            );
+        }
+    }
+}
+
+fn range(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+        (min.min(v), max.max(v))
+    })
+}
+
+// Give flat or empty data some height so it can still be drawn
+fn widen((min, max): (f64, f64)) -> (f64, f64) {
+    if !min.is_finite() || !max.is_finite() {
+        (0.0, 1.0)
+    } else if max <= min {
+        (min - 1.0, max + 1.0)
+    } else {
+        (min, max)
+    }
+}
+
+fn label(value: f64) -> String {
+    if value.abs() >= 100.0 || value.fract() == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.1}", value)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every element is closed in order, the document is a single <svg> element and no number is
+    // left undefined
+    fn assert_well_formed(svg: &str) {
+        assert!(svg.starts_with("<svg "), "{}", svg);
+        assert!(svg.ends_with("</svg>\n"), "{}", svg);
+        assert!(!svg.contains("NaN") && !svg.contains("inf"), "{}", svg);
+
+        let mut open = Vec::new();
+        let mut rest = svg;
+        while let Some(start) = rest.find('<') {
+            let end = start + rest[start..].find('>').expect("unterminated tag");
+            let tag = &rest[start + 1..end];
+            assert!(!tag.contains('<'), "{}", tag);
+            let name = tag
+                .trim_start_matches('/')
+                .split_whitespace()
+                .next()
+                .unwrap();
+            if tag.starts_with('/') {
+                assert_eq!(open.pop(), Some(name), "unbalanced </{}>", name);
+            } else if !tag.ends_with('/') {
+                open.push(name);
+            }
+            // Text between tags must have its markup escaped
+            let text = &rest[end + 1..];
+            let text = &text[..text.find('<').unwrap_or(text.len())];
+            assert!(!text.contains('>'), "{}", text);
+            rest = &rest[end + 1..];
+            if open.is_empty() {
+                assert_eq!(rest.trim(), "");
+            }
+        }
+        assert!(open.is_empty(), "unclosed {:?}", open);
+    }
+
+    #[test]
+    fn a_run_without_readings_still_gets_a_chart() {
+        let svg = render("home", &Outcome::Completed, &Series::default().panels());
+        assert_well_formed(&svg);
+        assert!(svg.contains("No charts for the home simulation"));
+    }
+
+    #[test]
+    fn every_reading_gets_a_panel() {
+        let mut series = Series {
+            simulation: "<town & co>".to_string(),
+            temperature: vec![(0.0, 250.0), (1.0, 260.0), (2.0, 255.0)],
+            // A single flat reading still needs a range to draw it in
+            power: vec![(0.0, 150.0)],
+            structural_health: vec![(0.0, 100.0), (5.0, 80.0)],
+            repairs: vec![3.0],
+            waits: vec![1.5, 2.0, 2.0, 9.0],
+            ..Series::default()
+        };
+        let panels = series.panels();
+        assert_eq!(panels.len(), 4);
+
+        let svg = render(&series.simulation, &Outcome::Scram, &panels);
+        assert_well_formed(&svg);
+        assert!(svg.contains("&lt;town &amp; co&gt; run: SCRAM"));
+        assert!(svg.contains("Cafe wait times"));
+        assert!(svg.contains(r#"height="1076""#));
+    }
+}
@@ -0,0 +1,455 @@
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::cancel::Cancel;
+use crate::clock::{Clock, ClockMode};
+use crate::config::Config;
+use crate::events::{EventLog, EventSink, Record};
+use crate::faults;
+use crate::simulation::{self, Report, Simulation, Step};
+use crate::snapshot::Checkpoint;
+use crate::telemetry::Row;
+
+// A scripted run of one simulation with expectations about what happens during it, e.g.
+//
+//   simulation = "nuclear"
+//   ticks = 10
+//   [[inject]]
+//   at_tick = 5
+//   fault = "power_surge"
+//   [[expect]]
+//   event = "emergency_power_reduction"
+//   after_tick = 5
+//   within_ticks = 1
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    #[serde(skip)]
+    pub name: String, // File name without its extension
+    pub simulation: String,
+    #[serde(default)]
+    pub seed: u64, // Fixed, so a script behaves the same every time it is run
+    pub ticks: Option<u64>, // End the run after this many ticks; None lets it finish by itself
+    #[serde(default)]
+    pub config: Config,
+    #[serde(default)]
+    pub inject: Vec<Injection>,
+    #[serde(default)]
+    pub expect: Vec<Expectation>,
+}
+
+// A fault forced at a tick, as with `--fault NAME@TICK`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Injection {
+    pub at_tick: u64,
+    pub fault: String,
+}
+
+// Something that must hold for the run; each names exactly one of `event`, `outcome` or `stat`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Expectation {
+    pub event: Option<String>,   // Event name as in the event log, e.g. "scram"
+    pub never: bool,             // The event must not happen instead
+    pub after_tick: Option<u64>, // Only count events from this tick on
+    pub within_ticks: Option<u64>, // ...and at most this many ticks after it
+    pub outcome: Option<String>, // Kind of outcome, e.g. "evacuation"; "stopped" when `ticks` ran out
+    pub stat: Option<String>,    // Name of a stat in the final report
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Script> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read check {}", path.display()))?;
+        let mut script: Script =
+            toml::from_str(&text).with_context(|| format!("Invalid check {}", path.display()))?;
+        script.name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        script
+            .validate()
+            .with_context(|| format!("Invalid check {}", path.display()))?;
+        Ok(script)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if simulation::create(&self.simulation).is_none() {
+            bail!("Unknown simulation '{}'", self.simulation);
+        }
+        let known = faults::names(&self.simulation);
+        for injection in &self.inject {
+            if injection.at_tick == 0 {
+                bail!("Injections are at ticks counted from 1, not tick 0");
+            }
+            if !known.contains(&injection.fault.as_str()) {
+                bail!(
+                    "The {} simulation can inject {}, not '{}'",
+                    self.simulation,
+                    known.join(", "),
+                    injection.fault
+                );
+            }
+        }
+        for expectation in &self.expect {
+            let kinds = [&expectation.event, &expectation.outcome, &expectation.stat];
+            if kinds.iter().filter(|kind| kind.is_some()).count() != 1 {
+                bail!("Each expectation needs exactly one of event, outcome or stat");
+            }
+        }
+        self.config.validate()
+    }
+
+    // Run the script in virtual time and judge every expectation
+    pub fn run(&self, cancel: &Cancel) -> Verdict {
+        let collector = Arc::new(Collector::default());
+        let events = EventLog::new();
+        events.add_sink(collector.clone());
+
+        let mut config = Config {
+            seed: Some(self.seed),
+            clock: ClockMode::Virtual,
+            events,
+            cancel: cancel.clone(),
+            checkpoint: Checkpoint {
+                path: None,
+                at_tick: self.ticks,
+            },
+            ..self.config.clone()
+        };
+        for injection in &self.inject {
+            config
+                .faults
+                .entry(injection.fault.clone())
+                .or_default()
+                .at_ticks
+                .push(injection.at_tick);
+        }
+
+        let mut simulation = simulation::create(&self.simulation).expect("validated simulation");
+        let mut probe = Probe {
+            simulation: simulation.as_mut(),
+            tick: Arc::clone(&collector.tick),
+        };
+        let report = simulation::run(&mut probe, &config);
+        let failures = match &report {
+            Ok(report) => {
+                let seen = collector.seen.lock();
+                self.expect
+                    .iter()
+                    .filter_map(|expectation| expectation.check(report, &seen).err())
+                    .collect()
+            }
+            Err(e) => vec![format!("Run failed: {:#}", e)],
+        };
+        Verdict {
+            name: self.name.clone(),
+            report: report.ok(),
+            failures,
+        }
+    }
+}
+
+impl Expectation {
+    // Ok if the expectation holds, otherwise why it does not
+    fn check(&self, report: &Report, seen: &[(u64, String)]) -> Result<(), String> {
+        if let Some(event) = &self.event {
+            let from = self.after_tick.unwrap_or(0);
+            let until = self.within_ticks.map(|ticks| from + ticks);
+            let window = match (self.after_tick, until) {
+                (_, Some(until)) => format!(" between ticks {} and {}", from, until),
+                (Some(from), None) => format!(" from tick {}", from),
+                (None, None) => String::new(),
+            };
+            let first = seen
+                .iter()
+                .filter(|(tick, name)| {
+                    name == event && *tick >= from && until.is_none_or(|until| *tick <= until)
+                })
+                .map(|(tick, _)| *tick)
+                .next();
+            return match (first, self.never) {
+                (Some(tick), true) => Err(format!(
+                    "expected no {}{}, but it happened at tick {}",
+                    event, window, tick
+                )),
+                (None, false) => Err(format!(
+                    "expected {}{}, but it never happened",
+                    event, window
+                )),
+                _ => Ok(()),
+            };
+        }
+
+        if let Some(outcome) = &self.outcome {
+            if report.outcome.kind() != outcome {
+                return Err(format!(
+                    "expected outcome {}, but the run ended in {}",
+                    outcome,
+                    report.outcome.kind()
+                ));
+            }
+            return Ok(());
+        }
+
+        let stat = self.stat.as_deref().unwrap_or_default();
+        let value = match report.stats.iter().find(|(name, _)| *name == stat) {
+            Some((_, value)) => *value,
+            None => return Err(format!("the report has no stat '{}'", stat)),
+        };
+        if self.min.is_some_and(|min| value < min) || self.max.is_some_and(|max| value > max) {
+            let bound = match (self.min, self.max) {
+                (Some(min), Some(max)) => format!("between {} and {}", min, max),
+                (Some(min), None) => format!("at least {}", min),
+                (_, max) => format!("at most {}", max.unwrap_or_default()),
+            };
+            return Err(format!("expected {} {}, but it was {}", stat, bound, value));
+        }
+        Ok(())
+    }
+}
+
+// Result of running one script
+pub struct Verdict {
+    pub name: String,
+    pub report: Option<Report>,
+    pub failures: Vec<String>, // One line per expectation that did not hold
+}
+
+impl Verdict {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.passed() { "PASS" } else { "FAIL" };
+        write!(f, "{} {}", status, self.name)?;
+        if let Some(report) = &self.report {
+            write!(f, " ({} after {} ticks)", report.outcome, report.ticks)?;
+        }
+        for failure in &self.failures {
+            write!(f, "\n  - {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+// Event names of a run, with the tick during which each happened
+#[derive(Default)]
+struct Collector {
+    tick: Arc<AtomicU64>,
+    seen: Mutex<Vec<(u64, String)>>,
+}
+
+impl EventSink for Collector {
+    fn record(&self, record: &Record) {
+        // Named as in the event log, e.g. "emergency_power_reduction"
+        if let Ok(Value::Object(fields)) = serde_json::to_value(&record.event) {
+            if let Some(Value::String(name)) = fields.get("event") {
+                let tick = self.tick.load(Ordering::SeqCst);
+                self.seen.lock().push((tick, name.clone()));
+            }
+        }
+    }
+}
+
+// Passes every call through to the simulation, telling the collector which tick is under way
+struct Probe<'a> {
+    simulation: &'a mut dyn Simulation,
+    tick: Arc<AtomicU64>,
+}
+
+impl Simulation for Probe<'_> {
+    fn name(&self) -> &'static str {
+        self.simulation.name()
+    }
+
+    fn clock(&self) -> &Clock {
+        self.simulation.clock()
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<()> {
+        self.simulation.configure(config)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.simulation.start()
+    }
+
+    fn step(&mut self) -> Result<Step> {
+        self.tick
+            .store(self.simulation.report().ticks + 1, Ordering::SeqCst);
+        self.simulation.step()
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.simulation.stop()
+    }
+
+    fn report(&self) -> Report {
+        self.simulation.report()
+    }
+
+    fn telemetry(&self) -> Result<Row> {
+        self.simulation.telemetry()
+    }
+
+    fn inject(&mut self, event: &str) -> Result<()> {
+        self.simulation.inject(event)
+    }
+
+    fn snapshot(&self) -> Result<Value> {
+        self.simulation.snapshot()
+    }
+
+    fn restore(&mut self, state: &Value) -> Result<()> {
+        self.simulation.restore(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Outcome;
+
+    fn report() -> Report {
+        Report {
+            simulation: "nuclear",
+            outcome: Outcome::Scram,
+            seed: 1,
+            ticks: 10,
+            elapsed_secs: 10.0,
+            stats: vec![("readings".into(), 10.0)],
+        }
+    }
+
+    fn seen() -> Vec<(u64, String)> {
+        vec![(3, "power_surge".to_string()), (6, "scram".to_string())]
+    }
+
+    fn event(name: &str) -> Expectation {
+        Expectation {
+            event: Some(name.to_string()),
+            ..Expectation::default()
+        }
+    }
+
+    fn stat(min: Option<f64>, max: Option<f64>) -> Expectation {
+        Expectation {
+            stat: Some("readings".to_string()),
+            min,
+            max,
+            ..Expectation::default()
+        }
+    }
+
+    #[test]
+    fn events_must_happen_within_their_window() {
+        let (report, seen) = (report(), seen());
+        assert!(event("scram").check(&report, &seen).is_ok());
+        assert!(event("evacuation").check(&report, &seen).is_err());
+
+        let in_window = Expectation {
+            after_tick: Some(3),
+            within_ticks: Some(3),
+            ..event("scram")
+        };
+        assert!(in_window.check(&report, &seen).is_ok());
+        let too_soon = Expectation {
+            after_tick: Some(3),
+            within_ticks: Some(2),
+            ..event("scram")
+        };
+        assert_eq!(
+            too_soon.check(&report, &seen).unwrap_err(),
+            "expected scram between ticks 3 and 5, but it never happened"
+        );
+        let too_late = Expectation {
+            after_tick: Some(7),
+            ..event("scram")
+        };
+        assert!(too_late.check(&report, &seen).is_err());
+    }
+
+    #[test]
+    fn never_events_must_not_happen() {
+        let (report, seen) = (report(), seen());
+        let never = |name| Expectation {
+            never: true,
+            ..event(name)
+        };
+        assert!(never("evacuation").check(&report, &seen).is_ok());
+        assert_eq!(
+            never("scram").check(&report, &seen).unwrap_err(),
+            "expected no scram, but it happened at tick 6"
+        );
+    }
+
+    #[test]
+    fn the_outcome_must_match() {
+        let outcome = |kind: &str| Expectation {
+            outcome: Some(kind.to_string()),
+            ..Expectation::default()
+        };
+        assert!(outcome("scram").check(&report(), &[]).is_ok());
+        assert_eq!(
+            outcome("completed").check(&report(), &[]).unwrap_err(),
+            "expected outcome completed, but the run ended in scram"
+        );
+    }
+
+    #[test]
+    fn stats_must_be_within_their_bounds() {
+        let report = report();
+        assert!(stat(Some(10.0), Some(10.0)).check(&report, &[]).is_ok());
+        assert!(stat(None, None).check(&report, &[]).is_ok());
+        assert_eq!(
+            stat(Some(11.0), None).check(&report, &[]).unwrap_err(),
+            "expected readings at least 11, but it was 10"
+        );
+        assert_eq!(
+            stat(Some(1.0), Some(5.0)).check(&report, &[]).unwrap_err(),
+            "expected readings between 1 and 5, but it was 10"
+        );
+        let missing = Expectation {
+            stat: Some("unknown".to_string()),
+            ..Expectation::default()
+        };
+        assert_eq!(
+            missing.check(&report, &[]).unwrap_err(),
+            "the report has no stat 'unknown'"
+        );
+    }
+
+    #[test]
+    fn expectations_name_exactly_one_kind() {
+        let script = |expect| Script {
+            name: "test".to_string(),
+            simulation: "nuclear".to_string(),
+            seed: 0,
+            ticks: None,
+            config: Config::default(),
+            inject: Vec::new(),
+            expect: vec![expect],
+        };
+        script(event("scram")).validate().unwrap();
+        let both = Expectation {
+            stat: Some("readings".to_string()),
+            ..event("scram")
+        };
+        assert!(script(both).validate().is_err());
+        assert!(script(Expectation::default()).validate().is_err());
+    }
+}
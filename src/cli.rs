@@ -0,0 +1,338 @@
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rust_simulations::chart::ChartReport;
+use rust_simulations::clock::ClockMode;
+use rust_simulations::config::Config;
+use rust_simulations::events::{EventLog, JsonLinesSink};
+use rust_simulations::faults;
+use rust_simulations::metrics::{self, Metrics};
+use rust_simulations::sim_cafe::CafeConfig;
+use rust_simulations::sim_factory::FactoryConfig;
+use rust_simulations::sim_home::HomeConfig;
+use rust_simulations::sim_nuclear::NuclearConfig;
+use rust_simulations::sim_town::TownConfig;
+use rust_simulations::sim_weather::WeatherConfig;
+use rust_simulations::snapshot::Checkpoint;
+
+// Command-line interface; running without a subcommand opens the interactive menu
+#[derive(Parser)]
+#[command(version, about = "Concurrency simulations in Rust")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Seed for all random decisions, to reproduce an earlier run
+    #[arg(long, global = true)]
+    pub seed: Option<u64>,
+
+    /// Run in virtual time, as fast as possible, instead of real time
+    #[arg(long, global = true)]
+    pub virtual_time: bool,
+
+    /// Also write every event as JSON lines to this file
+    #[arg(long, global = true, value_name = "PATH")]
+    pub event_log: Option<PathBuf>,
+
+    /// Serve Prometheus metrics at http://127.0.0.1:<PORT>/metrics while running
+    #[arg(long, global = true, value_name = "PORT")]
+    pub metrics_port: Option<u16>,
+
+    /// Scenario file (TOML or JSON), or the name of one in ./scenarios
+    #[arg(long, global = true, value_name = "NAME|PATH")]
+    pub scenario: Option<String>,
+
+    /// Show a live full-screen dashboard instead of the scrolling console output
+    #[arg(long, global = true)]
+    pub dashboard: bool,
+
+    /// Record every random decision and external input of the run to this file
+    #[arg(long, global = true, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+
+    /// Schedule (NAME@TICK), tune (NAME=CHANCE) or disable (NAME=off) a fault; may be repeated
+    #[arg(long = "fault", global = true, value_name = "SPEC")]
+    pub faults: Vec<String>,
+
+    /// Save the state of the run to this file when it ends, so it can be resumed later
+    #[arg(long, global = true, value_name = "PATH")]
+    pub snapshot: Option<PathBuf>,
+
+    /// End the run after this many ticks, e.g. to take a snapshot there
+    #[arg(long, global = true, value_name = "TICK")]
+    pub snapshot_at: Option<u64>,
+
+    /// Write the simulation's readings after every tick to this CSV file
+    #[arg(long, global = true, value_name = "PATH")]
+    pub telemetry: Option<PathBuf>,
+
+    /// Write an SVG report charting the run's key readings to this file when it ends
+    #[arg(long, global = true, value_name = "PATH")]
+    pub chart: Option<PathBuf>,
+}
+
+impl Cli {
+    // Settings shared by every simulation, before any subcommand options are applied.
+    // Command-line options take precedence over the scenario, which takes precedence over defaults.
+    pub fn config(&self) -> Result<Config> {
+        let mut config = match &self.scenario {
+            Some(scenario) => Config::load_scenario(scenario)?,
+            None => Config::default(),
+        };
+
+        let events = EventLog::new();
+        if let Some(path) = &self.event_log {
+            events.add_sink(Arc::new(JsonLinesSink::create(path)?));
+        }
+        if let Some(path) = &self.chart {
+            events.add_sink(Arc::new(ChartReport::new(path)));
+        }
+        config.events = events;
+
+        if self.seed.is_some() {
+            config.seed = self.seed;
+        }
+        if self.virtual_time {
+            config.clock = ClockMode::Virtual;
+        }
+        for spec in &self.faults {
+            faults::apply(&mut config.faults, spec)?;
+        }
+        config.checkpoint = Checkpoint {
+            path: self.snapshot.clone(),
+            at_tick: self.snapshot_at,
+        };
+        config.telemetry = self.telemetry.clone();
+        config.chart = self.chart.clone();
+        Ok(config)
+    }
+
+    // Serve metrics built from the run's events at --metrics-port, if given
+    pub fn serve_metrics(&self, events: &EventLog) -> Result<()> {
+        if let Some(port) = self.metrics_port {
+            let metrics = Arc::new(Metrics::new());
+            metrics::serve(Arc::clone(&metrics), port)?;
+            events.add_sink(metrics);
+            println!("Serving metrics at http://127.0.0.1:{}/metrics", port);
+        }
+        Ok(())
+    }
+
+    pub fn wants_snapshot(&self) -> bool {
+        self.snapshot.is_some() || self.snapshot_at.is_some()
+    }
+
+    // Options that write files about a single run
+    pub fn wants_run_output(&self) -> bool {
+        self.telemetry.is_some() || self.chart.is_some()
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(flatten)]
+    Simulation(SimulationCommand),
+    /// Replay a run saved with --record
+    Replay(ReplayArgs),
+    /// Carry on a run saved with --snapshot; give a different --seed to fork it
+    Resume(ResumeArgs),
+    /// Run a simulation many times with different seeds and summarise the results
+    Batch(BatchArgs),
+    /// Serve an HTTP/WebSocket API on localhost to start, stop, query and inject events into simulations
+    Serve(ServeArgs),
+    /// Run scripted checks against the simulations in virtual time and report which pass
+    Check(CheckArgs),
+}
+
+#[derive(Subcommand)]
+pub enum SimulationCommand {
+    /// Run the Cafe simulation
+    Cafe(CafeArgs),
+    /// Run the Factory simulation (requires RabbitMQ on localhost)
+    Factory(FactoryArgs),
+    /// Run the Home Automation simulation
+    Home(HomeArgs),
+    /// Run the Nuclear Reactor simulation
+    Nuclear(NuclearArgs),
+    /// Run the Weather Machine simulation
+    Weather(WeatherArgs),
+    /// Run every simulation together as one town
+    Town(TownArgs),
+}
+
+// Every option is optional so that unset values keep the simulation defaults
+#[derive(Args)]
+pub struct CafeArgs {
+    /// Seconds during which new customers arrive
+    #[arg(long)]
+    pub duration: Option<u64>,
+    /// Number of baristas
+    #[arg(long)]
+    pub baristas: Option<usize>,
+    /// Number of coffee machines
+    #[arg(long)]
+    pub machines: Option<usize>,
+}
+
+#[derive(Args)]
+pub struct FactoryArgs {
+    /// Production cycles before the factory stops
+    #[arg(long)]
+    pub max_cycles: Option<i32>,
+}
+
+#[derive(Args)]
+pub struct HomeArgs {
+    /// Real seconds to keep the simulation running
+    #[arg(long)]
+    pub duration: Option<u64>,
+    /// Simulated seconds per real second
+    #[arg(long)]
+    pub scale_factor: Option<f64>,
+}
+
+#[derive(Args)]
+pub struct NuclearArgs {
+    /// Stop after this many seconds instead of running until a critical failure
+    #[arg(long)]
+    pub duration: Option<u64>,
+}
+
+#[derive(Args)]
+pub struct WeatherArgs {
+    /// Stop after this many seconds instead of running until a failure
+    #[arg(long)]
+    pub duration: Option<u64>,
+}
+
+#[derive(Args)]
+pub struct TownArgs {
+    /// Seconds the town runs before every simulation is stopped
+    #[arg(long)]
+    pub duration: Option<u64>,
+}
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// Recording to replay
+    pub path: PathBuf,
+}
+
+#[derive(Args)]
+pub struct ResumeArgs {
+    /// Snapshot to resume from
+    pub path: PathBuf,
+}
+
+#[derive(Args)]
+pub struct CheckArgs {
+    /// Check scripts (TOML) to run; defaults to every script in ./checks
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Port to listen on; only connections from this machine are accepted
+    #[arg(long, default_value_t = 8080)]
+    pub port: u16,
+}
+
+// Batch runs always use virtual time; the simulation and its options follow the batch options
+#[derive(Args)]
+pub struct BatchArgs {
+    /// Number of runs
+    #[arg(long, default_value_t = 100)]
+    pub runs: usize,
+    /// Runs executed in parallel; defaults to the number of CPUs
+    #[arg(long)]
+    pub jobs: Option<usize>,
+    /// Directory for runs.csv and summary.csv
+    #[arg(long, value_name = "DIR", default_value = "batch")]
+    pub output: PathBuf,
+    #[command(subcommand)]
+    pub command: SimulationCommand,
+}
+
+impl SimulationCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SimulationCommand::Cafe(_) => "cafe",
+            SimulationCommand::Factory(_) => "factory",
+            SimulationCommand::Home(_) => "home",
+            SimulationCommand::Nuclear(_) => "nuclear",
+            SimulationCommand::Weather(_) => "weather",
+            SimulationCommand::Town(_) => "town",
+        }
+    }
+
+    // Override the configuration with the options given on the command line
+    pub fn apply(&self, config: &mut Config) {
+        match self {
+            SimulationCommand::Cafe(args) => args.apply(&mut config.cafe),
+            SimulationCommand::Factory(args) => args.apply(&mut config.factory),
+            SimulationCommand::Home(args) => args.apply(&mut config.home),
+            SimulationCommand::Nuclear(args) => args.apply(&mut config.nuclear),
+            SimulationCommand::Weather(args) => args.apply(&mut config.weather),
+            SimulationCommand::Town(args) => args.apply(&mut config.town),
+        }
+    }
+}
+
+impl CafeArgs {
+    fn apply(&self, config: &mut CafeConfig) {
+        if let Some(duration) = self.duration {
+            config.duration_secs = duration;
+        }
+        if let Some(baristas) = self.baristas {
+            config.baristas = baristas;
+        }
+        if let Some(machines) = self.machines {
+            config.coffee_machines = machines;
+        }
+    }
+}
+
+impl FactoryArgs {
+    fn apply(&self, config: &mut FactoryConfig) {
+        if let Some(max_cycles) = self.max_cycles {
+            config.max_cycles = max_cycles;
+        }
+    }
+}
+
+impl HomeArgs {
+    fn apply(&self, config: &mut HomeConfig) {
+        if let Some(duration) = self.duration {
+            config.duration_secs = duration;
+        }
+        if let Some(scale_factor) = self.scale_factor {
+            config.scale_factor = scale_factor;
+        }
+    }
+}
+
+impl NuclearArgs {
+    fn apply(&self, config: &mut NuclearConfig) {
+        if self.duration.is_some() {
+            config.duration_secs = self.duration;
+        }
+    }
+}
+
+impl WeatherArgs {
+    fn apply(&self, config: &mut WeatherConfig) {
+        if self.duration.is_some() {
+            config.duration_secs = self.duration;
+        }
+    }
+}
+
+impl TownArgs {
+    fn apply(&self, config: &mut TownConfig) {
+        if let Some(duration) = self.duration {
+            config.duration_secs = duration;
+        }
+    }
+}
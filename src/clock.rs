@@ -0,0 +1,283 @@
+use anyhow::{bail, Result};
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// How simulated time relates to wall-clock time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockMode {
+    #[default]
+    RealTime, // Sleeps take as long as they say
+    Virtual, // Time jumps ahead as soon as every participating thread is waiting
+}
+
+// Source of simulated time shared by every thread of a simulation.
+//
+// In virtual mode the clock counts the threads taking part in the run (the creating thread plus
+// every thread started with `spawn`). Time only advances once all of them are blocked in `sleep`
+// or `wait_until`, and then jumps straight to the earliest deadline. Any thread that changes state
+// another thread may be waiting for must call `notify`. If every participant is waiting without a
+// deadline the run is deadlocked: from then on every wait fails at once, and `check` reports it.
+#[derive(Clone)]
+pub struct Clock {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    mode: ClockMode,
+    origin: Instant,
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+struct State {
+    now: Duration,            // Current virtual time
+    offset: Duration,         // Time the clock was resumed at, added to real elapsed time
+    participants: usize,      // Threads that must be waiting before time can advance
+    waiting: usize,           // Participants currently blocked in the clock
+    deadlines: Vec<Duration>, // Deadlines of the waiting participants
+    epoch: u64,               // Bumped whenever every waiter has to re-check its condition
+    deadlocked: bool,         // Every participant was waiting with no deadline to jump to
+}
+
+impl Clock {
+    pub fn new(mode: ClockMode) -> Self {
+        Clock {
+            inner: Arc::new(Inner {
+                mode,
+                origin: Instant::now(),
+                state: Mutex::new(State {
+                    now: Duration::ZERO,
+                    offset: Duration::ZERO,
+                    participants: 1, // The thread driving the simulation
+                    waiting: 0,
+                    deadlines: Vec::new(),
+                    epoch: 0,
+                    deadlocked: false,
+                }),
+                condvar: Condvar::new(),
+            }),
+        }
+    }
+
+    pub fn mode(&self) -> ClockMode {
+        self.inner.mode
+    }
+
+    // Simulated time elapsed since the clock was created, plus any time it was resumed at
+    pub fn now(&self) -> Duration {
+        let state = self.inner.state.lock();
+        self.current(&state)
+    }
+
+    // Carry on from a time reached by an earlier run, e.g. when restoring a snapshot
+    pub fn resume_at(&self, now: Duration) {
+        let mut state = self.inner.state.lock();
+        match self.inner.mode {
+            ClockMode::RealTime => state.offset = now.saturating_sub(self.inner.origin.elapsed()),
+            ClockMode::Virtual => state.now = now,
+        }
+        self.wake_all(&mut state);
+    }
+
+    // Fails once the run has deadlocked, so the caller can end it
+    pub fn check(&self) -> Result<()> {
+        if self.inner.state.lock().deadlocked {
+            bail!("Simulation deadlock: every thread is waiting without a deadline");
+        }
+        Ok(())
+    }
+
+    pub fn sleep(&self, duration: Duration) {
+        self.wait_until(Some(duration), || false);
+    }
+
+    // Block until `ready` returns true or `timeout` elapses; returns whether `ready` succeeded.
+    // `ready` runs with the clock locked, so it must not call back into the clock.
+    // After a deadlock this returns false straight away, whether or not a timeout was given.
+    pub fn wait_until(&self, timeout: Option<Duration>, mut ready: impl FnMut() -> bool) -> bool {
+        let mut state = self.inner.state.lock();
+        let deadline = timeout.map(|t| self.current(&state) + t);
+
+        loop {
+            if ready() {
+                return true;
+            }
+            if state.deadlocked || deadline.is_some_and(|d| self.current(&state) >= d) {
+                return false;
+            }
+
+            match self.inner.mode {
+                ClockMode::RealTime => match deadline {
+                    Some(d) => {
+                        let remaining = d.saturating_sub(self.current(&state));
+                        self.inner.condvar.wait_for(&mut state, remaining);
+                    }
+                    None => self.inner.condvar.wait(&mut state),
+                },
+                ClockMode::Virtual => self.wait_virtual(&mut state, deadline),
+            }
+        }
+    }
+
+    // Wake every waiter so it re-checks its condition
+    pub fn notify(&self) {
+        let mut state = self.inner.state.lock();
+        self.wake_all(&mut state);
+    }
+
+    // Start a thread that takes part in the simulation; virtual time waits for it too
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.inner.state.lock().participants += 1;
+        let participant = Participant(self.clone());
+        thread::spawn(move || {
+            let _participant = participant; // Leaves the clock when the thread ends, even on panic
+            f()
+        })
+    }
+
+    fn current(&self, state: &State) -> Duration {
+        match self.inner.mode {
+            ClockMode::RealTime => self.inner.origin.elapsed() + state.offset,
+            ClockMode::Virtual => state.now,
+        }
+    }
+
+    fn wait_virtual(&self, state: &mut MutexGuard<State>, deadline: Option<Duration>) {
+        let epoch = state.epoch;
+        state.waiting += 1;
+        if let Some(d) = deadline {
+            state.deadlines.push(d);
+        }
+        self.advance_if_idle(state);
+
+        if state.epoch == epoch {
+            self.inner.condvar.wait(state);
+        }
+
+        // A wake-up that did not come from `notify` or a time jump leaves us registered
+        if state.epoch == epoch {
+            state.waiting -= 1;
+            if let Some(d) = deadline {
+                if let Some(i) = state.deadlines.iter().position(|x| *x == d) {
+                    state.deadlines.swap_remove(i);
+                }
+            }
+        }
+    }
+
+    // Jump to the earliest deadline once every participant is waiting, or give up if there is none
+    fn advance_if_idle(&self, state: &mut MutexGuard<State>) {
+        if state.waiting == 0 || state.waiting < state.participants {
+            return;
+        }
+        match state.deadlines.iter().min().copied() {
+            Some(next) => state.now = state.now.max(next),
+            None => state.deadlocked = true,
+        }
+        self.wake_all(state);
+    }
+
+    fn wake_all(&self, state: &mut MutexGuard<State>) {
+        state.epoch += 1;
+        state.waiting = 0;
+        state.deadlines.clear();
+        self.inner.condvar.notify_all();
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::new(ClockMode::default())
+    }
+}
+
+struct Participant(Clock);
+
+impl Drop for Participant {
+    fn drop(&mut self) {
+        let mut state = self.0.inner.state.lock();
+        state.participants -= 1;
+        self.0.wake_all(&mut state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn sleepers_wake_in_order_of_their_deadlines() {
+        let clock = Clock::new(ClockMode::Virtual);
+        let woken = Arc::new(Mutex::new(Vec::new()));
+        let sleepers: Vec<_> = [30, 10, 20]
+            .into_iter()
+            .map(|secs| {
+                let (clock, woken) = (clock.clone(), woken.clone());
+                clock.clone().spawn(move || {
+                    clock.sleep(Duration::from_secs(secs));
+                    woken.lock().push((secs, clock.now().as_secs()));
+                })
+            })
+            .collect();
+
+        clock.sleep(Duration::from_secs(25));
+        assert_eq!(clock.now(), Duration::from_secs(25));
+        assert_eq!(*woken.lock(), [(10, 10), (20, 20)]);
+        // Joining does not wait in the clock, so the last sleeper must be waited out first
+        clock.sleep(Duration::from_secs(10));
+        for sleeper in sleepers {
+            sleeper.join().unwrap();
+        }
+        assert_eq!(*woken.lock(), [(10, 10), (20, 20), (30, 30)]);
+        clock.check().unwrap();
+    }
+
+    #[test]
+    fn time_waits_for_every_participant() {
+        let clock = Clock::new(ClockMode::Virtual);
+        let worked = Arc::new(AtomicBool::new(false));
+        let worker = clock.spawn({
+            let worked = worked.clone();
+            move || {
+                // Busy outside the clock, so virtual time must not move on without it
+                thread::sleep(Duration::from_millis(50));
+                worked.store(true, Ordering::SeqCst);
+            }
+        });
+
+        clock.sleep(Duration::from_secs(1));
+        assert!(worked.load(Ordering::SeqCst));
+        worker.join().unwrap();
+
+        // With the worker gone, the driving thread is the only participant left
+        clock.sleep(Duration::from_secs(1));
+        assert_eq!(clock.now(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn waiting_with_no_deadline_anywhere_is_a_deadlock() {
+        let clock = Clock::new(ClockMode::Virtual);
+        let waiter = clock.spawn({
+            let clock = clock.clone();
+            move || clock.wait_until(None, || false)
+        });
+
+        assert!(!clock.wait_until(None, || false));
+        assert!(!waiter.join().unwrap());
+        assert!(clock.check().is_err());
+
+        // Later waits fail at once instead of hanging
+        clock.sleep(Duration::from_secs(1));
+        assert_eq!(clock.now(), Duration::ZERO);
+        assert!(clock.wait_until(None, || true));
+    }
+}
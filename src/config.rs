@@ -0,0 +1,227 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cancel::Cancel;
+use crate::clock::ClockMode;
+use crate::events::EventLog;
+use crate::faults::{self, FaultConfig};
+use crate::remote::Remote;
+use crate::replay::Tape;
+use crate::sim_cafe::CafeConfig;
+use crate::sim_factory::FactoryConfig;
+use crate::sim_home::HomeConfig;
+use crate::sim_nuclear::NuclearConfig;
+use crate::sim_town::{Links, TownConfig};
+use crate::sim_weather::WeatherConfig;
+use crate::snapshot::Checkpoint;
+
+// Settings for every simulation; each one reads only its own section
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub seed: Option<u64>, // Seed for all randomness; None picks a fresh one per run
+    pub clock: ClockMode,  // Real-time pacing or as-fast-as-possible virtual time
+    #[serde(skip)]
+    pub events: EventLog, // Where structured events are sent
+    #[serde(skip)]
+    pub tape: Tape, // Records or replays every random draw and external input
+    #[serde(skip)]
+    pub remote: Remote, // Requests from the remote control API to the running simulation
+    #[serde(skip)]
+    pub cancel: Cancel, // Set on Ctrl-C to stop the running simulation at its next step
+    #[serde(skip)]
+    pub checkpoint: Checkpoint, // Whether to save a snapshot of the run before it stops
+    #[serde(skip)]
+    pub telemetry: Option<PathBuf>, // CSV file to write a row of readings to after every tick
+    #[serde(skip)]
+    pub chart: Option<PathBuf>, // SVG report written by a chart event sink when the run ends
+    #[serde(skip)]
+    pub links: Links, // Ties the simulation to the rest of the town in town mode
+    pub cafe: CafeConfig,
+    pub factory: FactoryConfig,
+    pub home: HomeConfig,
+    pub nuclear: NuclearConfig,
+    pub weather: WeatherConfig,
+    pub town: TownConfig,
+    pub faults: BTreeMap<String, FaultConfig>, // Per-fault overrides, e.g. [faults.coolant_failure]
+}
+
+// Directory searched for scenarios given by name
+const SCENARIO_DIR: &str = "scenarios";
+
+impl Config {
+    // Load a scenario file (TOML or JSON, by extension); anything it leaves out keeps its default
+    pub fn load(path: &Path) -> Result<Config> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read scenario {}", path.display()))?;
+        let mut config: Config = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&text)
+                .with_context(|| format!("Invalid scenario {}", path.display()))?,
+            Some("json") => serde_json::from_str(&text)
+                .with_context(|| format!("Invalid scenario {}", path.display()))?,
+            _ => bail!(
+                "Scenario {} must have a .toml or .json extension",
+                path.display()
+            ),
+        };
+        if let Some(dir) = path.parent() {
+            config.cafe.arrivals.relative_to(dir);
+        }
+        config
+            .validate()
+            .with_context(|| format!("Invalid scenario {}", path.display()))?;
+        Ok(config)
+    }
+
+    // Load a scenario given either as a file path or by name from the scenarios directory
+    pub fn load_scenario(scenario: &str) -> Result<Config> {
+        let path = Path::new(scenario);
+        if path.is_file() {
+            return Config::load(path);
+        }
+
+        let dir = Path::new(SCENARIO_DIR);
+        for extension in ["toml", "json"] {
+            let path = dir.join(format!("{}.{}", scenario, extension));
+            if path.is_file() {
+                return Config::load(&path);
+            }
+        }
+
+        let available = scenario_names(dir);
+        if available.is_empty() {
+            bail!("No scenario file or named scenario '{}'", scenario);
+        }
+        bail!(
+            "No scenario file or named scenario '{}'; available scenarios: {}",
+            scenario,
+            available.join(", ")
+        )
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.cafe.validate()?;
+        self.factory.validate()?;
+        self.home.validate()?;
+        self.nuclear.validate()?;
+        self.weather.validate()?;
+        self.town.validate()?;
+        faults::validate(&self.faults)
+    }
+}
+
+// Probabilities must lie between 0 and 1
+pub fn check_chance(name: &str, chance: f64) -> Result<()> {
+    if !(0.0..=1.0).contains(&chance) {
+        bail!("{} must be between 0 and 1", name);
+    }
+    Ok(())
+}
+
+fn scenario_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("toml" | "json")
+            )
+        })
+        .filter_map(|path| path.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The message of the error `validate` gives after `change` is made to the defaults
+    fn rejection(change: impl FnOnce(&mut Config)) -> String {
+        let mut config = Config::default();
+        change(&mut config);
+        format!(
+            "{:#}",
+            config.validate().expect_err("config should be rejected")
+        )
+    }
+
+    #[test]
+    fn the_defaults_are_valid() {
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn each_simulation_section_is_checked() {
+        assert!(rejection(|config| config.cafe.baristas = 0).contains("cafe.baristas"));
+        assert!(rejection(|config| config.nuclear.scram_power_w = 301)
+            .contains("nuclear.scram_power_w must be between 1 and 300"));
+        assert!(
+            rejection(|config| config.nuclear.scram_power_w = 0).contains("nuclear.scram_power_w")
+        );
+        assert!(rejection(|config| config.town.simulations.clear()).contains("town.simulations"));
+    }
+
+    #[test]
+    fn chances_must_be_between_0_and_1() {
+        assert!(rejection(|config| config.cafe.extra_item_chance = 1.5)
+            .contains("cafe.extra_item_chance must be between 0 and 1"));
+        assert!(rejection(|config| config.nuclear.power_surge_chance = -0.1)
+            .contains("nuclear.power_surge_chance"));
+        assert!(
+            rejection(|config| config.cafe.machine_breakdown_chance = f64::NAN)
+                .contains("cafe.machine_breakdown_chance")
+        );
+    }
+
+    #[test]
+    fn faults_must_be_known_and_have_a_valid_chance() {
+        let message = rejection(|config| {
+            config
+                .faults
+                .insert("meteor_strike".to_string(), FaultConfig::default());
+        });
+        assert!(message.contains("Unknown fault 'meteor_strike'"));
+
+        let message = rejection(|config| {
+            let fault = FaultConfig {
+                chance: Some(2.0),
+                ..FaultConfig::default()
+            };
+            config.faults.insert("power_surge".to_string(), fault);
+        });
+        assert!(message.contains("faults.power_surge.chance"));
+    }
+
+    #[test]
+    fn repairs_must_take_a_finite_time() {
+        assert!(
+            rejection(|config| config.cafe.machine_repair_secs = f64::INFINITY)
+                .contains("cafe.machine_repair_secs")
+        );
+    }
+
+    #[test]
+    fn cafe_patience_is_checked() {
+        assert!(rejection(|config| config.cafe.min_patience_secs = 0.0)
+            .contains("cafe.min_patience_secs"));
+        assert!(rejection(|config| {
+            config.cafe.min_patience_secs = 50.0;
+            config.cafe.max_patience_secs = 40.0;
+        })
+        .contains("cafe.min_patience_secs"));
+
+        // A max patience of 0 means customers wait however long it takes
+        let mut config = Config::default();
+        config.cafe.min_patience_secs = 0.0;
+        config.cafe.max_patience_secs = 0.0;
+        config.validate().unwrap();
+    }
+}
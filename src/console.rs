@@ -0,0 +1,21 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set while simulations should run without narrating on the console, e.g. during a batch
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+// Console narration from a simulation; behaves like `println!` unless the console is quiet
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !$crate::console::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
@@ -0,0 +1,409 @@
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::widgets::{Block, Gauge, List, ListItem, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::thread::{self, ScopedJoinHandle};
+use std::time::Duration;
+
+use rust_simulations::cancel::Cancel;
+use rust_simulations::console;
+use rust_simulations::events::{Event, EventLog, EventSink, Record};
+use rust_simulations::simulation::Outcome;
+
+// Readings kept for each sparkline
+const HISTORY: usize = 200;
+// Lines kept in the event pane
+const LOG_LINES: usize = 500;
+// Time between two redraws
+const REFRESH: Duration = Duration::from_millis(100);
+
+// A numeric reading shown as a gauge with its recent history
+struct Meter {
+    label: &'static str,
+    unit: &'static str,
+    value: i64,
+    max: i64, // Full scale of the gauge; grows when a reading exceeds it
+    history: VecDeque<u64>,
+}
+
+impl Meter {
+    fn new(label: &'static str, unit: &'static str, max: i64) -> Self {
+        Meter {
+            label,
+            unit,
+            value: 0,
+            max,
+            history: VecDeque::with_capacity(HISTORY),
+        }
+    }
+
+    fn set(&mut self, value: i64) {
+        self.value = value;
+        self.max = self.max.max(value);
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(value.max(0) as u64);
+    }
+}
+
+// Everything the dashboard shows, rebuilt from the simulation's events
+#[derive(Default)]
+struct State {
+    simulation: String,
+    sim_time_secs: f64,
+    meters: Vec<Meter>,
+    status: BTreeMap<String, String>,
+    log: VecDeque<String>,
+    outcome: Option<Outcome>,
+    queued_orders: i64,
+    served_orders: u64,
+    lost_customers: u64,
+}
+
+impl State {
+    // Meter with this label, created with the given full scale on first use
+    fn meter(&mut self, label: &'static str, unit: &'static str, max: i64) -> &mut Meter {
+        let index = match self.meters.iter().position(|m| m.label == label) {
+            Some(index) => index,
+            None => {
+                self.meters.push(Meter::new(label, unit, max));
+                self.meters.len() - 1
+            }
+        };
+        &mut self.meters[index]
+    }
+
+    fn set_status(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.status.insert(key.into(), value.into());
+    }
+
+    fn apply(&mut self, record: &Record) {
+        self.simulation.clone_from(&record.simulation);
+        if let Some(secs) = record.sim_time_secs {
+            self.sim_time_secs = secs;
+        }
+
+        match &record.event {
+            Event::SimulationFinished { outcome } => self.outcome = Some(outcome.clone()),
+
+            // Cafe
+            Event::OrderPlaced { .. } => {
+                self.queued_orders += 1;
+                let queued = self.queued_orders;
+                self.meter("Order queue", "orders", 10).set(queued);
+            }
+            Event::BrewStarted { barista_id, ticket } => {
+                self.queued_orders -= 1;
+                let queued = self.queued_orders;
+                self.meter("Order queue", "orders", 10).set(queued);
+                self.set_status(
+                    format!("Barista {}", barista_id),
+                    format!("Brewing ticket {}", ticket),
+                );
+            }
+            Event::ItemPrepared {
+                barista_id,
+                ticket,
+                item,
+            } => self.set_status(
+                format!("Barista {}", barista_id),
+                format!("Made {} for ticket {}", item, ticket),
+            ),
+            Event::OrderReady { barista_id, .. } => {
+                self.set_status(format!("Barista {}", barista_id), "Idle")
+            }
+            Event::OrderServed { .. } => {
+                self.served_orders += 1;
+                self.set_status("Orders served", self.served_orders.to_string());
+            }
+            Event::CustomerBalked { .. } => {
+                self.lost_customers += 1;
+                self.set_status("Customers lost", self.lost_customers.to_string());
+            }
+            Event::OrderCancelled { was, .. } => {
+                if was == "queued" {
+                    self.queued_orders -= 1;
+                    let queued = self.queued_orders;
+                    self.meter("Order queue", "orders", 10).set(queued);
+                }
+                self.lost_customers += 1;
+                self.set_status("Customers lost", self.lost_customers.to_string());
+            }
+            Event::OrderDropped { barista_id, .. } => {
+                self.set_status(format!("Barista {}", barista_id), "Idle")
+            }
+            Event::CafeClosing => self.set_status("Cafe", "Closing"),
+            Event::MachineBreakdown { .. } => self.set_status("Machines", "Breakdown under repair"),
+            Event::MachineRepaired => self.set_status("Machines", "Repaired"),
+
+            // Factory
+            Event::InventoryChanged { grams } => {
+                self.meter("Inventory", "g", 300).set(*grams as i64);
+            }
+            Event::TaskRequested { task } => {
+                self.set_status("Stage", format!("{} requested", task))
+            }
+            Event::TaskAuthorized { task } => self.set_status("Stage", task.clone()),
+            Event::TaskCompleted { task } => self.set_status("Stage", format!("{} done", task)),
+            Event::RestockRequested => self.set_status("Stage", "Waiting for restock"),
+            Event::RestockCompleted => self.set_status("Stage", "Restocked"),
+            Event::ShipmentRequested { grams } => {
+                self.set_status("Shipment", format!("{} g requested", grams))
+            }
+            Event::ShipmentCompleted { grams } => {
+                self.set_status("Shipment", format!("{} g shipped", grams))
+            }
+            Event::EquipmentFailure { .. } => self.set_status("Stage", "Equipment failure"),
+
+            // Home
+            Event::HomeTaskExecuted { device, state, .. } => {
+                self.set_status(device.clone(), state.clone())
+            }
+            Event::Intrusion => self.set_status("Security", "Intruder detected"),
+            Event::DeadlyWeatherWarning => self.set_status("Power", "Emergency only"),
+
+            // Nuclear
+            Event::ReactorReading {
+                temperature_c,
+                power_w,
+                radiation_bq,
+            } => {
+                self.meter("Core temperature", "°C", 300)
+                    .set(*temperature_c as i64);
+                self.meter("Power output", "W", 300).set(*power_w as i64);
+                self.meter("Radiation", "Bq", 5000)
+                    .set(*radiation_bq as i64);
+            }
+            Event::Scram { .. } => self.set_status("Reactor", "SCRAM"),
+            Event::Evacuation => self.set_status("Site", "Evacuated"),
+
+            // Weather
+            Event::WeatherReading {
+                temperature_c,
+                wind_speed_kmh,
+                structural_health_pct,
+            } => {
+                self.meter("Temperature", "°C", 50)
+                    .set(*temperature_c as i64);
+                self.meter("Wind speed", "km/h", 300)
+                    .set(*wind_speed_kmh as i64);
+                self.meter("Structural health", "%", 100)
+                    .set(*structural_health_pct as i64);
+            }
+            Event::CatastrophicEvent { kind } => self.set_status("Catastrophe", kind.clone()),
+            Event::StructuralFailure => self.set_status("Structure", "Failed"),
+            _ => {}
+        }
+
+        // Readings already show up in the gauges
+        if !matches!(
+            record.event,
+            Event::ReactorReading { .. } | Event::WeatherReading { .. }
+        ) {
+            if self.log.len() == LOG_LINES {
+                self.log.pop_front();
+            }
+            self.log.push_back(format!(
+                "{:>9.1}s  {}",
+                self.sim_time_secs,
+                describe(&record.event)
+            ));
+        }
+    }
+}
+
+// One-line description of an event, e.g. "power_surge surge_w=30"
+fn describe(event: &Event) -> String {
+    if let Event::SimulationFinished { outcome } = event {
+        return format!("simulation_finished outcome={}", outcome);
+    }
+    let Ok(serde_json::Value::Object(mut fields)) = serde_json::to_value(event) else {
+        return format!("{:?}", event);
+    };
+    let mut line = match fields.remove("event") {
+        Some(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    };
+    for (key, value) in &fields {
+        match value {
+            serde_json::Value::String(text) => line.push_str(&format!(" {}={}", key, text)),
+            _ => line.push_str(&format!(" {}={}", key, value)),
+        }
+    }
+    line
+}
+
+struct DashboardSink {
+    state: Arc<Mutex<State>>,
+}
+
+impl EventSink for DashboardSink {
+    fn record(&self, record: &Record) {
+        self.state.lock().apply(record);
+    }
+}
+
+// Run `simulate` on a background thread while its events are drawn full-screen.
+// Quitting with q leaves the simulation running to completion; Ctrl-C stops it.
+pub fn run<T: Send>(
+    events: &EventLog,
+    cancel: &Cancel,
+    simulate: impl FnOnce() -> T + Send,
+) -> Result<T> {
+    let state = Arc::new(Mutex::new(State::default()));
+    events.add_sink(Arc::new(DashboardSink {
+        state: Arc::clone(&state),
+    }));
+
+    // Narration would scribble over the screen
+    console::set_quiet(true);
+    let mut terminal = ratatui::try_init()?;
+
+    let result = thread::scope(|scope| {
+        let simulation = scope.spawn(simulate);
+        let shown = show(&mut terminal, &state, &simulation, cancel);
+        ratatui::restore();
+        if !simulation.is_finished() {
+            if cancel.is_cancelled() {
+                println!("Dashboard closed; stopping the simulation...");
+            } else {
+                println!("Dashboard closed; waiting for the simulation to finish...");
+            }
+        }
+        let result = simulation
+            .join()
+            .map_err(|_| anyhow!("simulation panicked"));
+        shown.and(result)
+    });
+
+    console::set_quiet(false);
+    result
+}
+
+// Redraw until the user quits
+fn show<T>(
+    terminal: &mut DefaultTerminal,
+    state: &Mutex<State>,
+    simulation: &ScopedJoinHandle<'_, T>,
+    cancel: &Cancel,
+) -> Result<()> {
+    loop {
+        let finished = simulation.is_finished();
+        terminal.draw(|frame| draw(frame, &state.lock(), finished))?;
+
+        if event::poll(REFRESH)? {
+            if let TermEvent::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                // The terminal is in raw mode, so Ctrl-C arrives as a key rather than a signal
+                if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    cancel.cancel();
+                    return Ok(());
+                }
+                if matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, state: &State, finished: bool) {
+    let [header, body, log] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(8),
+        Constraint::Percentage(35),
+    ])
+    .areas(frame.area());
+
+    let status = match (&state.outcome, finished) {
+        (Some(outcome), _) => format!("Finished: {}", outcome),
+        (None, true) => "Finished".to_string(),
+        (None, false) => "Running".to_string(),
+    };
+    let title = format!(
+        " {} | t = {:.1}s | {} | q to quit, Ctrl-C to stop",
+        state.simulation, state.sim_time_secs, status
+    );
+    frame.render_widget(
+        Paragraph::new(title).block(Block::bordered().title(" Simulation dashboard ")),
+        header,
+    );
+
+    if state.meters.is_empty() {
+        draw_status(frame, state, body);
+    } else {
+        let [meters, status] =
+            Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)])
+                .areas(body);
+        draw_meters(frame, state, meters);
+        draw_status(frame, state, status);
+    }
+
+    let height = log.height.saturating_sub(2) as usize;
+    let lines: Vec<ListItem> = state
+        .log
+        .iter()
+        .skip(state.log.len().saturating_sub(height))
+        .map(|line| ListItem::new(line.as_str()))
+        .collect();
+    frame.render_widget(
+        List::new(lines).block(Block::bordered().title(" Events ")),
+        log,
+    );
+}
+
+fn draw_meters(frame: &mut Frame, state: &State, area: Rect) {
+    let rows = Layout::vertical(vec![Constraint::Fill(1); state.meters.len()]).split(area);
+    for (meter, row) in state.meters.iter().zip(rows.iter()) {
+        let block =
+            Block::bordered().title(format!(" {}: {} {} ", meter.label, meter.value, meter.unit));
+        let inner = block.inner(*row);
+        frame.render_widget(block, *row);
+
+        let [gauge, sparkline] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(inner);
+        let ratio = (meter.value as f64 / meter.max.max(1) as f64).clamp(0.0, 1.0);
+        frame.render_widget(
+            Gauge::default()
+                .gauge_style(Style::default().fg(Color::Green))
+                .ratio(ratio)
+                .label(format!("{} / {} {}", meter.value, meter.max, meter.unit)),
+            gauge,
+        );
+
+        // Only the most recent readings that fit the width
+        let history: Vec<u64> = meter
+            .history
+            .iter()
+            .skip(meter.history.len().saturating_sub(sparkline.width as usize))
+            .copied()
+            .collect();
+        frame.render_widget(
+            Sparkline::default()
+                .data(&history)
+                .max(meter.max.max(1) as u64)
+                .style(Style::default().fg(Color::Cyan)),
+            sparkline,
+        );
+    }
+}
+
+fn draw_status(frame: &mut Frame, state: &State, area: Rect) {
+    let lines: Vec<ListItem> = state
+        .status
+        .iter()
+        .map(|(key, value)| ListItem::new(format!("{}: {}", key, value)))
+        .collect();
+    frame.render_widget(
+        List::new(lines).block(Block::bordered().title(" Status ")),
+        area,
+    );
+}
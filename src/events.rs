@@ -0,0 +1,317 @@
+use anyhow::{Context, Result};
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::clock::Clock;
+use crate::simulation::Outcome;
+
+// Significant things that happen during a run
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    // Lifecycle
+    SimulationStarted,
+    SimulationFinished {
+        outcome: Outcome,
+    },
+
+    // Cafe
+    OrderPlaced {
+        customer_id: usize,
+        ticket: usize,
+        details: String,
+        items: Vec<String>,
+    },
+    BrewStarted {
+        barista_id: usize,
+        ticket: usize,
+    },
+    ItemPrepared {
+        barista_id: usize,
+        ticket: usize,
+        item: String,
+    },
+    OrderReady {
+        barista_id: usize,
+        ticket: usize,
+    },
+    OrderServed {
+        barista_id: usize,
+        ticket: usize,
+        details: String,
+    },
+    CustomerBalked {
+        customer_id: usize,
+        queue_length: usize,
+    },
+    OrderCancelled {
+        customer_id: usize,
+        ticket: usize,
+        was: String, // Where the order had got to: queued, brewing or on_counter
+    },
+    OrderDropped {
+        barista_id: usize,
+        ticket: usize,
+    },
+    CafeClosing,
+    MachineBreakdown {
+        repair_secs: f64,
+    },
+    MachineRepaired,
+
+    // Factory
+    TaskRequested {
+        task: String,
+    },
+    TaskAuthorized {
+        task: String,
+    },
+    TaskCompleted {
+        task: String,
+    },
+    InventoryChanged {
+        grams: i32,
+    },
+    RestockRequested,
+    RestockCompleted,
+    ShipmentRequested {
+        grams: i32,
+    },
+    ShipmentCompleted {
+        grams: i32,
+    },
+    EquipmentFailure {
+        repair_secs: f64,
+    },
+
+    // Home
+    HomeTaskExecuted {
+        task: String,
+        device: String,
+        state: String,
+    },
+    Intrusion,
+    DeadlyWeatherWarning,
+
+    // Nuclear
+    ReactorReading {
+        temperature_c: i32,
+        power_w: i32,
+        radiation_bq: i32,
+    },
+    CoolantFailure {
+        spike_c: i32,
+    },
+    PowerSurge {
+        surge_w: i32,
+    },
+    RadiationLeak {
+        level_bq: i32,
+    },
+    EmergencyPowerReduction {
+        amount_w: i32,
+    },
+    EmergencyCooling {
+        amount_c: i32,
+    },
+    Scram {
+        power_w: i32,
+    },
+    Evacuation,
+
+    // Weather
+    WeatherReading {
+        temperature_c: i32,
+        wind_speed_kmh: i32,
+        structural_health_pct: i32,
+    },
+    WindSpike {
+        damage_pct: i32,
+    },
+    CatastrophicEvent {
+        kind: String,
+    },
+    Repair {
+        amount_pct: i32,
+    },
+    StructuralFailure,
+}
+
+// An event stamped with when and where it happened
+#[derive(Clone, Debug, Serialize)]
+pub struct Record {
+    pub timestamp: String,          // Wall-clock time, RFC 3339
+    pub sim_time_secs: Option<f64>, // Simulation clock time, when known
+    pub simulation: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+// Destination for recorded events
+pub trait EventSink: Send + Sync {
+    fn record(&self, record: &Record);
+}
+
+// Writes each event as one JSON object per line
+pub struct JsonLinesSink {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl JsonLinesSink {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create event log {}", path.display()))?;
+        Ok(JsonLinesSink {
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+}
+
+impl EventSink for JsonLinesSink {
+    fn record(&self, record: &Record) {
+        let mut writer = self.writer.lock();
+        // A broken log must not take the simulation down with it
+        let written = serde_json::to_writer(&mut *writer, record)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(writer))
+            .and_then(|_| writer.flush());
+        if let Err(e) = written {
+            eprintln!("Failed to write event log: {}", e);
+        }
+    }
+}
+
+// Handle used by simulations to emit events; clones share the same sinks
+#[derive(Clone, Default)]
+pub struct EventLog {
+    sinks: Arc<RwLock<Vec<Arc<dyn EventSink>>>>,
+    simulation: String,
+    clock: Option<Clock>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        EventLog::default()
+    }
+
+    pub fn add_sink(&self, sink: Arc<dyn EventSink>) {
+        self.sinks.write().push(sink);
+    }
+
+    // Separate log sending to the same sinks plus one more, leaving this log as it is
+    pub fn with_sink(&self, sink: Arc<dyn EventSink>) -> EventLog {
+        let mut sinks = self.sinks.read().clone();
+        sinks.push(sink);
+        EventLog {
+            sinks: Arc::new(RwLock::new(sinks)),
+            simulation: self.simulation.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+
+    // Handle that stamps events with a simulation name and its clock
+    pub fn scoped(&self, simulation: &str, clock: Option<Clock>) -> EventLog {
+        EventLog {
+            sinks: Arc::clone(&self.sinks),
+            simulation: simulation.to_string(),
+            clock,
+        }
+    }
+
+    pub fn emit(&self, event: Event) {
+        let sinks = self.sinks.read();
+        if sinks.is_empty() {
+            return;
+        }
+
+        let record = Record {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            sim_time_secs: self.clock.as_ref().map(|c| c.now().as_secs_f64()),
+            simulation: self.simulation.clone(),
+            event,
+        };
+        for sink in sinks.iter() {
+            sink.record(&record);
+        }
+    }
+}
+
+impl fmt::Debug for EventLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventLog")
+            .field("sinks", &self.sinks.read().len())
+            .field("simulation", &self.simulation)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ClockMode;
+    use serde_json::{json, Value};
+    use std::fs;
+
+    #[test]
+    fn events_are_written_one_json_object_per_line() {
+        let path = std::env::temp_dir().join(format!("{}-events.jsonl", std::process::id()));
+        let log = EventLog::new();
+        log.add_sink(Arc::new(JsonLinesSink::create(&path).unwrap()));
+        let events = log.scoped("cafe", Some(Clock::new(ClockMode::Virtual)));
+        events.emit(Event::SimulationStarted);
+        events.emit(Event::BrewStarted {
+            barista_id: 2,
+            ticket: 5,
+        });
+        log.emit(Event::SimulationFinished {
+            outcome: Outcome::Scram,
+        });
+
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let records: Vec<Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 3);
+        for record in &records {
+            let timestamp = record["timestamp"].as_str().unwrap();
+            assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+        }
+
+        let fields = |record: &Value| {
+            let mut record = record.clone();
+            record.as_object_mut().unwrap().remove("timestamp");
+            record
+        };
+        assert_eq!(
+            fields(&records[0]),
+            json!({"sim_time_secs": 0.0, "simulation": "cafe", "event": "simulation_started"})
+        );
+        assert_eq!(
+            fields(&records[1]),
+            json!({
+                "sim_time_secs": 0.0,
+                "simulation": "cafe",
+                "event": "brew_started",
+                "barista_id": 2,
+                "ticket": 5,
+            })
+        );
+        // Events from outside any simulation have no simulation time
+        assert_eq!(
+            fields(&records[2]),
+            json!({
+                "sim_time_secs": null,
+                "simulation": "",
+                "event": "simulation_finished",
+                "outcome": "scram",
+            })
+        );
+    }
+}
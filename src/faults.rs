@@ -0,0 +1,194 @@
+use anyhow::{bail, Context, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::config::check_chance;
+
+// Every fault that can be scheduled, tuned, disabled or injected, with the simulation it belongs to
+pub const FAULTS: &[(&str, &str)] = &[
+    ("cafe", "machine_breakdown"),
+    ("factory", "equipment_failure"),
+    ("home", "intrusion"),
+    ("home", "weather_warning"),
+    ("nuclear", "coolant_failure"),
+    ("nuclear", "power_surge"),
+    ("nuclear", "radiation_leak"),
+    ("weather", "wind_spike"),
+    ("weather", "catastrophe"),
+];
+
+// Faults of one simulation, e.g. for listing what it can inject
+pub fn names(simulation: &str) -> Vec<&'static str> {
+    FAULTS
+        .iter()
+        .filter(|(owner, _)| *owner == simulation)
+        .map(|(_, fault)| *fault)
+        .collect()
+}
+
+// How one named fault behaves during a run
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultConfig {
+    pub chance: Option<f64>, // Chance per tick, replacing the simulation's own setting
+    pub disabled: bool,      // Never strikes by chance; scheduled and injected faults still do
+    pub at_ticks: Vec<u64>, // Ticks, counted from 1, at which the fault strikes regardless of chance
+}
+
+pub fn validate(faults: &BTreeMap<String, FaultConfig>) -> Result<()> {
+    for (name, fault) in faults {
+        if !FAULTS.iter().any(|(_, known)| known == name) {
+            let known: Vec<&str> = FAULTS.iter().map(|(_, fault)| *fault).collect();
+            bail!(
+                "Unknown fault '{}'; known faults: {}",
+                name,
+                known.join(", ")
+            );
+        }
+        if let Some(chance) = fault.chance {
+            check_chance(&format!("faults.{}.chance", name), chance)?;
+        }
+        if fault.at_ticks.contains(&0) {
+            bail!("faults.{}.at_ticks must count from 1", name);
+        }
+    }
+    Ok(())
+}
+
+// Apply a command-line fault setting: NAME@TICK schedules it, NAME=CHANCE tunes it, NAME=off disables it
+pub fn apply(faults: &mut BTreeMap<String, FaultConfig>, spec: &str) -> Result<()> {
+    if let Some((name, tick)) = spec.split_once('@') {
+        let tick = tick
+            .parse()
+            .with_context(|| format!("Invalid tick in fault '{}'", spec))?;
+        if tick == 0 {
+            bail!("Invalid tick in fault '{}': ticks count from 1", spec);
+        }
+        faults
+            .entry(name.to_string())
+            .or_default()
+            .at_ticks
+            .push(tick);
+    } else if let Some((name, setting)) = spec.split_once('=') {
+        let fault = faults.entry(name.to_string()).or_default();
+        match setting {
+            "off" => fault.disabled = true,
+            "on" => fault.disabled = false,
+            chance => {
+                fault.chance = Some(
+                    chance
+                        .parse()
+                        .with_context(|| format!("Invalid chance in fault '{}'", spec))?,
+                )
+            }
+        }
+    } else {
+        bail!(
+            "Fault '{}' must be NAME@TICK, NAME=CHANCE or NAME=off",
+            spec
+        );
+    }
+    validate(faults)
+}
+
+// Decides when the faults of a run strike
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    config: BTreeMap<String, FaultConfig>,
+}
+
+impl Faults {
+    pub fn new(config: &BTreeMap<String, FaultConfig>) -> Self {
+        Faults {
+            config: config.clone(),
+        }
+    }
+
+    // Whether the fault was scheduled for this tick
+    pub fn scheduled(&self, fault: &str, tick: u64) -> bool {
+        self.config
+            .get(fault)
+            .is_some_and(|f| f.at_ticks.contains(&tick))
+    }
+
+    // Roll for the fault with its configured chance, or `chance` if it has none.
+    // The roll happens even when the fault is disabled, so every other random draw stays the same.
+    pub fn by_chance(&self, fault: &str, chance: f64, rng: &mut impl Rng) -> bool {
+        let config = self.config.get(fault);
+        let chance = config.and_then(|f| f.chance).unwrap_or(chance);
+        let struck = rng.gen_bool(chance);
+        struck && !config.is_some_and(|f| f.disabled)
+    }
+
+    // Whether the fault strikes this tick, either as scheduled or by chance
+    pub fn strikes(&self, fault: &str, tick: u64, chance: f64, rng: &mut impl Rng) -> bool {
+        let by_chance = self.by_chance(fault, chance, rng);
+        self.scheduled(fault, tick) || by_chance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied(specs: &[&str]) -> Result<BTreeMap<String, FaultConfig>> {
+        let mut faults = BTreeMap::new();
+        for spec in specs {
+            apply(&mut faults, spec)?;
+        }
+        Ok(faults)
+    }
+
+    fn rejection(spec: &str) -> String {
+        format!(
+            "{:#}",
+            applied(&[spec]).expect_err("spec should be rejected")
+        )
+    }
+
+    #[test]
+    fn specs_schedule_tune_and_disable_faults() {
+        let faults = applied(&[
+            "power_surge@3",
+            "power_surge@7",
+            "coolant_failure=0.25",
+            "radiation_leak=off",
+        ])
+        .unwrap();
+        assert_eq!(faults["power_surge"].at_ticks, [3, 7]);
+        assert_eq!(faults["coolant_failure"].chance, Some(0.25));
+        assert!(faults["radiation_leak"].disabled);
+
+        let faults = applied(&["radiation_leak=off", "radiation_leak=on"]).unwrap();
+        assert!(!faults["radiation_leak"].disabled);
+    }
+
+    #[test]
+    fn bad_specs_are_rejected() {
+        assert!(rejection("power_surge").contains("must be NAME@TICK, NAME=CHANCE or NAME=off"));
+        assert!(rejection("power_surge@soon").contains("Invalid tick in fault 'power_surge@soon'"));
+        assert!(rejection("power_surge@-1").contains("Invalid tick"));
+        assert!(rejection("power_surge=often").contains("Invalid chance in fault"));
+        assert!(rejection("power_surge=1.5").contains("faults.power_surge.chance"));
+        assert!(rejection("meteor_strike@2").contains("Unknown fault 'meteor_strike'"));
+    }
+
+    #[test]
+    fn ticks_count_from_1() {
+        assert_eq!(
+            rejection("power_surge@0"),
+            "Invalid tick in fault 'power_surge@0': ticks count from 1"
+        );
+
+        let mut faults = BTreeMap::new();
+        faults.insert(
+            "power_surge".to_string(),
+            FaultConfig {
+                at_ticks: vec![0],
+                ..FaultConfig::default()
+            },
+        );
+        assert!(validate(&faults).is_err());
+    }
+}
@@ -0,0 +1,33 @@
+// Coffee-themed concurrency simulations, usable from other crates as well as the menu binary.
+// Each simulation implements `Simulation` and is driven with `simulation::run`.
+
+#[macro_use]
+pub mod console;
+
+pub mod batch;
+pub mod cancel;
+pub mod chart;
+pub mod check;
+pub mod clock;
+pub mod config;
+pub mod events;
+pub mod faults;
+pub mod metrics;
+pub mod remote;
+pub mod replay;
+pub mod rng;
+pub mod simulation;
+pub mod snapshot;
+pub mod stats;
+pub mod telemetry;
+
+// Declare the modules for all simulations.
+pub mod sim_cafe;
+pub mod sim_factory;
+pub mod sim_home;
+pub mod sim_nuclear;
+pub mod sim_town;
+pub mod sim_weather;
+
+pub use config::Config;
+pub use simulation::{Outcome, Report, Simulation, Step};
@@ -1,14 +1,361 @@
+use anyhow::{bail, Context};
+use clap::Parser;
+use serde_json::Value;
 use std::io;
+use std::path::Path;
 
-// Declare the modules for all simulations.
-pub mod sim_cafe;
-pub mod sim_factory;
-pub mod sim_home;
-pub mod sim_nuclear;
-pub mod sim_weather; // Add the Weather Machine simulation module
+mod cli;
+mod dashboard;
 
-#[tokio::main]
-async fn main() {
+use cli::{BatchArgs, CheckArgs, Cli, Command, ResumeArgs};
+use rust_simulations::cancel::Cancel;
+use rust_simulations::check::Script;
+use rust_simulations::clock::ClockMode;
+use rust_simulations::config::Config;
+use rust_simulations::faults;
+use rust_simulations::replay::{Recording, Tape};
+use rust_simulations::snapshot::Snapshot;
+use rust_simulations::{batch, console, remote, simulation, Outcome};
+
+fn main() {
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
+    let mut config = cli.config()?;
+    check_options(&cli)?;
+    // Bound only once the command line has been checked, so a mistake there leaves the port free
+    cli.serve_metrics(&config.events)?;
+    handle_ctrl_c(&config.cancel)?;
+
+    // Without a subcommand, fall back to the interactive menu
+    match &cli.command {
+        Some(Command::Batch(args)) => run_batch(args, config),
+        Some(Command::Replay(args)) => replay_recording(&args.path, config),
+        Some(Command::Resume(args)) => resume_snapshot(&cli, args, config),
+        Some(Command::Serve(args)) => {
+            println!("Remote control listening on http://127.0.0.1:{}", args.port);
+            remote::serve(args.port, config)
+        }
+        Some(Command::Check(args)) => run_checks(args, &config.cancel),
+        Some(Command::Simulation(command)) => {
+            command.apply(&mut config);
+            run_simulation(
+                command.name(),
+                &config,
+                cli.record.as_deref(),
+                cli.dashboard,
+                None,
+            )
+        }
+        None => {
+            run_menu(&config);
+            Ok(())
+        }
+    }
+}
+
+// Reject global options that make no sense with the chosen subcommand
+fn check_options(cli: &Cli) -> anyhow::Result<()> {
+    match &cli.command {
+        Some(Command::Batch(_)) => {
+            if cli.record.is_some() {
+                bail!("--record cannot be used with batch");
+            }
+            if cli.dashboard {
+                bail!("--dashboard cannot be used with batch");
+            }
+            if cli.wants_snapshot() {
+                bail!("--snapshot and --snapshot-at cannot be used with batch");
+            }
+            if cli.wants_run_output() {
+                bail!("--telemetry and --chart cannot be used with batch");
+            }
+        }
+        Some(Command::Replay(_)) => {
+            if cli.record.is_some() {
+                bail!("--record cannot be used while replaying");
+            }
+            if cli.dashboard {
+                bail!("--dashboard cannot be used while replaying");
+            }
+            if cli.wants_snapshot() {
+                bail!("--snapshot and --snapshot-at cannot be used while replaying");
+            }
+        }
+        Some(Command::Resume(_)) => {
+            if cli.record.is_some() {
+                bail!("--record cannot be used when resuming a snapshot");
+            }
+        }
+        Some(Command::Serve(_)) => {
+            if cli.record.is_some()
+                || cli.dashboard
+                || cli.wants_snapshot()
+                || cli.wants_run_output()
+            {
+                bail!(
+                    "--record, --dashboard, --snapshot, --telemetry and --chart cannot be used with serve"
+                );
+            }
+        }
+        Some(Command::Check(_)) => {
+            if cli.record.is_some()
+                || cli.dashboard
+                || cli.wants_snapshot()
+                || cli.wants_run_output()
+            {
+                bail!(
+                    "--record, --dashboard, --snapshot, --telemetry and --chart cannot be used with check"
+                );
+            }
+        }
+        Some(Command::Simulation(command)) => {
+            // Each simulation of a town draws from its own streams, which a recording does not cover
+            if cli.record.is_some() && command.name() == "town" {
+                bail!("--record cannot be used with town");
+            }
+        }
+        None => {
+            if cli.record.is_some()
+                || cli.dashboard
+                || cli.wants_snapshot()
+                || cli.wants_run_output()
+            {
+                bail!(
+                    "--record, --dashboard, --snapshot, --telemetry and --chart need a simulation subcommand"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// The first Ctrl-C stops the running simulation cleanly; a second one, or one with nothing
+// running (e.g. at the menu prompt), exits straight away
+fn handle_ctrl_c(cancel: &Cancel) -> anyhow::Result<()> {
+    let cancel = cancel.clone();
+    ctrlc::set_handler(move || {
+        if cancel.is_cancelled() || !cancel.cancel() {
+            std::process::exit(130);
+        }
+        eprintln!("\nStopping... press Ctrl-C again to quit immediately");
+    })
+    .context("Failed to install the Ctrl-C handler")
+}
+
+// Run a simulation by name, or resume it from a snapshot's state, and print its final report,
+// recording it and showing it live if asked to
+fn run_simulation(
+    name: &str,
+    config: &Config,
+    record: Option<&Path>,
+    dashboard: bool,
+    resume: Option<&Value>,
+) -> anyhow::Result<()> {
+    config.validate().context("Invalid configuration")?;
+    config.cancel.reset();
+
+    // Fix the seed up front and show it, so any run can be replayed with --seed
+    let seed = config.seed.unwrap_or_else(rand::random);
+    println!("Seed: {}", seed);
+    let mut config = Config {
+        seed: Some(seed),
+        ..config.clone()
+    };
+    if record.is_some() {
+        config.tape = Tape::recording();
+    }
+
+    let simulate = || {
+        let mut simulation = simulation::create(name).expect("unknown simulation");
+        match resume {
+            Some(state) => simulation::resume(simulation.as_mut(), &config, state),
+            None => simulation::run(simulation.as_mut(), &config),
+        }
+    };
+    let result = if dashboard {
+        dashboard::run(&config.events, &config.cancel, simulate)?
+    } else {
+        simulate()
+    };
+
+    // Failed runs are kept too; they are usually the ones worth replaying
+    if let Some(path) = record {
+        Recording::new(name, &config).save(path)?;
+        println!("Run recorded to {}", path.display());
+    }
+
+    let report = result?;
+    println!("\n{}", report);
+    if let Some(path) = &config.checkpoint.path {
+        if report.outcome == Outcome::Stopped {
+            println!("Snapshot saved to {}", path.display());
+        } else {
+            println!("No snapshot saved, as the run finished by itself");
+        }
+    }
+    if let Some(path) = &config.telemetry {
+        println!("Telemetry written to {}", path.display());
+    }
+    if let Some(path) = &config.chart {
+        println!("Chart report written to {}", path.display());
+    }
+    Ok(())
+}
+
+// Carry on a snapshotted run with its saved settings; the seed, clock and faults can still be
+// changed from the command line, so several what-if runs can be forked from one snapshot
+fn resume_snapshot(cli: &Cli, args: &ResumeArgs, base: Config) -> anyhow::Result<()> {
+    let snapshot = Snapshot::load(&args.path)?;
+    if simulation::create(&snapshot.simulation).is_none() {
+        bail!("Unknown simulation '{}' in snapshot", snapshot.simulation);
+    }
+    let mut config = Config {
+        seed: cli.seed,
+        events: base.events,
+        cancel: base.cancel,
+        checkpoint: base.checkpoint,
+        telemetry: base.telemetry,
+        chart: base.chart,
+        ..snapshot.config
+    };
+    if cli.virtual_time {
+        config.clock = ClockMode::Virtual;
+    }
+    for spec in &cli.faults {
+        faults::apply(&mut config.faults, spec)?;
+    }
+
+    println!(
+        "Resuming {} run from {}",
+        snapshot.simulation,
+        args.path.display()
+    );
+    run_simulation(
+        &snapshot.simulation,
+        &config,
+        None,
+        cli.dashboard,
+        Some(&snapshot.state),
+    )
+}
+
+// Run a recorded simulation again, feeding it the recorded random decisions and inputs
+fn replay_recording(path: &Path, base: Config) -> anyhow::Result<()> {
+    let recording = Recording::load(path)?;
+    let mut config = Config {
+        events: base.events,
+        tape: Tape::replaying(&recording),
+        telemetry: base.telemetry,
+        chart: base.chart,
+        ..recording.config.clone()
+    };
+    // Recorded clock readings stand in for real time, so any run can be replayed at full speed
+    if base.clock == ClockMode::Virtual {
+        config.clock = ClockMode::Virtual;
+    }
+
+    println!(
+        "Replaying {} run recorded with seed {}",
+        recording.simulation,
+        config.seed.unwrap_or_default()
+    );
+    let mut simulation = simulation::create(&recording.simulation)
+        .with_context(|| format!("Unknown simulation '{}' in recording", recording.simulation))?;
+    let report = simulation::run(simulation.as_mut(), &config)?;
+    println!("\n{}", report);
+
+    match config.tape.divergence() {
+        None => println!("Replay matched the recording."),
+        Some(reason) => println!("Warning: replay diverged from the recording: {}", reason),
+    }
+    Ok(())
+}
+
+// Run many seeds of one simulation in parallel and summarise the outcomes
+fn run_batch(args: &BatchArgs, mut config: Config) -> anyhow::Result<()> {
+    let name = args.command.name();
+    args.command.apply(&mut config);
+    config.validate().context("Invalid configuration")?;
+    let seed = config.seed.unwrap_or_else(rand::random);
+    config.seed = Some(seed);
+    config.clock = ClockMode::Virtual;
+
+    // Factory runs share the same RabbitMQ queues, so they cannot overlap
+    let jobs = if name == "factory" {
+        1
+    } else {
+        args.jobs.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        })
+    };
+    println!(
+        "Running {} {} simulations from seed {} with {} parallel jobs",
+        args.runs, name, seed, jobs
+    );
+
+    console::set_quiet(true);
+    let runs = batch::run(name, &config, args.runs, jobs);
+    console::set_quiet(false);
+    if config.cancel.is_cancelled() {
+        println!("Batch cancelled after {} of {} runs", runs.len(), args.runs);
+    }
+
+    let summary = batch::Summary::new(name, &runs);
+    println!("\n{}", summary);
+    batch::write_csv(&args.output, &runs, &summary)?;
+    println!("Results written to {}", args.output.display());
+    Ok(())
+}
+
+// Directory searched for check scripts when none are given
+const CHECK_DIR: &str = "checks";
+
+// Run every check script, one after another, and fail if any expectation did not hold
+fn run_checks(args: &CheckArgs, cancel: &Cancel) -> anyhow::Result<()> {
+    let mut paths = args.paths.clone();
+    if paths.is_empty() {
+        paths = std::fs::read_dir(CHECK_DIR)
+            .with_context(|| format!("Failed to read {}", CHECK_DIR))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|e| e == "toml"))
+            .collect();
+        paths.sort();
+    }
+    let scripts = paths
+        .iter()
+        .map(|path| Script::load(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut failed = 0;
+    for script in &scripts {
+        console::set_quiet(true);
+        let verdict = script.run(cancel);
+        console::set_quiet(false);
+        println!("{}", verdict);
+        if !verdict.passed() {
+            failed += 1;
+        }
+        if cancel.is_cancelled() {
+            bail!("Checks cancelled");
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} of {} checks failed", failed, scripts.len());
+    }
+    println!("All {} checks passed", scripts.len());
+    Ok(())
+}
+
+fn run_menu(config: &Config) {
     loop {
         println!("\n=== Simulation Menu ===");
         println!("1. Cafe Simulation");
@@ -16,6 +363,7 @@ async fn main() {
         println!("3. Home Automation Simulation");
         println!("4. Nuclear Reactor Simulation");
         println!("5. Weather Machine Simulation");
+        println!("6. Town (every simulation together)");
         println!("0. Exit");
 
         // Get user input
@@ -24,34 +372,26 @@ async fn main() {
             .read_line(&mut choice)
             .expect("Failed to read input");
 
-        match choice.trim() {
-            "1" => {
-                println!("Running Cafe Simulation...");
-                sim_cafe::run().expect("Cafe simulation failed"); // Run the Cafe simulation
-            }
-            "2" => {
-                println!("Running Factory Simulation...");
-                sim_factory::run(); // Now running the Factory simulation
-            }
-            "3" => {
-                println!("Running Home Automation Simulation...");
-                sim_home::run().expect("Home automation simulation failed"); // Run the Home Automation simulation
-            }
-            "4" => {
-                println!("Running Nuclear Reactor Simulation...");
-                sim_nuclear::run().await; // Run the async Nuclear simulation
-            }
-            "5" => {
-                println!("Running Weather Machine Simulation...");
-                sim_weather::run().await; // Run the async Weather Machine simulation
-            }
+        let name = match choice.trim() {
+            "1" => "cafe",
+            "2" => "factory",
+            "3" => "home",
+            "4" => "nuclear",
+            "5" => "weather",
+            "6" => "town",
             "0" => {
                 println!("Exiting...");
                 break; // Exit the loop to stop the program
             }
             _ => {
                 println!("Invalid choice! Please select a valid number.");
+                continue;
             }
+        };
+
+        // Every simulation is driven the same way; a failed run returns to the menu
+        if let Err(e) = run_simulation(name, config, None, false, None) {
+            println!("Simulation failed: {:#}", e);
         }
     }
 }
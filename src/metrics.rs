@@ -0,0 +1,496 @@
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Response, Server};
+
+use crate::events::{Event, EventSink, Record};
+
+const RUNNING_HELP: &str = "Whether the simulation is running";
+const QUEUE_HELP: &str = "Orders placed but not yet being brewed";
+const COUNTER_HELP: &str = "Finished orders waiting on the counter to be collected";
+const LOST_HELP: &str = "Customers who left without being served, by reason";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+// All samples of one metric, keyed by their rendered label set
+struct Family {
+    kind: Kind,
+    help: &'static str,
+    samples: BTreeMap<String, f64>,
+}
+
+#[derive(Default)]
+struct Inner {
+    families: BTreeMap<&'static str, Family>,
+    ordered_at: HashMap<usize, f64>, // Simulation time each unserved cafe ticket was ordered
+    brewing_since: HashMap<usize, f64>, // Simulation time a barista started each brewing ticket
+    orders_served: u64,
+    total_wait_secs: f64,
+    orders_brewed: u64,
+    total_brew_secs: f64,
+}
+
+impl Inner {
+    fn family(&mut self, kind: Kind, name: &'static str, help: &'static str) -> &mut Family {
+        self.families.entry(name).or_insert_with(|| Family {
+            kind,
+            help,
+            samples: BTreeMap::new(),
+        })
+    }
+
+    fn set(&mut self, name: &'static str, help: &'static str, labels: &str, value: f64) {
+        let family = self.family(Kind::Gauge, name, help);
+        family.samples.insert(labels.to_string(), value);
+    }
+
+    fn add(
+        &mut self,
+        kind: Kind,
+        name: &'static str,
+        help: &'static str,
+        labels: &str,
+        delta: f64,
+    ) {
+        let family = self.family(kind, name, help);
+        *family.samples.entry(labels.to_string()).or_default() += delta;
+    }
+
+    fn count(&mut self, name: &'static str, help: &'static str, labels: &str) {
+        self.add(Kind::Counter, name, help, labels, 1.0);
+    }
+}
+
+// Counters and gauges built from the simulations' events, rendered in Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn render(&self) -> String {
+        let inner = self.inner.lock();
+        let mut out = String::new();
+        for (name, family) in &inner.families {
+            let kind = match family.kind {
+                Kind::Counter => "counter",
+                Kind::Gauge => "gauge",
+            };
+            let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in &family.samples {
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+            }
+        }
+        out
+    }
+}
+
+impl EventSink for Metrics {
+    fn record(&self, record: &Record) {
+        let mut inner = self.inner.lock();
+        let labels = label("simulation", &record.simulation);
+        let now = record.sim_time_secs.unwrap_or_default();
+
+        if let Some(now) = record.sim_time_secs {
+            inner.set(
+                "simulation_time_seconds",
+                "Simulation clock time of the latest event",
+                &labels,
+                now,
+            );
+        }
+        if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(&record.event) {
+            if let Some(serde_json::Value::String(event)) = fields.get("event") {
+                inner.count(
+                    "simulation_events_total",
+                    "Events emitted, by kind",
+                    &format!("{},{}", labels, label("event", event)),
+                );
+            }
+        }
+
+        match &record.event {
+            Event::SimulationStarted => {
+                inner.set("simulation_running", RUNNING_HELP, &labels, 1.0);
+                if record.simulation == "cafe" {
+                    inner.ordered_at.clear();
+                    inner.brewing_since.clear();
+                    inner.set("cafe_order_queue_length", QUEUE_HELP, &labels, 0.0);
+                    inner.set("cafe_orders_on_counter", COUNTER_HELP, &labels, 0.0);
+                }
+            }
+            Event::SimulationFinished { outcome } => {
+                inner.set("simulation_running", RUNNING_HELP, &labels, 0.0);
+                inner.count(
+                    "simulation_runs_total",
+                    "Finished runs, by outcome",
+                    &format!("{},{}", labels, label("outcome", outcome.kind())),
+                );
+            }
+
+            // Cafe
+            Event::OrderPlaced { ticket, .. } => {
+                inner.count(
+                    "cafe_orders_placed_total",
+                    "Orders placed by customers",
+                    &labels,
+                );
+                inner.add(
+                    Kind::Gauge,
+                    "cafe_order_queue_length",
+                    QUEUE_HELP,
+                    &labels,
+                    1.0,
+                );
+                inner.ordered_at.insert(*ticket, now);
+            }
+            Event::BrewStarted { ticket, .. } => {
+                inner.add(
+                    Kind::Gauge,
+                    "cafe_order_queue_length",
+                    QUEUE_HELP,
+                    &labels,
+                    -1.0,
+                );
+                inner.brewing_since.insert(*ticket, now);
+            }
+            Event::ItemPrepared { item, .. } => {
+                inner.count(
+                    "cafe_items_prepared_total",
+                    "Menu items prepared by baristas, by item",
+                    &format!("{},{}", labels, label("item", item)),
+                );
+            }
+            Event::OrderReady { ticket, .. } => {
+                inner.add(
+                    Kind::Gauge,
+                    "cafe_orders_on_counter",
+                    COUNTER_HELP,
+                    &labels,
+                    1.0,
+                );
+                if let Some(started_at) = inner.brewing_since.remove(ticket) {
+                    inner.orders_brewed += 1;
+                    inner.total_brew_secs += now - started_at;
+                    let average = inner.total_brew_secs / inner.orders_brewed as f64;
+                    inner.set(
+                        "cafe_average_brew_wait_seconds",
+                        "Average time from a barista starting an order to it being ready",
+                        &labels,
+                        average,
+                    );
+                }
+            }
+            Event::OrderServed { ticket, .. } => {
+                inner.add(
+                    Kind::Gauge,
+                    "cafe_orders_on_counter",
+                    COUNTER_HELP,
+                    &labels,
+                    -1.0,
+                );
+                inner.count(
+                    "cafe_orders_served_total",
+                    "Orders served to customers",
+                    &labels,
+                );
+                if let Some(ordered_at) = inner.ordered_at.remove(ticket) {
+                    inner.orders_served += 1;
+                    inner.total_wait_secs += now - ordered_at;
+                    let average = inner.total_wait_secs / inner.orders_served as f64;
+                    inner.set(
+                        "cafe_average_order_wait_seconds",
+                        "Average time from ordering to being served",
+                        &labels,
+                        average,
+                    );
+                }
+            }
+
+            Event::CustomerBalked { .. } => inner.count(
+                "cafe_customers_lost_total",
+                LOST_HELP,
+                &format!("{},reason=\"balked\"", labels),
+            ),
+            Event::OrderCancelled { ticket, was, .. } => {
+                inner.count(
+                    "cafe_customers_lost_total",
+                    LOST_HELP,
+                    &format!("{},reason=\"reneged\"", labels),
+                );
+                match was.as_str() {
+                    "queued" => inner.add(
+                        Kind::Gauge,
+                        "cafe_order_queue_length",
+                        QUEUE_HELP,
+                        &labels,
+                        -1.0,
+                    ),
+                    "on_counter" => inner.add(
+                        Kind::Gauge,
+                        "cafe_orders_on_counter",
+                        COUNTER_HELP,
+                        &labels,
+                        -1.0,
+                    ),
+                    _ => {}
+                }
+                inner.ordered_at.remove(ticket);
+                inner.brewing_since.remove(ticket);
+            }
+            Event::OrderDropped { ticket, .. } => {
+                inner.brewing_since.remove(ticket);
+            }
+            Event::MachineBreakdown { .. } => inner.count(
+                "cafe_machine_breakdowns_total",
+                "Coffee machine breakdowns",
+                &labels,
+            ),
+
+            // Factory
+            Event::InventoryChanged { grams } => inner.set(
+                "factory_inventory_grams",
+                "Bean inventory at the factory",
+                &labels,
+                *grams as f64,
+            ),
+            Event::TaskCompleted { task } => inner.count(
+                "factory_tasks_completed_total",
+                "Production tasks completed, by task",
+                &format!("{},{}", labels, label("task", task)),
+            ),
+            Event::ShipmentCompleted { grams } => inner.add(
+                Kind::Counter,
+                "factory_shipped_grams_total",
+                "Coffee shipped to retail",
+                &labels,
+                *grams as f64,
+            ),
+
+            Event::EquipmentFailure { .. } => inner.count(
+                "factory_equipment_failures_total",
+                "Production line breakdowns",
+                &labels,
+            ),
+
+            // Home
+            Event::HomeTaskExecuted { .. } => inner.count(
+                "home_tasks_executed_total",
+                "Scheduled tasks run by the home scheduler",
+                &labels,
+            ),
+            Event::Intrusion => {
+                inner.count("home_intrusions_total", "Intrusions detected", &labels)
+            }
+            Event::DeadlyWeatherWarning => inner.count(
+                "home_weather_warnings_total",
+                "Deadly weather warnings issued",
+                &labels,
+            ),
+
+            // Nuclear
+            Event::ReactorReading {
+                temperature_c,
+                power_w,
+                radiation_bq,
+            } => {
+                inner.set(
+                    "nuclear_temperature_celsius",
+                    "Reactor core temperature",
+                    &labels,
+                    *temperature_c as f64,
+                );
+                inner.set(
+                    "nuclear_power_watts",
+                    "Reactor power output",
+                    &labels,
+                    *power_w as f64,
+                );
+                inner.set(
+                    "nuclear_radiation_becquerels",
+                    "Radiation level at the reactor",
+                    &labels,
+                    *radiation_bq as f64,
+                );
+            }
+
+            // Weather
+            Event::WeatherReading {
+                temperature_c,
+                wind_speed_kmh,
+                structural_health_pct,
+            } => {
+                inner.set(
+                    "weather_temperature_celsius",
+                    "Temperature produced by the weather machine",
+                    &labels,
+                    *temperature_c as f64,
+                );
+                inner.set(
+                    "weather_wind_speed_kmh",
+                    "Wind speed produced by the weather machine",
+                    &labels,
+                    *wind_speed_kmh as f64,
+                );
+                inner.set(
+                    "weather_structural_health_percent",
+                    "Structural health of the weather machine",
+                    &labels,
+                    *structural_health_pct as f64,
+                );
+            }
+            Event::Repair { .. } => inner.count(
+                "weather_repairs_total",
+                "Structural repairs carried out",
+                &labels,
+            ),
+            _ => {}
+        }
+    }
+}
+
+// Serve the metrics at http://127.0.0.1:<port>/metrics from a background thread
+pub fn serve(metrics: Arc<Metrics>, port: u16) -> Result<()> {
+    let server = Server::http(("127.0.0.1", port))
+        .map_err(|e| anyhow!("Failed to serve metrics on port {}: {}", port, e))?;
+    let content_type =
+        Header::from_bytes("Content-Type", "text/plain; version=0.0.4").expect("valid header");
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let path = request.url().split('?').next().unwrap_or_default();
+            let response = if path == "/metrics" {
+                Response::from_string(metrics.render()).with_header(content_type.clone())
+            } else {
+                Response::from_string("Not found").with_status_code(404)
+            };
+            // A scraper hanging up early is not our problem
+            let _ = request.respond(response);
+        }
+    });
+    Ok(())
+}
+
+// A label as `name="value"`, with the value escaped as the text format requires
+fn label(name: &str, value: &str) -> String {
+    let value = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("{}=\"{}\"", name, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(metrics: &Metrics, sim_time_secs: f64, event: Event) {
+        metrics.record(&Record {
+            timestamp: String::new(),
+            sim_time_secs: Some(sim_time_secs),
+            simulation: "cafe".to_string(),
+            event,
+        });
+    }
+
+    #[test]
+    fn brew_and_order_waits_are_averaged_separately() {
+        let metrics = Metrics::new();
+        let placed = |ticket| Event::OrderPlaced {
+            customer_id: ticket,
+            ticket,
+            details: format!("ORDER{}", ticket),
+            items: Vec::new(),
+        };
+        at(&metrics, 0.0, placed(1));
+        at(&metrics, 0.0, placed(2));
+        at(
+            &metrics,
+            1.0,
+            Event::BrewStarted {
+                barista_id: 1,
+                ticket: 1,
+            },
+        );
+        at(
+            &metrics,
+            2.0,
+            Event::BrewStarted {
+                barista_id: 2,
+                ticket: 2,
+            },
+        );
+        at(
+            &metrics,
+            3.0,
+            Event::OrderReady {
+                barista_id: 1,
+                ticket: 1,
+            },
+        );
+        at(
+            &metrics,
+            4.0,
+            Event::OrderDropped {
+                barista_id: 2,
+                ticket: 2,
+            },
+        );
+        at(
+            &metrics,
+            5.0,
+            Event::OrderServed {
+                barista_id: 1,
+                ticket: 1,
+                details: "ORDER1".to_string(),
+            },
+        );
+
+        let text = metrics.render();
+        assert!(
+            text.contains("cafe_average_brew_wait_seconds{simulation=\"cafe\"} 2\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("cafe_average_order_wait_seconds{simulation=\"cafe\"} 5\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let metrics = Metrics::new();
+        at(
+            &metrics,
+            0.0,
+            Event::ItemPrepared {
+                barista_id: 1,
+                ticket: 1,
+                item: "Flat \"white\"\\\nlarge".to_string(),
+            },
+        );
+
+        let text = metrics.render();
+        assert!(
+            text.contains(
+                "cafe_items_prepared_total{simulation=\"cafe\",item=\"Flat \\\"white\\\"\\\\\\nlarge\"} 1\n"
+            ),
+            "{}",
+            text
+        );
+    }
+}
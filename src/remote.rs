@@ -0,0 +1,543 @@
+use anyhow::{anyhow, bail, Context, Result};
+use crossbeam::channel::{self, Receiver, Sender};
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tiny_http::{Header, Method, Request as HttpRequest, Response, Server, StatusCode};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::config::Config;
+use crate::events::{EventSink, Record};
+use crate::simulation::{self, Report, Simulation};
+use crate::snapshot::Snapshot;
+
+// How long to wait for a running simulation to pick up a request; steps take a few seconds at most
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// What the remote control API can ask of a running simulation
+#[derive(Debug)]
+enum Request {
+    Stop,
+    State,
+    Inject(String),
+    Snapshot,
+}
+
+#[derive(Debug)]
+struct Pending {
+    request: Request,
+    reply: Sender<Result<Value, String>>,
+}
+
+// Channel through which a running simulation takes requests between its steps
+#[derive(Clone, Debug)]
+pub struct Remote {
+    sender: Sender<Pending>,
+    receiver: Receiver<Pending>,
+}
+
+impl Default for Remote {
+    fn default() -> Self {
+        let (sender, receiver) = channel::unbounded();
+        Remote { sender, receiver }
+    }
+}
+
+impl Remote {
+    pub fn new() -> Self {
+        Remote::default()
+    }
+
+    // Answer every pending request; returns true once the simulation has been asked to stop
+    pub fn handle(&self, simulation: &mut dyn Simulation, config: &Config) -> bool {
+        let mut stop = false;
+        for pending in self.receiver.try_iter() {
+            let reply = match pending.request {
+                Request::Stop => {
+                    stop = true;
+                    Ok(json!({ "stopping": simulation.name() }))
+                }
+                // The run has not ended yet, so it has no outcome
+                Request::State => serde_json::to_value(simulation.report())
+                    .map(|mut state| {
+                        state["outcome"] = Value::Null;
+                        state
+                    })
+                    .map_err(|e| e.to_string()),
+                Request::Inject(event) => simulation
+                    .inject(&event)
+                    .map(|_| json!({ "injected": event }))
+                    .map_err(|e| format!("{:#}", e)),
+                Request::Snapshot => Snapshot::take(simulation, config)
+                    .and_then(|snapshot| Ok(serde_json::to_value(snapshot)?))
+                    .map_err(|e| format!("{:#}", e)),
+            };
+            // The requester may have given up waiting
+            let _ = pending.reply.send(reply);
+        }
+        stop
+    }
+
+    pub fn stop(&self) -> Result<Value> {
+        self.request(Request::Stop)
+    }
+
+    // Current state of the running simulation, as its report so far
+    pub fn state(&self) -> Result<Value> {
+        self.request(Request::State)
+    }
+
+    pub fn inject(&self, event: &str) -> Result<Value> {
+        self.request(Request::Inject(event.to_string()))
+    }
+
+    // Snapshot of the running simulation, which can be resumed later
+    pub fn snapshot(&self) -> Result<Value> {
+        self.request(Request::Snapshot)
+    }
+
+    fn request(&self, request: Request) -> Result<Value> {
+        let (reply, replied) = channel::bounded(1);
+        self.sender
+            .send(Pending { request, reply })
+            .map_err(|_| anyhow!("Simulation is not taking requests"))?;
+        replied
+            .recv_timeout(REPLY_TIMEOUT)
+            .map_err(|_| anyhow!("Simulation did not answer in time"))?
+            .map_err(|e| anyhow!(e))
+    }
+}
+
+// Forwards every event, as a JSON line, to each live subscriber
+#[derive(Default)]
+pub struct Broadcast {
+    subscribers: Mutex<Vec<Sender<String>>>,
+}
+
+impl Broadcast {
+    pub fn subscribe(&self) -> Receiver<String> {
+        let (sender, receiver) = channel::unbounded();
+        self.subscribers.lock().push(sender);
+        receiver
+    }
+}
+
+impl EventSink for Broadcast {
+    fn record(&self, record: &Record) {
+        let Ok(line) = serde_json::to_string(record) else {
+            return;
+        };
+        // Subscribers that went away are dropped
+        self.subscribers
+            .lock()
+            .retain(|subscriber| subscriber.send(line.clone()).is_ok());
+    }
+}
+
+struct Running {
+    simulation: &'static str,
+    remote: Remote,
+    stopping: bool, // Asked to stop; no longer taking requests while it winds down
+    thread: JoinHandle<Result<Report>>,
+}
+
+// Runs at most one simulation at a time on behalf of the HTTP API
+struct Controller {
+    base: Config,
+    broadcast: Arc<Broadcast>,
+    running: Mutex<Option<Running>>,
+    last: Mutex<Value>, // Result of the most recently finished run
+}
+
+impl Controller {
+    // Collect the result of a run that has finished by itself
+    fn reap(&self, running: &mut Option<Running>) {
+        if running.as_ref().is_some_and(|r| r.thread.is_finished()) {
+            let done = running.take().expect("checked above");
+            let result = match done.thread.join() {
+                Ok(Ok(report)) => json!({ "report": report }),
+                Ok(Err(e)) => json!({ "error": format!("{:#}", e) }),
+                Err(_) => json!({ "error": "simulation panicked" }),
+            };
+            *self.last.lock() = json!({ "simulation": done.simulation, "result": result });
+        }
+    }
+
+    fn start(&self, name: &str, body: &str) -> Result<Value, (u16, String)> {
+        let simulation = simulation::create(name)
+            .ok_or_else(|| (404, format!("Unknown simulation '{}'", name)))?
+            .name();
+
+        // An optional JSON body overrides the server's configuration, like a scenario file
+        let config = if body.trim().is_empty() {
+            self.base.clone()
+        } else {
+            let layered = layered(&self.base, body)
+                .map_err(|e| (400, format!("Invalid configuration: {}", e)))?;
+            Config {
+                events: self.base.events.clone(),
+                tape: self.base.tape.clone(),
+                ..layered
+            }
+        };
+        self.launch(simulation, config, None)
+    }
+
+    // Carry on from a snapshot taken with GET /snapshot, with a fresh seed unless one is configured
+    fn resume(&self, body: &str) -> Result<Value, (u16, String)> {
+        let snapshot: Snapshot =
+            serde_json::from_str(body).map_err(|e| (400, format!("Invalid snapshot: {}", e)))?;
+        let simulation = simulation::create(&snapshot.simulation)
+            .ok_or_else(|| {
+                (
+                    400,
+                    format!("Unknown simulation '{}' in snapshot", snapshot.simulation),
+                )
+            })?
+            .name();
+        let config = Config {
+            seed: self.base.seed,
+            events: self.base.events.clone(),
+            tape: self.base.tape.clone(),
+            ..snapshot.config
+        };
+        self.launch(simulation, config, Some(snapshot.state))
+    }
+
+    fn launch(
+        &self,
+        simulation: &'static str,
+        mut config: Config,
+        state: Option<Value>,
+    ) -> Result<Value, (u16, String)> {
+        let mut running = self.running.lock();
+        self.reap(&mut running);
+        if let Some(current) = running.as_ref() {
+            return Err((
+                409,
+                format!("The {} simulation is already running", current.simulation),
+            ));
+        }
+        config
+            .validate()
+            .map_err(|e| (400, format!("Invalid configuration: {:#}", e)))?;
+        config.remote = Remote::new();
+        // Ctrl-C on the server stops the served run too
+        config.cancel = self.base.cancel.clone();
+        config.cancel.reset();
+
+        let remote = config.remote.clone();
+        let thread = thread::spawn(move || {
+            let mut simulation = simulation::create(simulation).expect("checked above");
+            match state {
+                Some(state) => simulation::resume(simulation.as_mut(), &config, &state),
+                None => simulation::run(simulation.as_mut(), &config),
+            }
+        });
+        *running = Some(Running {
+            simulation,
+            remote,
+            stopping: false,
+            thread,
+        });
+        Ok(json!({ "started": simulation }))
+    }
+
+    // Forward a request to the running simulation
+    fn forward(
+        &self,
+        request: impl FnOnce(&Remote) -> Result<Value>,
+    ) -> Result<Value, (u16, String)> {
+        let remote = {
+            let mut running = self.running.lock();
+            self.reap(&mut running);
+            match running.as_ref() {
+                Some(current) if current.stopping => {
+                    return Err((
+                        409,
+                        format!("The {} simulation is stopping", current.simulation),
+                    ))
+                }
+                Some(current) => current.remote.clone(),
+                None => return Err((409, "No simulation is running".to_string())),
+            }
+        };
+        request(&remote).map_err(|e| (400, format!("{:#}", e)))
+    }
+
+    fn stop(&self) -> Result<Value, (u16, String)> {
+        let stopped = self.forward(Remote::stop)?;
+        if let Some(current) = self.running.lock().as_mut() {
+            current.stopping = true;
+        }
+        Ok(stopped)
+    }
+
+    fn state(&self) -> Value {
+        let current = {
+            let mut running = self.running.lock();
+            self.reap(&mut running);
+            running
+                .as_ref()
+                .map(|r| (r.simulation, r.stopping, r.remote.clone()))
+        };
+        match current {
+            Some((simulation, true, _)) => {
+                json!({ "running": true, "simulation": simulation, "stopping": true })
+            }
+            Some((simulation, false, remote)) => match remote.state() {
+                Ok(report) => json!({ "running": true, "simulation": simulation, "state": report }),
+                Err(e) => {
+                    json!({ "running": true, "simulation": simulation, "error": format!("{:#}", e) })
+                }
+            },
+            None => json!({ "running": false, "last": *self.last.lock() }),
+        }
+    }
+}
+
+// Serve the remote control API on http://127.0.0.1:<port>, blocking until the process exits:
+//   POST /start/<simulation>   start a simulation; an optional JSON body overrides the configuration
+//   POST /stop                 stop the running simulation
+//   GET  /state                current state of the running simulation, or the last result
+//   POST /inject/<event>       trigger an event, e.g. coolant_failure or intrusion
+//   GET  /snapshot             complete state of the running simulation, to resume later
+//   POST /resume               resume the snapshot given as the body
+//   GET  /events               WebSocket stream of every event as JSON
+pub fn serve(port: u16, base: Config) -> Result<()> {
+    // Loopback only; nothing here is meant to be reachable from other machines
+    let server = Server::http(("127.0.0.1", port))
+        .map_err(|e| anyhow!("Failed to serve remote control on port {}: {}", port, e))?;
+    let broadcast = Arc::new(Broadcast::default());
+    base.events.add_sink(broadcast.clone());
+    let controller = Arc::new(Controller {
+        base,
+        broadcast,
+        running: Mutex::new(None),
+        last: Mutex::new(Value::Null),
+    });
+
+    for request in server.incoming_requests() {
+        let controller = Arc::clone(&controller);
+        thread::spawn(move || {
+            if let Err(e) = handle(&controller, request) {
+                eprintln!("Remote control request failed: {:#}", e);
+            }
+        });
+    }
+    bail!("Remote control server stopped")
+}
+
+fn handle(controller: &Controller, mut request: HttpRequest) -> Result<()> {
+    // Browsers send an Origin; refuse pages from anywhere but this machine
+    if let Some(origin) = header(&request, "Origin") {
+        if !is_local_origin(&origin) {
+            return respond(
+                request,
+                Err((403, format!("Origin {} is not allowed", origin))),
+            );
+        }
+    }
+
+    let path = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let method = request.method().clone();
+
+    if method == Method::Get && segments == ["events"] {
+        return subscribe(controller, request);
+    }
+
+    let mut body = String::new();
+    request
+        .as_reader()
+        .read_to_string(&mut body)
+        .context("Failed to read request body")?;
+
+    let result = match (method, segments.as_slice()) {
+        (Method::Get, ["state"]) => Ok(controller.state()),
+        (Method::Post, ["start", name]) => controller.start(name, &body),
+        (Method::Post, ["stop"]) => controller.stop(),
+        (Method::Post, ["inject", event]) => controller.forward(|remote| remote.inject(event)),
+        (Method::Get, ["snapshot"]) => controller.forward(Remote::snapshot),
+        (Method::Post, ["resume"]) => controller.resume(&body),
+        _ => Err((404, format!("No such endpoint: {}", path))),
+    };
+    respond(request, result)
+}
+
+fn respond(request: HttpRequest, result: Result<Value, (u16, String)>) -> Result<()> {
+    let (status, body) = match result {
+        Ok(value) => (200, value),
+        Err((status, error)) => (status, json!({ "error": error })),
+    };
+    let content_type =
+        Header::from_bytes("Content-Type", "application/json").expect("valid header");
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(content_type);
+    request.respond(response).context("Failed to send response")
+}
+
+// Upgrade to a WebSocket and stream events until the client goes away
+fn subscribe(controller: &Controller, request: HttpRequest) -> Result<()> {
+    let Some(key) = header(&request, "Sec-WebSocket-Key") else {
+        return respond(
+            request,
+            Err((400, "Expected a WebSocket upgrade".to_string())),
+        );
+    };
+    let accept = Header::from_bytes("Sec-WebSocket-Accept", derive_accept_key(key.as_bytes()))
+        .expect("valid header");
+    let response = Response::empty(StatusCode(101)).with_header(accept);
+    let stream = request.upgrade("websocket", response);
+
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+    for line in controller.broadcast.subscribe().iter() {
+        if socket.send(Message::text(line)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn header(request: &HttpRequest, name: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.to_string())
+}
+
+// The settings in a JSON body laid over `base`; anything the body leaves out keeps its base value
+fn layered(base: &Config, body: &str) -> Result<Config> {
+    let overrides: Value = serde_json::from_str(body)?;
+    let mut config = serde_json::to_value(base)?;
+    merge(&mut config, overrides);
+    Ok(serde_json::from_value(config)?)
+}
+
+// Objects are merged key by key; anything else replaces the base value
+fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+// An origin is scheme://host[:port]; IPv6 hosts come in brackets, e.g. http://[::1]:8080
+fn is_local_origin(origin: &str) -> bool {
+    let Some((_, authority)) = origin.split_once("://") else {
+        return false;
+    };
+    let authority = authority.split('/').next().unwrap_or_default();
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((host, port)) => (host, port),
+            None => return false,
+        },
+        None => authority.split_at(authority.find(':').unwrap_or(authority.len())),
+    };
+    let port_ok = port.is_empty()
+        || port
+            .strip_prefix(':')
+            .is_some_and(|port| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()));
+    port_ok && matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ClockMode;
+
+    #[test]
+    fn a_request_body_is_laid_over_the_server_configuration() {
+        let mut base = Config {
+            seed: Some(7),
+            clock: ClockMode::Virtual,
+            ..Config::default()
+        };
+        base.nuclear.scram_power_w = 250;
+
+        let config = layered(&base, "{}").unwrap();
+        assert_eq!(config.seed, Some(7));
+        assert_eq!(config.clock, ClockMode::Virtual);
+        assert_eq!(config.nuclear.scram_power_w, 250);
+
+        let config = layered(&base, r#"{"seed": 9, "cafe": {"baristas": 2}}"#).unwrap();
+        assert_eq!(config.seed, Some(9));
+        assert_eq!(config.clock, ClockMode::Virtual);
+        assert_eq!(config.cafe.baristas, 2);
+        assert_eq!(
+            config.cafe.coffee_machines,
+            Config::default().cafe.coffee_machines
+        );
+
+        assert!(layered(&base, r#"{"cafe": {"chefs": 2}}"#).is_err());
+        assert!(layered(&base, "[").is_err());
+    }
+
+    #[test]
+    fn a_running_simulation_has_no_outcome_yet() {
+        let remote = Remote::new();
+        let asking = {
+            let remote = remote.clone();
+            thread::spawn(move || remote.state())
+        };
+        let mut simulation = simulation::create("nuclear").unwrap();
+        while !asking.is_finished() {
+            remote.handle(simulation.as_mut(), &Config::default());
+            thread::sleep(Duration::from_millis(1));
+        }
+        let state = asking.join().unwrap().unwrap();
+        assert_eq!(state["simulation"], "nuclear");
+        assert!(state["outcome"].is_null());
+    }
+
+    #[test]
+    fn only_this_machine_is_a_local_origin() {
+        for origin in [
+            "http://localhost",
+            "http://localhost:3000",
+            "https://127.0.0.1:8443",
+            "http://[::1]",
+            "http://[::1]:8080",
+        ] {
+            assert!(is_local_origin(origin), "{} should be allowed", origin);
+        }
+        for origin in [
+            "null",
+            "localhost",
+            "http://localhost.evil.com",
+            "http://localhost.evil.com:3000",
+            "http://127.0.0.1.evil.com",
+            "http://evil.com",
+            "http://localhost@evil.com",
+            "http://localhost:80@evil.com",
+            "http://[::1].evil.com",
+            "http://[::2]",
+            "http://[::1",
+            "http://localhost:",
+            "http://localhost:evil",
+        ] {
+            assert!(!is_local_origin(origin), "{} should be rejected", origin);
+        }
+    }
+}
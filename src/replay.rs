@@ -0,0 +1,266 @@
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::Config;
+
+// Everything a run took from outside its own deterministic logic, in the order it was taken
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Tracks {
+    streams: BTreeMap<String, VecDeque<u64>>, // Raw draws of each named random stream
+    inputs: BTreeMap<String, VecDeque<Value>>, // Clock readings, received messages, ...
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Mode {
+    #[default]
+    Off, // Values come straight from their live source
+    Record,
+    Replay,
+}
+
+#[derive(Default)]
+struct State {
+    tracks: Tracks,
+    divergence: Option<String>, // First point at which a replay stopped matching its recording
+}
+
+// Records every random draw and external input of a run, or feeds them back from a recording.
+//
+// Components never read a nondeterministic value directly; they pass the live source to the tape,
+// which either returns it (recording it when asked to) or substitutes the recorded value. Values
+// are kept per named track, so threads that each own a track replay in the same order.
+#[derive(Clone, Default)]
+pub struct Tape {
+    mode: Mode,
+    state: Arc<Mutex<State>>,
+}
+
+impl Tape {
+    pub fn recording() -> Self {
+        Tape {
+            mode: Mode::Record,
+            ..Default::default()
+        }
+    }
+
+    pub fn replaying(recording: &Recording) -> Self {
+        Tape {
+            mode: Mode::Replay,
+            state: Arc::new(Mutex::new(State {
+                tracks: recording.tracks.clone(),
+                divergence: None,
+            })),
+        }
+    }
+
+    // Next raw value of a random stream
+    pub fn draw(&self, stream: &str, live: impl FnOnce() -> u64) -> u64 {
+        match self.mode {
+            Mode::Off => live(),
+            Mode::Record => {
+                let value = live();
+                let mut state = self.state.lock();
+                track(&mut state.tracks.streams, stream).push_back(value);
+                value
+            }
+            Mode::Replay => {
+                let mut state = self.state.lock();
+                match track(&mut state.tracks.streams, stream).pop_front() {
+                    Some(value) => value,
+                    None => {
+                        state.diverge(format!("random stream '{}' ran out of values", stream));
+                        drop(state);
+                        live()
+                    }
+                }
+            }
+        }
+    }
+
+    // Value read from outside the simulation, such as the time or a received message
+    pub fn input<T>(&self, channel: &str, live: impl FnOnce() -> T) -> T
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        match self.mode {
+            Mode::Off => live(),
+            Mode::Record => {
+                let value = live();
+                let recorded = serde_json::to_value(&value).expect("tape inputs must serialize");
+                let mut state = self.state.lock();
+                track(&mut state.tracks.inputs, channel).push_back(recorded);
+                value
+            }
+            Mode::Replay => {
+                let mut state = self.state.lock();
+                let recorded = track(&mut state.tracks.inputs, channel).pop_front();
+                match recorded.map(serde_json::from_value) {
+                    Some(Ok(value)) => value,
+                    Some(Err(_)) => {
+                        state.diverge(format!("input '{}' has an unexpected type", channel));
+                        drop(state);
+                        live()
+                    }
+                    None => {
+                        state.diverge(format!("input '{}' ran out of values", channel));
+                        drop(state);
+                        live()
+                    }
+                }
+            }
+        }
+    }
+
+    // Why a finished replay did not follow its recording exactly, if it did not
+    pub fn divergence(&self) -> Option<String> {
+        let state = self.state.lock();
+        if let Some(divergence) = &state.divergence {
+            return Some(divergence.clone());
+        }
+        if self.mode != Mode::Replay {
+            return None;
+        }
+
+        // Values left over mean the replay took a shorter path than the recorded run
+        let leftover_stream = state.tracks.streams.iter().find(|(_, v)| !v.is_empty());
+        let leftover_input = state.tracks.inputs.iter().find(|(_, v)| !v.is_empty());
+        if let Some((name, values)) = leftover_stream {
+            Some(format!(
+                "random stream '{}' has {} unused values",
+                name,
+                values.len()
+            ))
+        } else {
+            leftover_input.map(|(name, values)| {
+                format!("input '{}' has {} unused values", name, values.len())
+            })
+        }
+    }
+
+    fn tracks(&self) -> Tracks {
+        self.state.lock().tracks.clone()
+    }
+}
+
+impl State {
+    fn diverge(&mut self, reason: String) {
+        self.divergence.get_or_insert(reason);
+    }
+}
+
+impl fmt::Debug for Tape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tape").field("mode", &self.mode).finish()
+    }
+}
+
+fn track<'a, T>(tracks: &'a mut BTreeMap<String, VecDeque<T>>, name: &str) -> &'a mut VecDeque<T> {
+    tracks.entry(name.to_string()).or_default()
+}
+
+// A recorded run: which simulation ran, with which settings, and everything its tape captured
+#[derive(Serialize, Deserialize)]
+pub struct Recording {
+    pub simulation: String,
+    pub config: Config,
+    tracks: Tracks,
+}
+
+impl Recording {
+    // Capture a run that used a recording tape
+    pub fn new(simulation: &str, config: &Config) -> Self {
+        Recording {
+            simulation: simulation.to_string(),
+            config: config.clone(),
+            tracks: config.tape.tracks(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open recording {}", path.display()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Failed to read recording {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::RngSource;
+    use rand::Rng;
+
+    // Takes `count` draws and one outside reading, the seed standing in for it, through the tape
+    fn run(config: &Config, count: usize) -> (Vec<u32>, u64) {
+        let rngs = RngSource::new(config.seed).with_tape(config.tape.clone());
+        let mut rng = rngs.stream("reactor");
+        let draws = (0..count).map(|_| rng.gen()).collect();
+        (draws, config.tape.input("seed", || rngs.seed()))
+    }
+
+    fn recorded(count: usize) -> (Recording, (Vec<u32>, u64)) {
+        let config = Config {
+            seed: Some(7),
+            tape: Tape::recording(),
+            ..Config::default()
+        };
+        let result = run(&config, count);
+        (Recording::new("nuclear", &config), result)
+    }
+
+    fn replayed(recording: &Recording, count: usize) -> (Tape, (Vec<u32>, u64)) {
+        // A different seed shows the values come from the recording rather than the generator
+        let config = Config {
+            seed: Some(8),
+            tape: Tape::replaying(recording),
+            ..Config::default()
+        };
+        let result = run(&config, count);
+        (config.tape, result)
+    }
+
+    #[test]
+    fn a_replay_follows_its_recording() {
+        let (recording, original) = recorded(5);
+        assert_eq!(recording.simulation, "nuclear");
+        let (tape, replay) = replayed(&recording, 5);
+        assert_eq!(replay, original);
+        assert_eq!(replay.1, 7);
+        assert_eq!(tape.divergence(), None);
+    }
+
+    #[test]
+    fn a_replay_that_strays_from_its_recording_is_detected() {
+        let (recording, _) = recorded(5);
+
+        let (tape, _) = replayed(&recording, 6);
+        assert_eq!(
+            tape.divergence().as_deref(),
+            Some("random stream 'reactor' ran out of values")
+        );
+
+        let (tape, _) = replayed(&recording, 4);
+        assert_eq!(
+            tape.divergence().as_deref(),
+            Some("random stream 'reactor' has 1 unused values")
+        );
+    }
+}
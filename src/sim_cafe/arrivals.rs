@@ -0,0 +1,634 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDateTime, NaiveTime, Timelike};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// How customers turn up, e.g.
+//
+//   [cafe.arrivals]
+//   model = "poisson"
+//   per_minute = 90
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Arrivals {
+    #[default]
+    Uniform, // Gaps drawn evenly between cafe.min_arrival_ms and cafe.max_arrival_ms
+    Poisson {
+        per_minute: f64,
+    },
+    TimeOfDay(DayProfile),
+    Bursty(Groups),
+    Trace(TraceFile), // Replays the arrival times recorded by a point-of-sale system
+}
+
+// Arrival rate changing over the day, such as a morning rush and a lunch peak
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DayProfile {
+    pub opens_at_hour: f64, // Time of day the run starts at, e.g. 7.5 for half past seven
+    pub hour_secs: f64,     // Simulated seconds that stand for one hour of the day
+    pub periods: Vec<Period>,
+}
+
+// Customers keep arriving at this rate from `from_hour` until the next period starts
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Period {
+    pub from_hour: f64,
+    pub per_minute: f64,
+}
+
+// Customers arriving together in groups, the groups themselves at random
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Groups {
+    pub groups_per_minute: f64,
+    pub min_group: usize,
+    pub max_group: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TraceFile {
+    pub path: PathBuf, // CSV whose first column holds one timestamp per customer
+    #[serde(default = "TraceFile::default_speedup")]
+    pub speedup: f64, // Replay the trace this many times faster than it was recorded
+}
+
+impl Default for DayProfile {
+    fn default() -> Self {
+        let period = |from_hour, per_minute| Period {
+            from_hour,
+            per_minute,
+        };
+        DayProfile {
+            opens_at_hour: 7.0,
+            hour_secs: 10.0,
+            periods: vec![
+                period(7.0, 40.0),
+                period(8.0, 80.0), // Morning rush
+                period(10.0, 30.0),
+                period(12.0, 70.0), // Lunch
+                period(14.0, 20.0),
+            ],
+        }
+    }
+}
+
+impl Default for Groups {
+    fn default() -> Self {
+        Groups {
+            groups_per_minute: 20.0,
+            min_group: 2,
+            max_group: 5,
+        }
+    }
+}
+
+impl TraceFile {
+    fn default_speedup() -> f64 {
+        1.0
+    }
+}
+
+impl Arrivals {
+    // Paths in a scenario file are taken from where the scenario lives
+    pub fn relative_to(&mut self, dir: &Path) {
+        if let Arrivals::Trace(trace) = self {
+            if trace.path.is_relative() {
+                trace.path = dir.join(&trace.path);
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let positive = |value: f64| value > 0.0 && value.is_finite();
+        match self {
+            Arrivals::Uniform => {}
+            Arrivals::Poisson { per_minute } => {
+                if !positive(*per_minute) {
+                    bail!("cafe.arrivals.per_minute must be a positive number");
+                }
+            }
+            Arrivals::TimeOfDay(profile) => {
+                if !profile.opens_at_hour.is_finite() {
+                    bail!("cafe.arrivals.opens_at_hour must be a number");
+                }
+                if !positive(profile.hour_secs) {
+                    bail!("cafe.arrivals.hour_secs must be a positive number");
+                }
+                if profile.periods.iter().any(|p| {
+                    !(p.from_hour.is_finite() && p.per_minute >= 0.0 && p.per_minute.is_finite())
+                }) {
+                    bail!("cafe.arrivals.periods need an hour and a non-negative rate each");
+                }
+                if !profile.periods.iter().any(|p| p.per_minute > 0.0) {
+                    bail!("cafe.arrivals.periods need at least one positive rate");
+                }
+            }
+            Arrivals::Bursty(groups) => {
+                if !positive(groups.groups_per_minute) {
+                    bail!("cafe.arrivals.groups_per_minute must be a positive number");
+                }
+                if groups.min_group == 0 || groups.min_group > groups.max_group {
+                    bail!("cafe.arrivals.min_group must be at least 1 and at most max_group");
+                }
+            }
+            Arrivals::Trace(trace) => {
+                if !positive(trace.speedup) {
+                    bail!("cafe.arrivals.speedup must be a positive number");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Decides when the next customer comes in; built afresh for every run
+pub struct ArrivalProcess {
+    model: Model,
+    next_at: Option<Duration>, // When the next customer comes in; None once nobody else will
+    closes_at: Duration,       // No arrivals are drawn beyond the end of the opening hours
+}
+
+// How far an arrival process has got, carried over in a snapshot. Random draws are not part of
+// it; a resumed run draws them afresh from its own seed.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    next_at_secs: Option<f64>,
+    left_in_group: Option<usize>, // Bursty: members of the current group still to come in
+    trace_next: usize,            // Trace: index of the next arrival time
+}
+
+enum Model {
+    Uniform(Range<u64>),
+    Poisson(f64), // Customers per second
+    TimeOfDay(DayProfile),
+    Bursty {
+        groups: Groups,
+        left_in_group: Option<usize>, // Members of the current group still to come in
+    },
+    Trace {
+        times: Vec<Duration>, // Arrival times from the start of the run
+        next: usize,
+    },
+}
+
+impl ArrivalProcess {
+    // `uniform_ms` holds the gaps of the uniform model; the first customer comes in at `now`
+    pub fn new(
+        arrivals: &Arrivals,
+        uniform_ms: Range<u64>,
+        now: Duration,
+        closes_at: Duration,
+    ) -> Result<Self> {
+        let model = match arrivals {
+            Arrivals::Uniform => Model::Uniform(uniform_ms),
+            Arrivals::Poisson { per_minute } => Model::Poisson(per_minute / 60.0),
+            Arrivals::TimeOfDay(profile) => {
+                let mut profile = profile.clone();
+                profile
+                    .periods
+                    .sort_by(|a, b| a.from_hour.total_cmp(&b.from_hour));
+                Model::TimeOfDay(profile)
+            }
+            Arrivals::Bursty(groups) => Model::Bursty {
+                groups: groups.clone(),
+                left_in_group: None,
+            },
+            Arrivals::Trace(trace) => {
+                // The first customer comes in as the run starts, as with every other model
+                Model::Trace {
+                    times: load_trace(&trace.path, trace.speedup)?,
+                    next: 1,
+                }
+            }
+        };
+        Ok(ArrivalProcess {
+            model,
+            next_at: Some(now),
+            closes_at,
+        })
+    }
+
+    pub fn next_at(&self) -> Option<Duration> {
+        self.next_at
+    }
+
+    // Time until the customer after the one arriving `now`; None once nobody else will come
+    // before closing time
+    pub fn next_gap(&mut self, now: Duration, rng: &mut impl Rng) -> Option<Duration> {
+        let gap = self
+            .draw_gap(now, rng)
+            .filter(|gap| now.checked_add(*gap).is_some_and(|at| at < self.closes_at));
+        self.next_at = gap.map(|gap| now + gap);
+        gap
+    }
+
+    pub fn progress(&self) -> Progress {
+        let mut progress = Progress {
+            next_at_secs: self.next_at.map(|at| at.as_secs_f64()),
+            ..Progress::default()
+        };
+        match &self.model {
+            Model::Bursty { left_in_group, .. } => progress.left_in_group = *left_in_group,
+            Model::Trace { next, .. } => progress.trace_next = *next,
+            _ => {}
+        }
+        progress
+    }
+
+    // Carry on from where a snapshotted run's arrivals had got to
+    pub fn resume(&mut self, progress: &Progress) {
+        self.next_at = progress.next_at_secs.map(Duration::from_secs_f64);
+        match &mut self.model {
+            Model::Bursty { left_in_group, .. } => *left_in_group = progress.left_in_group,
+            Model::Trace { next, .. } => *next = progress.trace_next,
+            _ => {}
+        }
+    }
+
+    fn draw_gap(&mut self, now: Duration, rng: &mut impl Rng) -> Option<Duration> {
+        let closes_at = self.closes_at;
+        match &mut self.model {
+            Model::Uniform(gap_ms) => Some(Duration::from_millis(rng.gen_range(gap_ms.clone()))),
+            Model::Poisson(rate) => exponential(*rate, rng),
+            Model::TimeOfDay(profile) => {
+                // Thinning: propose at the busiest rate and keep each proposal in proportion to
+                // the rate at its time of day
+                let peak = profile
+                    .periods
+                    .iter()
+                    .map(|period| period.per_minute)
+                    .fold(0.0, f64::max)
+                    / 60.0;
+                let mut at = now;
+                loop {
+                    at = at.checked_add(exponential(peak, rng)?)?;
+                    if at >= closes_at {
+                        return None;
+                    }
+                    if rng.gen::<f64>() * peak < profile.rate_at(at) {
+                        return Some(at - now);
+                    }
+                }
+            }
+            Model::Bursty {
+                groups,
+                left_in_group,
+            } => {
+                let mut group_size = || rng.gen_range(groups.min_group..=groups.max_group);
+                // The customer opening the run comes with a group too
+                let left = left_in_group.get_or_insert_with(|| group_size() - 1);
+                if *left > 0 {
+                    *left -= 1;
+                    return Some(Duration::ZERO);
+                }
+                *left = group_size() - 1;
+                exponential(groups.groups_per_minute / 60.0, rng)
+            }
+            Model::Trace { times, next } => {
+                let at = *times.get(*next)?;
+                *next += 1;
+                Some(at.saturating_sub(now))
+            }
+        }
+    }
+}
+
+impl DayProfile {
+    // Customers per second at a time into the run
+    fn rate_at(&self, at: Duration) -> f64 {
+        let hour = self.opens_at_hour + at.as_secs_f64() / self.hour_secs;
+        self.periods
+            .iter()
+            .take_while(|period| period.from_hour <= hour)
+            .last()
+            .map_or(0.0, |period| period.per_minute / 60.0)
+    }
+}
+
+// Time to the next event of a Poisson process with `rate` events per second; None if it is too
+// far off to represent, as happens with vanishingly small rates
+fn exponential(rate: f64, rng: &mut impl Rng) -> Option<Duration> {
+    let u: f64 = rng.gen();
+    Duration::try_from_secs_f64(-(1.0 - u).ln() / rate).ok()
+}
+
+// Arrival times of a trace, from its first customer on and scaled by `speedup`. A first line
+// that is not a timestamp is taken as the header.
+fn load_trace(path: &Path, speedup: f64) -> Result<Vec<Duration>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read arrival trace {}", path.display()))?;
+    let mut secs = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let field = line.split(',').next().unwrap_or_default();
+        let field = field.trim().trim_matches('"');
+        if field.is_empty() {
+            continue;
+        }
+        match parse_timestamp(field) {
+            Some(time) => secs.push(time),
+            None if i == 0 => {}
+            None => bail!(
+                "Invalid timestamp '{}' on line {} of {}",
+                field,
+                i + 1,
+                path.display()
+            ),
+        }
+    }
+    if secs.is_empty() {
+        bail!("Arrival trace {} has no timestamps", path.display());
+    }
+    secs.sort_by(f64::total_cmp);
+    let first = secs[0];
+    secs.into_iter()
+        .map(|time| {
+            Duration::try_from_secs_f64((time - first) / speedup).with_context(|| {
+                format!(
+                    "Arrival trace {} spans too long a time at a speedup of {}",
+                    path.display(),
+                    speedup
+                )
+            })
+        })
+        .collect()
+}
+
+// Seconds since some fixed point, from plain seconds, RFC 3339, "2024-05-01 07:30:00" or "07:30:00"
+fn parse_timestamp(field: &str) -> Option<f64> {
+    if let Ok(secs) = field.parse::<f64>() {
+        return secs.is_finite().then_some(secs);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(field) {
+        return Some(time.timestamp_millis() as f64 / 1000.0);
+    }
+    if let Ok(time) = NaiveDateTime::parse_from_str(field, "%Y-%m-%d %H:%M:%S%.f") {
+        return Some(time.and_utc().timestamp_millis() as f64 / 1000.0);
+    }
+    NaiveTime::parse_from_str(field, "%H:%M:%S%.f")
+        .ok()
+        .map(|time| time.num_seconds_from_midnight() as f64 + time.nanosecond() as f64 / 1e9)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const CLOSES_AT: Duration = Duration::from_secs(1_000_000);
+
+    fn process(arrivals: Arrivals) -> ArrivalProcess {
+        ArrivalProcess::new(&arrivals, 500..1000, Duration::ZERO, CLOSES_AT).unwrap()
+    }
+
+    // Gaps drawn one after another, each from the arrival before it
+    fn gaps(process: &mut ArrivalProcess, count: usize) -> Vec<Duration> {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut now = Duration::ZERO;
+        (0..count)
+            .map_while(|_| {
+                let gap = process.next_gap(now, &mut rng)?;
+                now += gap;
+                Some(gap)
+            })
+            .collect()
+    }
+
+    // A file in the temp directory, removed when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+            fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn trace(file: &TempFile, speedup: f64) -> Arrivals {
+        Arrivals::Trace(TraceFile {
+            path: file.0.clone(),
+            speedup,
+        })
+    }
+
+    #[test]
+    fn uniform_gaps_stay_in_range() {
+        let gaps = gaps(&mut process(Arrivals::Uniform), 1000);
+        assert_eq!(gaps.len(), 1000);
+        assert!(gaps
+            .iter()
+            .all(|gap| (500..1000).contains(&gap.as_millis())));
+    }
+
+    #[test]
+    fn poisson_gaps_average_one_over_the_rate() {
+        let gaps = gaps(&mut process(Arrivals::Poisson { per_minute: 60.0 }), 20_000);
+        let mean = gaps.iter().map(Duration::as_secs_f64).sum::<f64>() / gaps.len() as f64;
+        assert!((0.95..1.05).contains(&mean), "mean gap {}", mean);
+    }
+
+    #[test]
+    fn tiny_rates_end_arrivals_instead_of_overflowing() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut poisson = process(Arrivals::Poisson { per_minute: 1e-300 });
+        assert_eq!(poisson.next_gap(Duration::ZERO, &mut rng), None);
+
+        let mut bursty = process(Arrivals::Bursty(Groups {
+            groups_per_minute: 1e-300,
+            min_group: 1,
+            max_group: 1,
+        }));
+        assert_eq!(bursty.next_gap(Duration::ZERO, &mut rng), None);
+
+        let mut day = process(Arrivals::TimeOfDay(DayProfile {
+            opens_at_hour: 0.0,
+            hour_secs: 10.0,
+            periods: vec![Period {
+                from_hour: 0.0,
+                per_minute: 1e-300,
+            }],
+        }));
+        assert_eq!(day.next_gap(Duration::ZERO, &mut rng), None);
+    }
+
+    #[test]
+    fn nobody_arrives_after_closing_time() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let arrivals = Arrivals::Poisson { per_minute: 60.0 };
+        let mut process =
+            ArrivalProcess::new(&arrivals, 1..2, Duration::ZERO, Duration::from_secs(5)).unwrap();
+        let mut now = Duration::ZERO;
+        while let Some(gap) = process.next_gap(now, &mut rng) {
+            now += gap;
+            assert!(now < Duration::from_secs(5));
+        }
+    }
+
+    #[test]
+    fn time_of_day_follows_the_periods() {
+        // Closed for the first hour, then busy until the third, then closed again
+        let period = |from_hour, per_minute| Period {
+            from_hour,
+            per_minute,
+        };
+        let profile = DayProfile {
+            opens_at_hour: 6.0,
+            hour_secs: 10.0,
+            periods: vec![period(8.0, 0.0), period(7.0, 600.0), period(6.0, 0.0)],
+        };
+        let arrivals = Arrivals::TimeOfDay(profile);
+        let mut process =
+            ArrivalProcess::new(&arrivals, 1..2, Duration::ZERO, Duration::from_secs(100)).unwrap();
+        let mut now = Duration::ZERO;
+        for gap in gaps(&mut process, 1000) {
+            now += gap;
+            assert!(
+                (10.0..20.0).contains(&now.as_secs_f64()),
+                "arrival at {:?}",
+                now
+            );
+        }
+        assert!(
+            now > Duration::from_secs(19),
+            "arrivals stopped at {:?}",
+            now
+        );
+    }
+
+    #[test]
+    fn bursty_customers_come_in_whole_groups() {
+        let groups = Groups {
+            groups_per_minute: 10.0,
+            min_group: 3,
+            max_group: 3,
+        };
+        let gaps = gaps(&mut process(Arrivals::Bursty(groups)), 9);
+        let together: Vec<bool> = gaps.iter().map(|gap| gap.is_zero()).collect();
+        // The first customer opens a group of three, and so does every later gap
+        assert_eq!(
+            together,
+            [true, true, false, true, true, false, true, true, false]
+        );
+    }
+
+    #[test]
+    fn trace_skips_the_header_and_applies_the_speedup() {
+        let file = TempFile::new(
+            "trace.csv",
+            "timestamp,register\n\
+             2024-05-06 07:30:02,1\n\
+             2024-05-06 07:30:06,2\n\
+             \n\
+             \"2024-05-06 07:30:12\",1\n",
+        );
+        let mut process = process(trace(&file, 2.0));
+        assert_eq!(
+            gaps(&mut process, 10),
+            [Duration::from_secs(2), Duration::from_secs(3)]
+        );
+    }
+
+    #[test]
+    fn trace_accepts_plain_seconds_and_times_of_day() {
+        let file = TempFile::new("mixed.csv", "07:00:00\n07:00:01.5\n");
+        assert_eq!(
+            gaps(&mut process(trace(&file, 1.0)), 10),
+            [Duration::from_millis(1500)]
+        );
+
+        let file = TempFile::new("secs.csv", "10\n4\n7\n");
+        assert_eq!(
+            gaps(&mut process(trace(&file, 1.0)), 10),
+            [Duration::from_secs(3), Duration::from_secs(3)]
+        );
+    }
+
+    #[test]
+    fn resumed_processes_carry_on_where_they_left_off() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let file = TempFile::new("resume.csv", "0\n1\n2.5\n3\n");
+        let mut original = process(trace(&file, 1.0));
+        original.next_gap(Duration::ZERO, &mut rng);
+        original.next_gap(Duration::from_secs(1), &mut rng);
+        let progress = original.progress();
+
+        let mut resumed = process(trace(&file, 1.0));
+        resumed.resume(&progress);
+        assert_eq!(resumed.next_at(), Some(Duration::from_millis(2500)));
+        assert_eq!(
+            resumed.next_gap(Duration::from_millis(2500), &mut rng),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(resumed.next_gap(Duration::from_secs(3), &mut rng), None);
+        assert_eq!(resumed.next_at(), None);
+
+        // The rest of a group still comes in together
+        let groups = Arrivals::Bursty(Groups {
+            groups_per_minute: 10.0,
+            min_group: 3,
+            max_group: 3,
+        });
+        let mut original = process(groups.clone());
+        original.next_gap(Duration::ZERO, &mut rng);
+        let mut resumed = process(groups);
+        resumed.resume(&original.progress());
+        assert_eq!(resumed.next_at(), Some(Duration::ZERO));
+        assert_eq!(
+            resumed.next_gap(Duration::ZERO, &mut rng),
+            Some(Duration::ZERO)
+        );
+        assert!(resumed.next_gap(Duration::ZERO, &mut rng).unwrap() > Duration::ZERO);
+    }
+
+    #[test]
+    fn bad_traces_are_rejected() {
+        let file = TempFile::new("bad.csv", "when\n1\nsoon\n");
+        let error = ArrivalProcess::new(&trace(&file, 1.0), 1..2, Duration::ZERO, CLOSES_AT)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("'soon' on line 3"), "{}", error);
+
+        let file = TempFile::new("empty.csv", "when\n");
+        assert!(ArrivalProcess::new(&trace(&file, 1.0), 1..2, Duration::ZERO, CLOSES_AT).is_err());
+
+        let file = TempFile::new("long.csv", "0\n1000\n");
+        assert!(
+            ArrivalProcess::new(&trace(&file, 1e-300), 1..2, Duration::ZERO, CLOSES_AT).is_err()
+        );
+    }
+
+    #[test]
+    fn validation_rejects_unusable_models() {
+        assert!(Arrivals::Poisson { per_minute: 0.0 }.validate().is_err());
+        assert!(Arrivals::Bursty(Groups {
+            min_group: 4,
+            max_group: 2,
+            ..Groups::default()
+        })
+        .validate()
+        .is_err());
+        assert!(Arrivals::TimeOfDay(DayProfile {
+            periods: Vec::new(),
+            ..DayProfile::default()
+        })
+        .validate()
+        .is_err());
+        assert!(Arrivals::TimeOfDay(DayProfile::default())
+            .validate()
+            .is_ok());
+    }
+}
@@ -0,0 +1,444 @@
+use super::menu::Ingredients;
+use super::Order;
+use crate::clock::Clock;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+// In which order finished orders are handed to the customers waiting at the counter
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PickupOrder {
+    #[default]
+    Ticket, // Nobody gets their order before everyone who ordered earlier
+    Ready, // Each order is handed out as soon as it is finished
+}
+
+// Where baristas leave finished orders and customers wait to collect them. Baristas never wait
+// here; customers block on the clock until their order may be handed out or they give up.
+pub(super) struct Counter {
+    pickup_order: PickupOrder,
+    clock: Clock,
+    pub items_made: AtomicUsize,
+    pub used: Mutex<Ingredients>, // Stock that has gone into the items made so far
+    pub sales: Mutex<Sales>,
+    pub outstanding: Mutex<BTreeMap<usize, Order>>, // Orders placed but not yet settled, by ticket
+    pub max_queue: AtomicUsize, // Most orders ever waiting for a barista at once
+    shelf: Mutex<Shelf>,
+}
+
+// An order is settled once it has been served or cancelled
+struct Shelf {
+    finished: BTreeMap<usize, Finished>, // By ticket
+    in_progress: BTreeSet<usize>,        // Tickets a barista is working on
+    cancelled: BTreeSet<usize>,          // Tickets whose customer gave up on them
+    served: usize,
+    next_ticket: usize, // Lowest ticket not yet settled; going by ticket, the next to hand out
+    settled_early: BTreeSet<usize>, // Settled tickets above `next_ticket`
+    waits: Vec<Waits>,  // Per order served
+    gave_up_after: Vec<f64>, // Seconds from ordering to giving up, per order cancelled
+    dropped_brew_secs: f64, // Time baristas spent on orders that were then cancelled
+}
+
+// A finished order waiting on the counter
+struct Finished {
+    barista_id: usize,
+    started_at: Duration, // When the barista took the order
+    ready_at: Duration,
+}
+
+// Seconds an order spent at each stage, from ordering to being collected
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub(super) struct Waits {
+    pub queue: f64,  // Waiting for a barista to take it
+    pub brew: f64,   // Being made
+    pub pickup: f64, // Sitting on the counter
+}
+
+// What was sold, and what was lost to customers leaving
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub(super) struct Sales {
+    pub sold: BTreeMap<String, u32>, // Items handed to customers, by name
+    pub lost: BTreeMap<String, u32>, // Items customers left without, by name
+    pub balked: usize,               // Customers who left without ordering
+    pub reneged: usize,              // Customers who gave up waiting and cancelled their order
+}
+
+// Pickup progress carried over in a snapshot
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub(super) struct Pickups {
+    pub served: usize,
+    pub next_ticket: usize,
+    pub settled_early: BTreeSet<usize>,
+    pub waits: Vec<Waits>,
+    pub gave_up_after: Vec<f64>,
+    pub dropped_brew_secs: f64,
+    pub max_queue: usize,
+}
+
+impl Waits {
+    pub fn total(&self) -> f64 {
+        self.queue + self.brew + self.pickup
+    }
+}
+
+impl Sales {
+    fn add(counts: &mut BTreeMap<String, u32>, items: &[String]) {
+        for item in items {
+            *counts.entry(item.clone()).or_default() += 1;
+        }
+    }
+}
+
+impl Shelf {
+    fn settle(&mut self, ticket: usize) {
+        self.settled_early.insert(ticket);
+        while self.settled_early.remove(&self.next_ticket) {
+            self.next_ticket += 1;
+        }
+    }
+}
+
+fn secs_between(from: Duration, to: Duration) -> f64 {
+    to.saturating_sub(from).as_secs_f64()
+}
+
+impl Counter {
+    pub fn new(pickup_order: PickupOrder, clock: Clock) -> Self {
+        Counter {
+            pickup_order,
+            clock,
+            items_made: AtomicUsize::new(0),
+            used: Mutex::new(Ingredients::default()),
+            sales: Mutex::new(Sales::default()),
+            outstanding: Mutex::new(BTreeMap::new()),
+            max_queue: AtomicUsize::new(0),
+            shelf: Mutex::new(Shelf {
+                finished: BTreeMap::new(),
+                in_progress: BTreeSet::new(),
+                cancelled: BTreeSet::new(),
+                served: 0,
+                next_ticket: 1,
+                settled_early: BTreeSet::new(),
+                waits: Vec::new(),
+                gave_up_after: Vec::new(),
+                dropped_brew_secs: 0.0,
+            }),
+        }
+    }
+
+    // A customer who left without ordering
+    pub fn balk(&self, items: &[String]) {
+        let mut sales = self.sales.lock();
+        sales.balked += 1;
+        Sales::add(&mut sales.lost, items);
+    }
+
+    // A barista taking an order; false if its customer has already left
+    pub fn start(&self, ticket: usize) -> bool {
+        let mut shelf = self.shelf.lock();
+        if shelf.cancelled.contains(&ticket) {
+            return false;
+        }
+        shelf.in_progress.insert(ticket);
+        true
+    }
+
+    pub fn is_cancelled(&self, ticket: usize) -> bool {
+        self.shelf.lock().cancelled.contains(&ticket)
+    }
+
+    // Leave a finished order, taken by the barista at `started_at`, for its customer; false if
+    // the customer has left in the meantime and the order is thrown away
+    pub fn place(&self, ticket: usize, barista_id: usize, started_at: Duration) -> bool {
+        let now = self.clock.now();
+        let mut shelf = self.shelf.lock();
+        shelf.in_progress.remove(&ticket);
+        if shelf.cancelled.contains(&ticket) {
+            shelf.dropped_brew_secs += secs_between(started_at, now);
+            return false;
+        }
+        shelf.finished.insert(
+            ticket,
+            Finished {
+                barista_id,
+                started_at,
+                ready_at: now,
+            },
+        );
+        drop(shelf);
+        self.clock.notify();
+        true
+    }
+
+    // Wait until the order can be handed to its customer, then give it to `hand_over` along with
+    // who made it. Going by ticket, the next order waits until this one has been settled.
+    // Returns false, leaving the order as it is, if the customer runs out of patience first.
+    pub fn collect(&self, order: &Order, hand_over: impl FnOnce(usize)) -> bool {
+        let ticket = order.ticket_number;
+        let patience = order
+            .gives_up_at
+            .map(|at| at.saturating_sub(self.clock.now()));
+        let ready = self.clock.wait_until(patience, || {
+            let shelf = self.shelf.lock();
+            shelf.finished.contains_key(&ticket)
+                && (self.pickup_order == PickupOrder::Ready || shelf.next_ticket == ticket)
+        });
+        if !ready {
+            return false;
+        }
+
+        let now = self.clock.now();
+        let finished = self
+            .shelf
+            .lock()
+            .finished
+            .remove(&ticket)
+            .expect("order checked to be on the counter");
+        hand_over(finished.barista_id);
+
+        // Served under the outstanding lock, so a snapshot never sees an order half handed out
+        let mut outstanding = self.outstanding.lock();
+        Sales::add(&mut self.sales.lock().sold, &order.items);
+        let mut shelf = self.shelf.lock();
+        shelf.served += 1;
+        shelf.settle(ticket);
+        shelf.waits.push(Waits {
+            queue: secs_between(order.placed_at, finished.started_at),
+            brew: secs_between(finished.started_at, finished.ready_at),
+            pickup: secs_between(finished.ready_at, now),
+        });
+        outstanding.remove(&ticket);
+        drop(shelf);
+        drop(outstanding);
+        self.clock.notify();
+        true
+    }
+
+    // Call off an order whose customer has left, telling `announce` whether it was still
+    // "queued", "brewing" or "on_counter". Baristas drop it as soon as they notice.
+    pub fn cancel(&self, order: &Order, announce: impl FnOnce(&'static str)) {
+        let ticket = order.ticket_number;
+        let was = {
+            let mut shelf = self.shelf.lock();
+            shelf.cancelled.insert(ticket);
+            if let Some(finished) = shelf.finished.remove(&ticket) {
+                shelf.dropped_brew_secs += secs_between(finished.started_at, finished.ready_at);
+                "on_counter"
+            } else if shelf.in_progress.contains(&ticket) {
+                "brewing"
+            } else {
+                "queued"
+            }
+        };
+        announce(was);
+
+        let mut outstanding = self.outstanding.lock();
+        let now = self.clock.now();
+        let mut sales = self.sales.lock();
+        sales.reneged += 1;
+        Sales::add(&mut sales.lost, &order.items);
+        drop(sales);
+        let mut shelf = self.shelf.lock();
+        shelf.settle(ticket);
+        shelf.gave_up_after.push(secs_between(order.placed_at, now));
+        outstanding.remove(&ticket);
+        drop(shelf);
+        drop(outstanding);
+        self.clock.notify();
+    }
+
+    pub fn served(&self) -> usize {
+        self.shelf.lock().served
+    }
+
+    // Lowest ticket not yet served or cancelled; one past the last ticket once all are settled
+    pub fn first_open_ticket(&self) -> usize {
+        self.shelf.lock().next_ticket
+    }
+
+    // Finished orders waiting to be collected
+    pub fn on_shelf(&self) -> usize {
+        self.shelf.lock().finished.len()
+    }
+
+    pub fn waits(&self) -> Vec<Waits> {
+        self.shelf.lock().waits.clone()
+    }
+
+    pub fn gave_up_after(&self) -> Vec<f64> {
+        self.shelf.lock().gave_up_after.clone()
+    }
+
+    pub fn dropped_brew_secs(&self) -> f64 {
+        self.shelf.lock().dropped_brew_secs
+    }
+
+    pub fn pickups(&self) -> Pickups {
+        let shelf = self.shelf.lock();
+        Pickups {
+            served: shelf.served,
+            next_ticket: shelf.next_ticket,
+            settled_early: shelf.settled_early.clone(),
+            waits: shelf.waits.clone(),
+            gave_up_after: shelf.gave_up_after.clone(),
+            dropped_brew_secs: shelf.dropped_brew_secs,
+            max_queue: self.max_queue.load(Ordering::SeqCst),
+        }
+    }
+
+    // Orders left on the counter are made again, so only the progress is restored
+    pub fn resume(&self, pickups: Pickups) {
+        let mut shelf = self.shelf.lock();
+        shelf.served = pickups.served;
+        shelf.next_ticket = pickups.next_ticket;
+        shelf.settled_early = pickups.settled_early;
+        shelf.waits = pickups.waits;
+        shelf.gave_up_after = pickups.gave_up_after;
+        shelf.dropped_brew_secs = pickups.dropped_brew_secs;
+        self.max_queue.store(pickups.max_queue, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ClockMode;
+    use std::sync::Arc;
+
+    fn order(ticket: usize, item: &str) -> Order {
+        Order {
+            customer_id: ticket,
+            order_details: format!("ORDER{}", ticket),
+            items: vec![item.to_string()],
+            ticket_number: ticket,
+            placed_at: Duration::ZERO,
+            gives_up_at: Some(Duration::from_secs(5)),
+        }
+    }
+
+    // A counter holding the given orders, each already placed and waiting
+    fn counter(pickup_order: PickupOrder, orders: &[Order]) -> Counter {
+        let counter = Counter::new(pickup_order, Clock::new(ClockMode::Virtual));
+        let mut outstanding = counter.outstanding.lock();
+        for order in orders {
+            outstanding.insert(order.ticket_number, order.clone());
+        }
+        drop(outstanding);
+        counter
+    }
+
+    #[test]
+    fn by_ticket_an_order_waits_for_every_earlier_one() {
+        let (first, second) = (order(1, "latte"), order(2, "tea"));
+        let counter = counter(PickupOrder::Ticket, &[first.clone(), second.clone()]);
+        assert!(counter.start(2));
+        assert!(counter.place(2, 7, Duration::ZERO));
+
+        // Ticket 1 is still open, so the second customer runs out of patience
+        assert!(!counter.collect(&second, |_| panic!("handed out out of turn")));
+        assert_eq!(counter.clock.now(), Duration::from_secs(5));
+        assert_eq!(counter.on_shelf(), 1);
+
+        // Once the first order is settled the second can be handed over
+        counter.cancel(&first, |was| assert_eq!(was, "queued"));
+        assert_eq!(counter.first_open_ticket(), 2);
+        let second = Order {
+            gives_up_at: None,
+            ..second
+        };
+        let mut made_by = None;
+        assert!(counter.collect(&second, |barista_id| made_by = Some(barista_id)));
+        assert_eq!(made_by, Some(7));
+        assert_eq!(counter.first_open_ticket(), 3);
+        assert_eq!(counter.served(), 1);
+    }
+
+    #[test]
+    fn when_ready_an_order_is_handed_out_straight_away() {
+        let (first, second) = (order(1, "latte"), order(2, "tea"));
+        let counter = counter(PickupOrder::Ready, &[first, second.clone()]);
+        assert!(counter.start(2));
+        assert!(counter.place(2, 1, Duration::ZERO));
+        assert!(counter.collect(&second, |_| {}));
+        assert_eq!(counter.clock.now(), Duration::ZERO);
+        // Ticket 1 is still open, so ticket 2 is settled early
+        assert_eq!(counter.first_open_ticket(), 1);
+        assert_eq!(counter.pickups().settled_early, BTreeSet::from([2]));
+        assert_eq!(counter.sales.lock().sold.get("tea"), Some(&1));
+    }
+
+    #[test]
+    fn cancelling_settles_the_order_and_counts_what_was_lost() {
+        let orders = [order(1, "latte"), order(2, "tea"), order(3, "pastry")];
+        let counter = counter(PickupOrder::Ticket, &orders);
+        assert!(counter.start(1));
+        assert!(counter.start(2));
+        assert!(counter.place(2, 1, Duration::ZERO));
+
+        counter.cancel(&orders[0], |was| assert_eq!(was, "brewing"));
+        counter.cancel(&orders[1], |was| assert_eq!(was, "on_counter"));
+        counter.cancel(&orders[2], |was| assert_eq!(was, "queued"));
+
+        // Baristas find out when they try to take or finish a cancelled order
+        assert!(!counter.place(1, 1, Duration::ZERO));
+        assert!(!counter.start(3));
+        assert!(counter.is_cancelled(3));
+
+        assert_eq!(counter.first_open_ticket(), 4);
+        assert_eq!(counter.served(), 0);
+        assert_eq!(counter.on_shelf(), 0);
+        assert!(counter.outstanding.lock().is_empty());
+        assert_eq!(counter.gave_up_after().len(), 3);
+        let sales = counter.sales.lock();
+        assert_eq!(sales.reneged, 3);
+        assert_eq!(sales.lost.values().sum::<u32>(), 3);
+        assert!(sales.sold.is_empty());
+    }
+
+    // Orders 1 to 3 are finished one a second in the order given, while each customer waits at
+    // the counter on their own thread; returns the tickets in the order they were handed out
+    fn handed_out(pickup_order: PickupOrder, finished: [usize; 3]) -> Vec<usize> {
+        let clock = Clock::new(ClockMode::Virtual);
+        let counter = Arc::new(Counter::new(pickup_order, clock.clone()));
+        let handed = Arc::new(Mutex::new(Vec::new()));
+        let customers: Vec<_> = (1..=3)
+            .map(|ticket| {
+                let order = Order {
+                    gives_up_at: None,
+                    ..order(ticket, "latte")
+                };
+                counter.outstanding.lock().insert(ticket, order.clone());
+                let (counter, handed) = (counter.clone(), handed.clone());
+                clock.spawn(move || counter.collect(&order, |_| handed.lock().push(ticket)))
+            })
+            .collect();
+
+        for ticket in finished {
+            clock.sleep(Duration::from_secs(1));
+            assert!(counter.start(ticket));
+            assert!(counter.place(ticket, 1, Duration::ZERO));
+        }
+        // Joining does not wait in the clock, so the customers are given a moment to collect
+        clock.sleep(Duration::from_secs(1));
+        for customer in customers {
+            assert!(customer.join().unwrap());
+        }
+        assert_eq!(counter.served(), 3);
+        assert_eq!(counter.on_shelf(), 0);
+        let handed = handed.lock().clone();
+        handed
+    }
+
+    #[test]
+    fn by_ticket_orders_are_handed_out_in_turn() {
+        assert_eq!(handed_out(PickupOrder::Ticket, [3, 1, 2]), [1, 2, 3]);
+    }
+
+    #[test]
+    fn when_ready_orders_are_handed_out_as_they_are_finished() {
+        assert_eq!(handed_out(PickupOrder::Ready, [3, 1, 2]), [3, 1, 2]);
+    }
+}
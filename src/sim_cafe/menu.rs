@@ -0,0 +1,232 @@
+use anyhow::{bail, Result};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::ops::AddAssign;
+use std::time::Duration;
+
+// Something customers can order, with how it is made and what goes into it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MenuItem {
+    pub name: String,
+    pub weight: f64, // How often customers pick it, relative to the other items
+    #[serde(default)]
+    pub price: f64,
+    pub steps: Vec<RecipeStep>,
+    #[serde(default)]
+    pub ingredients: Ingredients,
+}
+
+// One step of a recipe, e.g. pulling a shot on a coffee machine
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecipeStep {
+    pub action: String,
+    pub secs: f64,
+    #[serde(default)]
+    pub machine: bool, // Needs one of the coffee machines for the duration of the step
+}
+
+// Stock used by one item, or in total
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ingredients {
+    pub beans_g: u32,
+    pub milk_ml: u32,
+    pub water_ml: u32,
+    pub ice_g: u32,
+    pub tea_g: u32,
+    pub pastries: u32,
+}
+
+impl AddAssign for Ingredients {
+    fn add_assign(&mut self, other: Ingredients) {
+        self.beans_g += other.beans_g;
+        self.milk_ml += other.milk_ml;
+        self.water_ml += other.water_ml;
+        self.ice_g += other.ice_g;
+        self.tea_g += other.tea_g;
+        self.pastries += other.pastries;
+    }
+}
+
+impl MenuItem {
+    fn new(
+        name: &str,
+        weight: f64,
+        price: f64,
+        steps: &[(&str, f64, bool)],
+        ingredients: Ingredients,
+    ) -> Self {
+        MenuItem {
+            name: name.to_string(),
+            weight,
+            price,
+            steps: steps
+                .iter()
+                .map(|&(action, secs, machine)| RecipeStep {
+                    action: action.to_string(),
+                    secs,
+                    machine,
+                })
+                .collect(),
+            ingredients,
+        }
+    }
+
+    // Time to make the item from start to finish
+    pub fn prep_time(&self) -> Duration {
+        Duration::from_secs_f64(self.steps.iter().map(|step| step.secs).sum())
+    }
+}
+
+// What the cafe serves unless the configuration says otherwise
+pub fn default_menu() -> Vec<MenuItem> {
+    let coffee = Ingredients {
+        beans_g: 18,
+        water_ml: 30,
+        ..Ingredients::default()
+    };
+    vec![
+        MenuItem::new(
+            "espresso",
+            3.0,
+            2.5,
+            &[("Grind beans", 0.5, false), ("Pull shot", 1.5, true)],
+            coffee,
+        ),
+        MenuItem::new(
+            "latte",
+            4.0,
+            3.8,
+            &[
+                ("Grind beans", 0.5, false),
+                ("Pull shot", 1.5, true),
+                ("Steam milk", 1.5, true),
+                ("Pour", 0.5, false),
+            ],
+            Ingredients {
+                milk_ml: 200,
+                ..coffee
+            },
+        ),
+        MenuItem::new(
+            "cappuccino",
+            3.0,
+            3.6,
+            &[
+                ("Grind beans", 0.5, false),
+                ("Pull shot", 1.5, true),
+                ("Froth milk", 2.0, true),
+                ("Pour", 0.5, false),
+            ],
+            Ingredients {
+                milk_ml: 120,
+                ..coffee
+            },
+        ),
+        // Brewed overnight, so only the pouring happens while the customer waits
+        MenuItem::new(
+            "cold brew",
+            2.0,
+            4.2,
+            &[("Pour over ice", 1.0, false)],
+            Ingredients {
+                beans_g: 20,
+                water_ml: 150,
+                ice_g: 100,
+                ..Ingredients::default()
+            },
+        ),
+        MenuItem::new(
+            "tea",
+            2.0,
+            2.5,
+            &[("Heat water", 1.0, true), ("Steep tea", 3.0, false)],
+            Ingredients {
+                water_ml: 250,
+                tea_g: 3,
+                ..Ingredients::default()
+            },
+        ),
+        MenuItem::new(
+            "pastry",
+            3.0,
+            3.2,
+            &[("Warm pastry", 1.5, false)],
+            Ingredients {
+                pastries: 1,
+                ..Ingredients::default()
+            },
+        ),
+    ]
+}
+
+pub fn validate(menu: &[MenuItem]) -> Result<()> {
+    if menu.is_empty() {
+        bail!("cafe.menu must have at least one item");
+    }
+    for (i, item) in menu.iter().enumerate() {
+        if menu[..i].iter().any(|other| other.name == item.name) {
+            bail!("cafe.menu lists '{}' more than once", item.name);
+        }
+        if !(item.weight >= 0.0 && item.weight.is_finite()) {
+            bail!(
+                "cafe.menu item '{}' must have a non-negative weight",
+                item.name
+            );
+        }
+        if !(item.price >= 0.0 && item.price.is_finite()) {
+            bail!(
+                "cafe.menu item '{}' must have a non-negative price",
+                item.name
+            );
+        }
+        if item.steps.is_empty() {
+            bail!("cafe.menu item '{}' needs at least one step", item.name);
+        }
+        if item
+            .steps
+            .iter()
+            .any(|step| !(step.secs >= 0.0 && step.secs.is_finite()))
+        {
+            bail!(
+                "cafe.menu item '{}' has a step without a non-negative duration",
+                item.name
+            );
+        }
+    }
+    if menu.iter().all(|item| item.weight == 0.0) {
+        bail!("cafe.menu needs an item with a positive weight");
+    }
+    Ok(())
+}
+
+// Picks what each customer orders
+pub struct Chooser {
+    items: WeightedIndex<f64>,
+    extra_item_chance: f64,
+    max_items: usize,
+}
+
+impl Chooser {
+    // The menu and limits are validated with the rest of the configuration
+    pub fn new(menu: &[MenuItem], extra_item_chance: f64, max_items: usize) -> Self {
+        Chooser {
+            items: WeightedIndex::new(menu.iter().map(|item| item.weight))
+                .expect("validated menu weights"),
+            extra_item_chance,
+            max_items,
+        }
+    }
+
+    // Indices into the menu; at least one item, each further one with `extra_item_chance`
+    pub fn choose(&self, rng: &mut impl Rng) -> Vec<usize> {
+        let mut items = vec![self.items.sample(rng)];
+        while items.len() < self.max_items && rng.gen_bool(self.extra_item_chance) {
+            items.push(self.items.sample(rng));
+        }
+        items
+    }
+}
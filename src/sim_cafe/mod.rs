@@ -1,47 +1,181 @@
-use anyhow::{Context, Result};
-use chrono::{Duration, Local};
-use crossbeam::channel;
-use parking_lot::{Condvar, Mutex};
+pub mod arrivals;
+pub mod counter;
+pub mod menu;
+
+use crate::clock::Clock;
+use crate::config::{check_chance, Config};
+use crate::events::{Event, EventLog};
+use crate::faults::{self, Faults};
+use crate::replay::Tape;
+use crate::rng::{RngSource, SimRng};
+use crate::sim_town::Links;
+use crate::simulation::{Outcome, Report, Simulation, Step};
+use crate::stats::{self, Distribution};
+use crate::telemetry::Row;
+use anyhow::{anyhow, bail, Context, Result};
+use arrivals::{ArrivalProcess, Arrivals, Progress};
+use counter::{Counter, PickupOrder, Pickups, Sales, Waits};
+use crossbeam::channel::{self, TryRecvError};
+use menu::{Chooser, Ingredients, MenuItem};
+use parking_lot::Mutex;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
+    borrow::Cow,
+    collections::BTreeMap,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
-    thread, time,
+    thread::JoinHandle,
+    time,
 };
 
-#[derive(Clone)]
+// Interval at which the cafe checks whether it can close
+const TICK: time::Duration = time::Duration::from_secs(1);
+
+#[derive(Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 struct Order {
     customer_id: usize,
     order_details: String,
+    items: Vec<String>, // Names of the menu items ordered, in the order they are made
     ticket_number: usize,
+    placed_at: time::Duration, // Simulation time at which the customer ordered
+    gives_up_at: Option<time::Duration>, // When the customer leaves if the order is not ready
+}
+
+// How much waiting a customer puts up with
+#[derive(Clone, Copy, Default)]
+struct Patience {
+    max_queue: Option<usize>, // Leave without ordering if this many orders are waiting
+    max_wait: Option<time::Duration>, // Cancel the order if it is not ready by then
 }
 
 struct Customer {
     id: usize,
+    items: Vec<String>,
+    patience: Patience,
     order_sender: channel::Sender<Order>,
+    counter: Arc<Counter>,
+    clock: Clock,
+    events: EventLog,
 }
 
 impl Customer {
-    fn new(id: usize, order_sender: channel::Sender<Order>) -> Self {
-        Customer { id, order_sender }
+    fn new(
+        id: usize,
+        items: Vec<String>,
+        patience: Patience,
+        order_sender: channel::Sender<Order>,
+        counter: Arc<Counter>,
+        clock: Clock,
+        events: EventLog,
+    ) -> Self {
+        Customer {
+            id,
+            items,
+            patience,
+            order_sender,
+            counter,
+            clock,
+            events,
+        }
     }
 
-    fn place_order(&self, ticket_counter: Arc<AtomicUsize>) -> Result<()> {
+    // None if the queue is too long and the customer leaves without ordering
+    fn place_order(&self, ticket_counter: Arc<AtomicUsize>) -> Result<Option<Order>> {
+        // Tickets are handed out under the counter's lock so a snapshot never sees half an order
+        let mut outstanding = self.counter.outstanding.lock();
+        let queue_length = self.order_sender.len();
+        if self
+            .patience
+            .max_queue
+            .is_some_and(|max_queue| queue_length >= max_queue)
+        {
+            drop(outstanding);
+            say!(
+                "Customer {}: Sees {} orders waiting and leaves without ordering",
+                self.id,
+                queue_length
+            );
+            self.events.emit(Event::CustomerBalked {
+                customer_id: self.id,
+                queue_length,
+            });
+            self.counter.balk(&self.items);
+            return Ok(None);
+        }
+
         let ticket_number = ticket_counter.fetch_add(1, Ordering::SeqCst);
         let order_details = format!("ORDER{}", self.id);
+        let placed_at = self.clock.now();
         let order = Order {
             customer_id: self.id,
             order_details: order_details.clone(),
+            items: self.items.clone(),
             ticket_number,
+            placed_at,
+            gives_up_at: self.patience.max_wait.map(|max_wait| placed_at + max_wait),
         };
-        println!("Customer {}: Orders coffee {}", self.id, order_details);
-        self.order_sender
-            .send(order)
-            .context("Failed to send order to barista")?;
-        Ok(())
+        outstanding.insert(ticket_number, order.clone());
+        say!(
+            "Customer {}: Orders {} ({})",
+            self.id,
+            self.items.join(", "),
+            order_details
+        );
+        self.events.emit(Event::OrderPlaced {
+            customer_id: self.id,
+            ticket: ticket_number,
+            details: order_details,
+            items: self.items.clone(),
+        });
+        if let Err(error) = self.order_sender.send(order.clone()) {
+            // Call the order off so the cafe does not wait for it to be served
+            drop(outstanding);
+            self.counter
+                .cancel(&order, |was| self.announce_cancelled(&order, was));
+            return Err(error).context("Failed to send order to barista");
+        }
+        self.counter
+            .max_queue
+            .fetch_max(self.order_sender.len(), Ordering::SeqCst);
+        drop(outstanding);
+        self.clock.notify(); // Wake an idle barista
+        Ok(Some(order))
+    }
+
+    // Wait at the counter until the order is handed over, or give up on it
+    fn collect(&self, order: &Order) {
+        let collected = self.counter.collect(order, |barista_id| {
+            let prepared_order = format!("Prepared {}", order.order_details);
+            say!("Customer {}: Collects {}", self.id, prepared_order);
+            self.events.emit(Event::OrderServed {
+                barista_id,
+                ticket: order.ticket_number,
+                details: prepared_order,
+            });
+        });
+        if !collected {
+            self.counter.cancel(order, |was| {
+                say!(
+                    "Customer {}: Gives up waiting and cancels {}",
+                    self.id,
+                    order.order_details
+                );
+                self.announce_cancelled(order, was);
+            });
+        }
+    }
+
+    fn announce_cancelled(&self, order: &Order, was: &str) {
+        self.events.emit(Event::OrderCancelled {
+            customer_id: self.id,
+            ticket: order.ticket_number,
+            was: was.to_string(),
+        });
     }
 }
 
@@ -49,67 +183,155 @@ struct Barista {
     id: usize,
     order_queue: channel::Receiver<Order>,
     coffee_machine: Arc<Semaphore>,
-    next_ticket: Arc<AtomicUsize>,
+    counter: Arc<Counter>,
+    menu: Arc<Vec<MenuItem>>,
+    clock: Clock,
+    events: EventLog,
+    town: Links,
 }
 
 impl Barista {
-    fn new(
-        id: usize,
-        order_queue: channel::Receiver<Order>,
-        coffee_machine: Arc<Semaphore>,
-        next_ticket: Arc<AtomicUsize>,
-    ) -> Self {
-        Barista {
-            id,
-            order_queue,
-            coffee_machine,
-            next_ticket,
-        }
-    }
-
     fn process_orders(&self) -> Result<()> {
-        while let Ok(order) = self.order_queue.recv() {
-            if !self.coffee_machine.try_acquire() {
-                println!("Barista {}: Coffee Machine occupied, waiting.", self.id);
-                self.coffee_machine.acquire();
+        while let Some(order) = self.next_order() {
+            if !self.counter.start(order.ticket_number) {
+                say!(
+                    "Barista {}: Skips {}, the customer has left",
+                    self.id,
+                    order.order_details
+                );
+                continue;
+            }
+            say!("Barista {}: Preparing {}", self.id, order.order_details);
+            let started_at = self.clock.now();
+            self.events.emit(Event::BrewStarted {
+                barista_id: self.id,
+                ticket: order.ticket_number,
+            });
+            for name in &order.items {
+                if self.counter.is_cancelled(order.ticket_number) {
+                    break;
+                }
+                self.make(name, &order)?;
             }
 
-            println!("Barista {}: Brewing {}", self.id, order.order_details);
-            thread::sleep(time::Duration::from_secs(2)); // Simulate brewing time
-            println!("Barista {}: Brewed {}", self.id, order.order_details);
-
-            // Wait until it's this order's turn to be served
-            while self.next_ticket.load(Ordering::SeqCst) != order.ticket_number {
-                thread::sleep(time::Duration::from_millis(10));
+            // Leave it for the customer and move straight on to the next order
+            if self.counter.place(order.ticket_number, self.id, started_at) {
+                say!(
+                    "Barista {}: {} is ready for pickup",
+                    self.id,
+                    order.order_details
+                );
+                self.events.emit(Event::OrderReady {
+                    barista_id: self.id,
+                    ticket: order.ticket_number,
+                });
+            } else {
+                say!(
+                    "Barista {}: Stops on {}, the customer has left",
+                    self.id,
+                    order.order_details
+                );
+                self.events.emit(Event::OrderDropped {
+                    barista_id: self.id,
+                    ticket: order.ticket_number,
+                });
             }
+        }
+        Ok(())
+    }
 
-            let prepared_order = format!("Prepared {}", order.order_details);
-            println!("Barista {}: Serving {}", self.id, prepared_order);
+    // Follow an item's recipe; a machine is only held for the steps that need one
+    fn make(&self, name: &str, order: &Order) -> Result<()> {
+        let item = self
+            .menu
+            .iter()
+            .find(|item| item.name == name)
+            .with_context(|| format!("{} is not on the menu", name))?;
+        let beans_g = item.ingredients.beans_g;
+        if !self.town.take_beans(beans_g) {
+            say!("Barista {}: Out of beans, waiting for a delivery.", self.id);
+            self.clock
+                .wait_until(None, || self.town.take_beans(beans_g));
+        }
 
-            self.next_ticket.fetch_add(1, Ordering::SeqCst);
-            self.coffee_machine.release();
+        for step in &item.steps {
+            if step.machine && !self.coffee_machine.try_acquire() {
+                say!("Barista {}: Coffee Machine occupied, waiting.", self.id);
+                self.coffee_machine.acquire();
+            }
+            say!(
+                "Barista {}: {} for the {} of {}",
+                self.id,
+                step.action,
+                item.name,
+                order.order_details
+            );
+            // Slower when the town is short of power
+            let secs = time::Duration::from_secs_f64(step.secs / self.town.power_factor());
+            self.clock.sleep(secs);
+            if step.machine {
+                self.coffee_machine.release(secs);
+            }
         }
+
+        self.counter.items_made.fetch_add(1, Ordering::SeqCst);
+        *self.counter.used.lock() += item.ingredients;
+        self.events.emit(Event::ItemPrepared {
+            barista_id: self.id,
+            ticket: order.ticket_number,
+            item: item.name.clone(),
+        });
         Ok(())
     }
+
+    // Wait for the next order; None once the cafe has closed and every order has been taken
+    fn next_order(&self) -> Option<Order> {
+        let mut order = None;
+        self.clock
+            .wait_until(None, || match self.order_queue.try_recv() {
+                Ok(next) => {
+                    order = Some(next);
+                    true
+                }
+                Err(TryRecvError::Empty) => false,
+                Err(TryRecvError::Disconnected) => true,
+            });
+        order
+    }
 }
 
 struct Semaphore {
-    permits: Mutex<usize>,
-    condvar: Condvar,
+    permits: Mutex<isize>, // Negative while more machines are broken than were free
+    in_use: AtomicUsize,   // Machines brewing right now
+    busy: Mutex<time::Duration>, // Time spent brewing, summed over the machines
+    clock: Clock,
 }
 
 impl Semaphore {
-    fn new(capacity: usize) -> Arc<Self> {
+    fn new(capacity: usize, clock: Clock) -> Arc<Self> {
         Arc::new(Semaphore {
-            permits: Mutex::new(capacity),
-            condvar: Condvar::new(),
+            permits: Mutex::new(capacity as isize),
+            in_use: AtomicUsize::new(0),
+            busy: Mutex::new(time::Duration::ZERO),
+            clock,
         })
     }
 
+    // Take a machine out of service; if all are busy, the next one to be released is taken
+    fn take_out_of_service(&self) {
+        *self.permits.lock() -= 1;
+    }
+
+    fn return_to_service(&self) {
+        *self.permits.lock() += 1;
+        self.clock.notify();
+    }
+
     fn try_acquire(&self) -> bool {
         let mut permits = self.permits.lock();
         if *permits > 0 {
             *permits -= 1;
+            self.in_use.fetch_add(1, Ordering::SeqCst);
             true
         } else {
             false
@@ -117,78 +339,705 @@ impl Semaphore {
     }
 
     fn acquire(&self) {
-        let mut permits = self.permits.lock();
-        while *permits == 0 {
-            println!("Waiting for a free coffee machine slot...");
-            self.condvar.wait(&mut permits);
+        say!("Waiting for a free coffee machine slot...");
+        self.clock.wait_until(None, || self.try_acquire());
+    }
+
+    // Give back a machine that was held for `held`
+    fn release(&self, held: time::Duration) {
+        *self.busy.lock() += held;
+        self.in_use.fetch_sub(1, Ordering::SeqCst);
+        self.return_to_service();
+    }
+
+    fn in_use(&self) -> usize {
+        self.in_use.load(Ordering::SeqCst)
+    }
+
+    fn busy(&self) -> time::Duration {
+        *self.busy.lock()
+    }
+}
+
+// Tunable parameters for a cafe run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CafeConfig {
+    pub duration_secs: u64,     // How long the cafe accepts new customers
+    pub baristas: usize,        // Number of barista threads
+    pub coffee_machines: usize, // Number of coffee machines shared by the baristas
+    pub menu: Vec<MenuItem>,    // What customers can order
+    pub extra_item_chance: f64, // Chance of a customer adding one more item to an order
+    pub max_items_per_order: usize,
+    pub pickup_order: PickupOrder, // Whether finished orders are handed out by ticket or when ready
+    pub arrivals: Arrivals,        // How customers turn up; uniform gaps unless set
+    pub min_arrival_ms: u64,       // Shortest gap between two customers
+    pub max_arrival_ms: u64,       // Longest gap between two customers (exclusive)
+    pub machine_breakdown_chance: f64, // Chance per second of a coffee machine breaking down
+    pub machine_repair_secs: f64,  // Time a broken coffee machine is out of service
+    pub balk_queue_length: usize,  // Customers finding this many orders waiting leave; 0 never do
+    pub min_patience_secs: f64,    // Shortest time a customer waits for an order before giving up
+    pub max_patience_secs: f64,    // Longest such time; 0 has customers wait however long it takes
+}
+
+impl Default for CafeConfig {
+    fn default() -> Self {
+        CafeConfig {
+            duration_secs: 10,
+            baristas: 5,
+            coffee_machines: 3,
+            menu: menu::default_menu(),
+            extra_item_chance: 0.3,
+            max_items_per_order: 3,
+            pickup_order: PickupOrder::Ticket,
+            arrivals: Arrivals::default(),
+            min_arrival_ms: 500,
+            max_arrival_ms: 1000,
+            machine_breakdown_chance: 0.0,
+            machine_repair_secs: 5.0,
+            balk_queue_length: 8,
+            min_patience_secs: 20.0,
+            max_patience_secs: 40.0,
         }
-        *permits -= 1;
     }
+}
 
-    fn release(&self) {
-        let mut permits = self.permits.lock();
-        *permits += 1;
-        self.condvar.notify_one();
-    }
-}
-
-pub fn run() -> Result<()> {
-    println!("Welcome to the Cafe! Your orders will be processed shortly.");
-    let running = Arc::new(AtomicBool::new(true));
-    let ticket_counter = Arc::new(AtomicUsize::new(1));
-    let (order_sender, order_receiver) = channel::unbounded();
-    let start_time = Local::now();
-    let run_duration = Duration::seconds(10);
-
-    // Start baristas
-    let coffee_machine = Semaphore::new(3);
-    let next_ticket = Arc::new(AtomicUsize::new(1));
-    let baristas: Vec<_> = (1..=5)
-        .map(|id| {
-            let order_receiver = order_receiver.clone();
-            let coffee_machine = coffee_machine.clone();
-            let next_ticket = next_ticket.clone();
-            thread::spawn(move || {
-                let barista = Barista::new(id, order_receiver, coffee_machine, next_ticket);
-                barista.process_orders().unwrap();
+impl CafeConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.baristas == 0 {
+            bail!("cafe.baristas must be at least 1");
+        }
+        if self.coffee_machines == 0 {
+            bail!("cafe.coffee_machines must be at least 1");
+        }
+        menu::validate(&self.menu)?;
+        check_chance("cafe.extra_item_chance", self.extra_item_chance)?;
+        if self.max_items_per_order == 0 {
+            bail!("cafe.max_items_per_order must be at least 1");
+        }
+        // A zero gap would let the generator spawn customers forever without time passing. The
+        // other arrival models leave these settings unused.
+        if matches!(self.arrivals, Arrivals::Uniform)
+            && (self.min_arrival_ms == 0 || self.min_arrival_ms >= self.max_arrival_ms)
+        {
+            bail!("cafe.min_arrival_ms must be positive and below cafe.max_arrival_ms");
+        }
+        self.arrivals.validate()?;
+        if self.max_patience_secs != 0.0
+            && !(self.min_patience_secs > 0.0
+                && self.min_patience_secs <= self.max_patience_secs
+                && self.max_patience_secs.is_finite())
+        {
+            bail!(
+                "cafe.min_patience_secs must be positive and at most cafe.max_patience_secs, \
+                 unless cafe.max_patience_secs is 0"
+            );
+        }
+        if !(self.machine_repair_secs >= 0.0 && self.machine_repair_secs.is_finite()) {
+            bail!("cafe.machine_repair_secs must be a non-negative number");
+        }
+        check_chance(
+            "cafe.machine_breakdown_chance",
+            self.machine_breakdown_chance,
+        )
+    }
+}
+
+// Everything needed to carry on a cafe run from where a snapshot left it.
+// Orders still waiting are made again from scratch when the run resumes, even if they were
+// already on the counter, and their customers go back to waiting for them. The next customer
+// still comes in when they were due, but random draws start afresh from the resumed run's seed,
+// so who they are and what they order may differ from the original run.
+#[derive(Serialize, Deserialize)]
+struct CafeState {
+    elapsed_secs: f64,
+    ticks: u64,
+    open: bool, // Still letting new customers in
+    next_customer: usize,
+    next_ticket: usize,  // Ticket the next customer will be given
+    waiting: Vec<Order>, // Placed but not yet served, in ticket order
+    pickups: Pickups,
+    items_made: usize,
+    used: Ingredients,
+    sales: Sales,
+    breakdowns: u64,
+    repairs_due_secs: Vec<f64>, // When each broken coffee machine is back in service
+    machine_busy_secs: f64,
+    orders_in_cafe_sampled: u64,
+    arrivals: Progress,
+}
+
+pub struct CafeSimulation {
+    config: CafeConfig,
+    restored: Option<CafeState>, // Applied by the next `start`
+    faults: Faults,
+    rngs: RngSource,
+    fault_rng: SimRng, // Kept apart so breakdowns leave the customers' draws unchanged
+    tape: Tape,
+    clock: Clock,
+    events: EventLog,
+    links: Links,
+    ticks: u64,
+    orders_in_cafe_sampled: u64, // Orders in the cafe, counted at every tick and summed
+    running: Arc<AtomicBool>,    // True while new customers are still arriving
+    working_baristas: Arc<AtomicUsize>, // Baristas that have not yet finished their shift
+    next_customer: Arc<AtomicUsize>,
+    ticket_counter: Arc<AtomicUsize>,
+    arrivals: Arc<Mutex<Progress>>, // Held while a customer comes in and places their order
+    counter: Arc<Counter>,
+    coffee_machine: Arc<Semaphore>,
+    repairs: Vec<time::Duration>, // When each broken coffee machine is back in service
+    breakdowns: u64,
+    order_sender: Option<channel::Sender<Order>>,
+    order_queue: channel::Receiver<Order>, // Orders no barista has picked up yet
+    customers: Option<JoinHandle<()>>,
+    baristas: Vec<JoinHandle<Result<()>>>,
+}
+
+impl CafeSimulation {
+    pub fn new() -> Self {
+        let rngs = RngSource::default();
+        CafeSimulation {
+            config: CafeConfig::default(),
+            restored: None,
+            faults: Faults::default(),
+            fault_rng: rngs.stream("faults"),
+            rngs,
+            tape: Tape::default(),
+            clock: Clock::default(),
+            events: EventLog::default(),
+            links: Links::default(),
+            ticks: 0,
+            orders_in_cafe_sampled: 0,
+            running: Arc::new(AtomicBool::new(false)),
+            working_baristas: Arc::new(AtomicUsize::new(0)),
+            next_customer: Arc::new(AtomicUsize::new(1)),
+            ticket_counter: Arc::new(AtomicUsize::new(1)),
+            arrivals: Arc::new(Mutex::new(Progress::default())),
+            counter: Arc::new(Counter::new(PickupOrder::default(), Clock::default())),
+            coffee_machine: Semaphore::new(0, Clock::default()),
+            repairs: Vec::new(),
+            breakdowns: 0,
+            order_sender: None,
+            order_queue: channel::never(),
+            customers: None,
+            baristas: Vec::new(),
+        }
+    }
+}
+
+impl CafeSimulation {
+    // Close the channel to stop baristas after all orders are processed
+    fn close_orders(&mut self) {
+        if self.order_sender.take().is_some() {
+            self.clock.notify();
+        }
+    }
+
+    fn break_machine(&mut self) {
+        say!(
+            "Cafe: A coffee machine broke down! It will be back in {}s.",
+            self.config.machine_repair_secs
+        );
+        self.coffee_machine.take_out_of_service();
+        let repair = time::Duration::from_secs_f64(self.config.machine_repair_secs);
+        self.repairs.push(self.clock.now() + repair);
+        self.breakdowns += 1;
+        self.events.emit(Event::MachineBreakdown {
+            repair_secs: self.config.machine_repair_secs,
+        });
+    }
+
+    // Put machines whose repair is done back in service, or every broken one if `all`
+    fn repair_machines(&mut self, all: bool) {
+        let now = self.clock.now();
+        let before = self.repairs.len();
+        self.repairs.retain(|due| !all && *due > now);
+        for _ in self.repairs.len()..before {
+            say!("Cafe: A coffee machine is repaired and back in service.");
+            self.coffee_machine.return_to_service();
+            self.events.emit(Event::MachineRepaired);
+        }
+    }
+}
+
+impl Default for CafeSimulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulation for CafeSimulation {
+    fn name(&self) -> &'static str {
+        "cafe"
+    }
+
+    fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<()> {
+        self.config = config.cafe.clone();
+        self.faults = Faults::new(&config.faults);
+        self.rngs = RngSource::new(config.seed).with_tape(config.tape.clone());
+        self.tape = config.tape.clone();
+        self.clock = config.links.clock(config.clock);
+        self.events = config.events.scoped(self.name(), Some(self.clock.clone()));
+        self.links = config.links.clone();
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        say!("Welcome to the Cafe! Your orders will be processed shortly.");
+        let running = Arc::new(AtomicBool::new(true));
+        let next_customer = Arc::new(AtomicUsize::new(1));
+        let ticket_counter = Arc::new(AtomicUsize::new(1));
+        let (order_sender, order_receiver) = channel::unbounded();
+        let run_duration = time::Duration::from_secs(self.config.duration_secs);
+        let coffee_machine = Semaphore::new(self.config.coffee_machines, self.clock.clone());
+        let counter = Arc::new(Counter::new(self.config.pickup_order, self.clock.clone()));
+        self.ticks = 0;
+        self.orders_in_cafe_sampled = 0;
+        self.repairs.clear();
+        self.breakdowns = 0;
+
+        // Pick up where a snapshot left off before any thread starts
+        let mut resumed_arrivals = None;
+        if let Some(state) = self.restored.take() {
+            self.clock
+                .resume_at(time::Duration::from_secs_f64(state.elapsed_secs));
+            self.ticks = state.ticks;
+            self.orders_in_cafe_sampled = state.orders_in_cafe_sampled;
+            resumed_arrivals = Some(state.arrivals);
+            running.store(state.open, Ordering::SeqCst);
+            next_customer.store(state.next_customer, Ordering::SeqCst);
+            ticket_counter.store(state.next_ticket, Ordering::SeqCst);
+            counter.resume(state.pickups);
+            *coffee_machine.busy.lock() = time::Duration::from_secs_f64(state.machine_busy_secs);
+            counter.items_made.store(state.items_made, Ordering::SeqCst);
+            *counter.used.lock() = state.used;
+            *counter.sales.lock() = state.sales;
+            let mut outstanding = counter.outstanding.lock();
+            for order in state.waiting {
+                outstanding.insert(order.ticket_number, order.clone());
+                order_sender
+                    .send(order.clone())
+                    .context("Failed to requeue a waiting order")?;
+                // Their patience already shows in when the order is given up on
+                let customer = Customer::new(
+                    order.customer_id,
+                    order.items.clone(),
+                    Patience::default(),
+                    order_sender.clone(),
+                    counter.clone(),
+                    self.clock.clone(),
+                    self.events.clone(),
+                );
+                self.clock.spawn(move || customer.collect(&order));
+            }
+            drop(outstanding);
+            self.breakdowns = state.breakdowns;
+            for due in state.repairs_due_secs {
+                coffee_machine.take_out_of_service();
+                self.repairs.push(time::Duration::from_secs_f64(due));
+            }
+        }
+
+        // Start baristas
+        let working_baristas = Arc::new(AtomicUsize::new(self.config.baristas));
+        let menu = Arc::new(self.config.menu.clone());
+        self.baristas = (1..=self.config.baristas)
+            .map(|id| {
+                let order_receiver = order_receiver.clone();
+                let coffee_machine = coffee_machine.clone();
+                let counter = counter.clone();
+                let menu = menu.clone();
+                let working_baristas = working_baristas.clone();
+                let clock = self.clock.clone();
+                let events = self.events.clone();
+                let town = self.links.clone();
+                self.clock.spawn(move || {
+                    let barista = Barista {
+                        id,
+                        order_queue: order_receiver,
+                        coffee_machine,
+                        counter,
+                        menu,
+                        clock,
+                        events,
+                        town,
+                    };
+                    let result = barista.process_orders();
+                    working_baristas.fetch_sub(1, Ordering::SeqCst);
+                    result
+                })
             })
-        })
-        .collect();
-
-    // Generate customers
-    let customers = {
-        let order_sender = order_sender.clone();
-        thread::spawn({
-            let running = running.clone();
-            move || {
-                let mut id = 1;
-                while Local::now() - start_time < run_duration {
-                    let sender_clone = order_sender.clone();
-                    let ticket_clone = ticket_counter.clone();
-                    thread::spawn(move || {
-                        let customer = Customer::new(id, sender_clone);
-                        customer.place_order(ticket_clone).unwrap();
-                    });
-                    id += 1;
-                    // Reduced delay between customers to 300-500 milliseconds for faster customer generation
-                    thread::sleep(time::Duration::from_millis(
-                        rand::thread_rng().gen_range(500..1000),
-                    ));
+            .collect();
+
+        // Generate customers
+        self.customers = Some({
+            let order_sender = order_sender.clone();
+            let next_customer = next_customer.clone();
+            let ticket_counter = ticket_counter.clone();
+            let counter = counter.clone();
+            let mut rng = self.rngs.stream("customers");
+            let mut arrivals = ArrivalProcess::new(
+                &self.config.arrivals,
+                self.config.min_arrival_ms..self.config.max_arrival_ms,
+                self.clock.now(),
+                run_duration,
+            )?;
+            if let Some(progress) = &resumed_arrivals {
+                arrivals.resume(progress);
+            }
+            let progress = Arc::new(Mutex::new(arrivals.progress()));
+            self.arrivals = progress.clone();
+            let chooser = Chooser::new(
+                &self.config.menu,
+                self.config.extra_item_chance,
+                self.config.max_items_per_order,
+            );
+            let balk_queue_length = self.config.balk_queue_length;
+            let patience_secs = self.config.min_patience_secs..=self.config.max_patience_secs;
+            let clock = self.clock.clone();
+            let events = self.events.clone();
+            let tape = self.tape.clone();
+            self.clock.spawn({
+                let running = running.clone();
+                move || {
+                    // Nobody else arriving before closing time closes the cafe early. Waiting for
+                    // the next customer stops as soon as the cafe is stopped.
+                    while let Some(at) = arrivals.next_at() {
+                        let wait = at.saturating_sub(clock.now());
+                        clock.wait_until(Some(wait), || !running.load(Ordering::SeqCst));
+                        // A snapshot sees either all of this customer or none of them
+                        let mut progress = progress.lock();
+                        let now = tape.input("uptime", || clock.now());
+                        if !running.load(Ordering::SeqCst) || now >= run_duration {
+                            break;
+                        }
+                        let id = next_customer.fetch_add(1, Ordering::SeqCst);
+                        let items = chooser
+                            .choose(&mut rng)
+                            .into_iter()
+                            .map(|i| menu[i].name.clone())
+                            .collect();
+                        let patience = Patience {
+                            max_queue: (balk_queue_length > 0).then_some(balk_queue_length),
+                            max_wait: (*patience_secs.end() > 0.0).then(|| {
+                                time::Duration::from_secs_f64(rng.gen_range(patience_secs.clone()))
+                            }),
+                        };
+                        let customer = Customer::new(
+                            id,
+                            items,
+                            patience,
+                            order_sender.clone(),
+                            counter.clone(),
+                            clock.clone(),
+                            events.clone(),
+                        );
+                        match customer.place_order(ticket_counter.clone()) {
+                            Ok(Some(order)) => {
+                                clock.spawn(move || customer.collect(&order));
+                            }
+                            Ok(None) => {}
+                            // The order has been called off, so the run carries on without it
+                            Err(error) => say!("Customer {}: {:#}", id, error),
+                        }
+                        arrivals.next_gap(now, &mut rng);
+                        *progress = arrivals.progress();
+                    }
+                    running.store(false, Ordering::SeqCst);
                 }
-                running.store(false, Ordering::SeqCst);
+            })
+        });
+
+        self.running = running;
+        self.working_baristas = working_baristas;
+        self.next_customer = next_customer;
+        self.ticket_counter = ticket_counter;
+        self.counter = counter;
+        self.coffee_machine = coffee_machine;
+        self.fault_rng = self.rngs.stream("faults");
+        self.order_sender = Some(order_sender);
+        self.order_queue = order_receiver;
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<Step> {
+        self.clock.sleep(TICK);
+        self.ticks += 1;
+        self.orders_in_cafe_sampled += self.counter.outstanding.lock().len() as u64;
+
+        let chance = self.config.machine_breakdown_chance;
+        if self
+            .faults
+            .strikes("machine_breakdown", self.ticks, chance, &mut self.fault_rng)
+        {
+            self.break_machine();
+        }
+        self.repair_machines(false);
+
+        // Wait for the running period to end
+        if self.order_sender.is_some() && !self.running.load(Ordering::SeqCst) {
+            say!("Cafe is closing, last orders!");
+            self.events.emit(Event::CafeClosing);
+            self.close_orders();
+        }
+
+        if self.order_sender.is_none()
+            && self.working_baristas.load(Ordering::SeqCst) == 0
+            && self.counter.first_open_ticket() == self.ticket_counter.load(Ordering::SeqCst)
+        {
+            Ok(Step::Finished(Outcome::Completed))
+        } else {
+            Ok(Step::Continue)
+        }
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        // Turn away new customers if the cafe is stopped early, then serve whoever already ordered.
+        // Waits go through the clock so virtual time keeps moving for the remaining threads.
+        self.running.store(false, Ordering::SeqCst);
+        self.clock.notify();
+        self.close_orders();
+        // Nobody is left to wait out the repairs, so finish them now
+        self.repair_machines(true);
+        // Customers collect their orders as soon as the last ones are on the counter, or give up
+        let (working_baristas, counter, tickets) =
+            (&self.working_baristas, &self.counter, &self.ticket_counter);
+        self.clock.wait_until(None, || {
+            working_baristas.load(Ordering::SeqCst) == 0
+                && counter.first_open_ticket() == tickets.load(Ordering::SeqCst)
+        });
+
+        if let Some(customers) = self.customers.take() {
+            customers
+                .join()
+                .map_err(|_| anyhow!("Customer generator panicked"))?;
+        }
+
+        for barista in self.baristas.drain(..) {
+            barista
+                .join()
+                .map_err(|_| anyhow!("Barista thread panicked"))??;
+        }
+
+        say!("Cafe is now closed! Thanks for coming.");
+        Ok(())
+    }
+
+    fn telemetry(&self) -> Result<Row> {
+        Ok(vec![
+            ("queue_length", self.order_queue.len().to_string()),
+            ("orders_on_counter", self.counter.on_shelf().to_string()),
+            ("machines_in_use", self.coffee_machine.in_use().to_string()),
+            ("machines_broken", self.repairs.len().to_string()),
+            ("coffee_machines", self.config.coffee_machines.to_string()),
+            ("orders_served", self.counter.served().to_string()),
+        ])
+    }
+
+    fn inject(&mut self, event: &str) -> Result<()> {
+        match event {
+            "machine_breakdown" => self.break_machine(),
+            _ => bail!(
+                "The cafe simulation can inject {}, not '{}'",
+                faults::names(self.name()).join(", "),
+                event
+            ),
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Value> {
+        // Customers and baristas keep working; holding the counter's lock freezes the orders
+        let arrivals = self.arrivals.lock();
+        let outstanding = self.counter.outstanding.lock();
+        let state = CafeState {
+            elapsed_secs: self.clock.now().as_secs_f64(),
+            ticks: self.ticks,
+            open: self.running.load(Ordering::SeqCst),
+            next_customer: self.next_customer.load(Ordering::SeqCst),
+            next_ticket: self.ticket_counter.load(Ordering::SeqCst),
+            waiting: outstanding.values().cloned().collect(),
+            pickups: self.counter.pickups(),
+            items_made: self.counter.items_made.load(Ordering::SeqCst),
+            used: *self.counter.used.lock(),
+            sales: self.counter.sales.lock().clone(),
+            breakdowns: self.breakdowns,
+            repairs_due_secs: self.repairs.iter().map(|due| due.as_secs_f64()).collect(),
+            machine_busy_secs: self.coffee_machine.busy().as_secs_f64(),
+            orders_in_cafe_sampled: self.orders_in_cafe_sampled,
+            arrivals: arrivals.clone(),
+        };
+        drop(outstanding);
+        drop(arrivals);
+        Ok(serde_json::to_value(state)?)
+    }
+
+    fn restore(&mut self, state: &Value) -> Result<()> {
+        self.restored = Some(CafeState::deserialize(state)?);
+        Ok(())
+    }
+
+    fn report(&self) -> Report {
+        let waits = self.counter.waits();
+        let used = *self.counter.used.lock();
+        let elapsed_secs = self.clock.now().as_secs_f64();
+        let placed = (self.ticket_counter.load(Ordering::SeqCst) - 1) as f64;
+        let served = self.counter.served() as f64;
+        // Per second of the run, or per second of each of `count` baristas or machines
+        let per_sec = |total: f64, count: usize| {
+            if elapsed_secs > 0.0 {
+                total / (elapsed_secs * count as f64)
+            } else {
+                0.0
             }
-        })
-    };
+        };
+
+        // Little's law: on average the cafe holds as many orders as arrive per second times the
+        // seconds each spends in it. The orders in the cafe are counted at every tick, apart from
+        // the waits, so a gap between the two shows up sampling error or orders lost from the books.
+        let in_cafe = if self.ticks > 0 {
+            self.orders_in_cafe_sampled as f64 / self.ticks as f64
+        } else {
+            0.0
+        };
+        let total_waits: Vec<f64> = waits.iter().map(Waits::total).collect();
+        let stays = [total_waits.clone(), self.counter.gave_up_after()].concat();
+        let lambda_w = per_sec(placed, 1) * stats::mean(&stays);
+        let littles_law_gap_pct = if lambda_w > 0.0 {
+            (in_cafe - lambda_w).abs() / lambda_w * 100.0
+        } else {
+            0.0
+        };
+
+        let sales = self.counter.sales.lock().clone();
+        // Folded from 0.0, as an empty f64 sum comes out as -0
+        let takings = |counts: &BTreeMap<String, u32>| {
+            counts
+                .iter()
+                .filter_map(|(name, count)| {
+                    let item = self.config.menu.iter().find(|item| &item.name == name)?;
+                    Some(item.price * *count as f64)
+                })
+                .fold(0.0, |total, takings| total + takings)
+        };
 
-    // Wait for the running period to end
-    customers.join().unwrap();
-    println!("Cafe is closing, last orders!");
-    drop(order_sender); // Close the channel to stop baristas after all orders are processed
+        let mut stats: Vec<(Cow<'static, str>, f64)> = vec![
+            ("orders_placed".into(), placed),
+            ("orders_served".into(), served),
+            ("customers_balked".into(), sales.balked as f64),
+            ("orders_cancelled".into(), sales.reneged as f64),
+            (
+                "customers_lost".into(),
+                (sales.balked + sales.reneged) as f64,
+            ),
+            ("revenue".into(), takings(&sales.sold)),
+            ("lost_revenue".into(), takings(&sales.lost)),
+            ("throughput_per_min".into(), per_sec(served, 1) * 60.0),
+        ];
+        stats.extend(spread("wait", &total_waits));
+        let stage = |secs: fn(&Waits) -> f64| waits.iter().map(secs).collect::<Vec<_>>();
+        stats.extend(spread("queue_wait", &stage(|waits| waits.queue)));
+        stats.extend(spread("brew_time", &stage(|waits| waits.brew)));
+        stats.extend(spread("pickup_wait", &stage(|waits| waits.pickup)));
+        // Work on orders that were then cancelled kept the baristas busy all the same
+        let brewing_secs =
+            waits.iter().map(|waits| waits.brew).sum::<f64>() + self.counter.dropped_brew_secs();
+        stats.extend([
+            (
+                "max_queue_length".into(),
+                self.counter.max_queue.load(Ordering::SeqCst) as f64,
+            ),
+            (
+                "barista_utilisation".into(),
+                per_sec(brewing_secs, self.config.baristas),
+            ),
+            (
+                "machine_utilisation".into(),
+                per_sec(
+                    self.coffee_machine.busy().as_secs_f64(),
+                    self.config.coffee_machines,
+                ),
+            ),
+            ("littles_law_orders_in_cafe".into(), in_cafe),
+            ("littles_law_lambda_w".into(), lambda_w),
+            ("littles_law_gap_pct".into(), littles_law_gap_pct),
+            (
+                "items_made".into(),
+                self.counter.items_made.load(Ordering::SeqCst) as f64,
+            ),
+            ("beans_used_g".into(), used.beans_g as f64),
+            ("milk_used_ml".into(), used.milk_ml as f64),
+            ("water_used_ml".into(), used.water_ml as f64),
+            ("ice_used_g".into(), used.ice_g as f64),
+            ("tea_used_g".into(), used.tea_g as f64),
+            ("pastries_used".into(), used.pastries as f64),
+            ("machine_breakdowns".into(), self.breakdowns as f64),
+        ]);
+        Report {
+            simulation: self.name(),
+            outcome: Outcome::Completed,
+            seed: self.rngs.seed(),
+            ticks: self.ticks,
+            elapsed_secs,
+            stats,
+        }
+    }
+}
+
+const SPREAD: [&str; 5] = ["mean", "p50", "p90", "p99", "max"];
+
+// Mean, percentiles and maximum of some waits, as `<prefix>_<measure>_secs` stats
+fn spread(prefix: &str, secs: &[f64]) -> Vec<(Cow<'static, str>, f64)> {
+    let waits = Distribution::new(secs);
+    SPREAD
+        .into_iter()
+        .zip([waits.mean, waits.p50, waits.p90, waits.p99, waits.max])
+        .map(|(measure, value)| (format!("{}_{}_secs", prefix, measure).into(), value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for barista in baristas {
-        barista.join().unwrap();
+    #[test]
+    fn an_order_that_cannot_be_sent_is_called_off() {
+        let (order_sender, order_receiver) = channel::unbounded();
+        drop(order_receiver);
+        let counter = Arc::new(Counter::new(PickupOrder::Ticket, Clock::default()));
+        let customer = Customer::new(
+            1,
+            vec!["latte".to_string()],
+            Patience::default(),
+            order_sender,
+            counter.clone(),
+            Clock::default(),
+            EventLog::default(),
+        );
+        let tickets = Arc::new(AtomicUsize::new(1));
+        assert!(customer.place_order(tickets.clone()).is_err());
+        // The ticket is settled, so the cafe can still close
+        assert_eq!(counter.first_open_ticket(), tickets.load(Ordering::SeqCst));
+        assert!(counter.outstanding.lock().is_empty());
+        assert_eq!(counter.sales.lock().lost.get("latte"), Some(&1));
     }
 
-    println!("Cafe is now closed! Thanks for coming.");
-    Ok(())
+    #[test]
+    fn arrival_gaps_only_matter_to_the_uniform_model() {
+        let config = CafeConfig {
+            min_arrival_ms: 0,
+            ..CafeConfig::default()
+        };
+        assert!(config.validate().is_err());
+        let config = CafeConfig {
+            arrivals: Arrivals::Poisson { per_minute: 60.0 },
+            ..config
+        };
+        assert!(config.validate().is_ok());
+    }
 }
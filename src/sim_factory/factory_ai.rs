@@ -2,15 +2,23 @@ use amiquip::{
     Channel, Connection, ConsumerMessage, ConsumerOptions, Exchange, Publish, QueueDeclareOptions,
     Result,
 };
+
+use crate::events::{Event, EventLog};
+use crate::replay::Tape;
+
 //FactoryAI Struct
 pub struct FactoryAI {
     resupply_pending: bool, // Tracks if a resupply is already in progress
+    events: EventLog,
+    tape: Tape,
 }
 
 impl FactoryAI {
-    pub fn new() -> Self {
+    pub fn new(events: EventLog, tape: Tape) -> Self {
         FactoryAI {
             resupply_pending: false, // Initialize with no resupply pending
+            events,
+            tape,
         }
     }
     // Simulation start, overseeing production
@@ -19,37 +27,39 @@ impl FactoryAI {
         let channel = connection.open_channel(None)?;
         let queue = channel.queue_declare("factory_ai", QueueDeclareOptions::default())?;
         let consumer = queue.consume(ConsumerOptions::default())?;
-        println!("FactoryAI: Waiting for task requests. Press Ctrl-C to exit.");
+        say!("FactoryAI: Waiting for task requests.");
 
         for message in consumer.receiver().iter() {
             match message {
                 ConsumerMessage::Delivery(delivery) => {
-                    let body = String::from_utf8_lossy(&delivery.body);
+                    let body = self.tape.input("factory_ai", || {
+                        String::from_utf8_lossy(&delivery.body).into_owned()
+                    });
 
-                    match body.as_ref() {
+                    match body.as_str() {
                         "RequestGrinding" => {
-                            println!("FactoryAI: Request [Grinding]");
+                            say!("FactoryAI: Request [Grinding]");
                             self.send_message("StartGrinding", &channel)?;
-                            println!("FactoryAI: Authorized [Grinding]");
+                            say!("FactoryAI: Authorized [Grinding]");
                         }
                         "RequestBrewing" => {
-                            println!("FactoryAI: Request [Brewing]");
+                            say!("FactoryAI: Request [Brewing]");
                             self.send_message("StartBrewing", &channel)?;
-                            println!("FactoryAI: Authorized [Brewing]");
+                            say!("FactoryAI: Authorized [Brewing]");
                         }
                         "RequestPackaging" => {
-                            println!("FactoryAI: Request [Packaging]");
+                            say!("FactoryAI: Request [Packaging]");
                             self.send_message("StartPackaging", &channel)?;
-                            println!("FactoryAI: Authorized [Packaging]");
+                            say!("FactoryAI: Authorized [Packaging]");
                         }
                         "GrindingComplete" => {
-                            println!("FactoryAI: Update [Grinding Complete]");
+                            say!("FactoryAI: Update [Grinding Complete]");
                         }
                         "BrewingComplete" => {
-                            println!("FactoryAI: Update [Brewing Complete]");
+                            say!("FactoryAI: Update [Brewing Complete]");
                         }
                         "PackagingComplete" => {
-                            println!("FactoryAI: Update [Packaging Complete]");
+                            say!("FactoryAI: Update [Packaging Complete]");
                         }
                         _ if body.starts_with("Inventory:") => {
                             let inventory_value: i32 = body["Inventory: ".len()..body.len() - 6]
@@ -58,18 +68,16 @@ impl FactoryAI {
                             if inventory_value >= 0 {
                                 if inventory_value == 0 && !self.resupply_pending {
                                     // Factory has already halted production before this point
-                                    println!(
-                                        "FactoryAI: Received notification: Inventory depleted."
-                                    );
+                                    say!("FactoryAI: Received notification: Inventory depleted.");
                                     self.request_restock(&channel)?; // Trigger resupply request
                                     self.resupply_pending = true; // Set pending flag to true
                                 }
-                                println!(
+                                say!(
                                     "FactoryAI: Update [Current Inventory: {} grams]",
                                     inventory_value
                                 );
                             } else {
-                                println!("FactoryAI: Invalid inventory message format.");
+                                say!("FactoryAI: Invalid inventory message format.");
                             }
                         }
                         _ if body.starts_with("RequestShipment:") => {
@@ -78,38 +86,40 @@ impl FactoryAI {
                                 .parse()
                                 .unwrap_or(-1);
                             if shipment_amount > 0 {
-                                println!(
+                                say!(
                                     "FactoryAI: Request Production Quota Reached: [Shipping to Retail]"
                                 );
                                 self.request_shipment(&channel, shipment_amount)?;
                             } else {
-                                println!("FactoryAI: Invalid shipment request format.");
+                                say!("FactoryAI: Invalid shipment request format.");
                             }
                         }
                         "RequestRestock" => {
                             // Ignore since FactoryAI already handles this case
-                            println!("FactoryAI: Ignoring duplicate restock request.");
+                            say!("FactoryAI: Ignoring duplicate restock request.");
                         }
                         "SupplyConfirmed" => {
-                            println!("FactoryAI: Update [Resupply Complete]");
+                            say!("FactoryAI: Update [Resupply Complete]");
                             self.send_message("RestockComplete", &channel)?;
-                            println!("FactoryAI: Inventory replenished.");
+                            say!("FactoryAI: Inventory replenished.");
                             self.resupply_pending = false; // Reset after successful resupply
                         }
                         "ShipmentConfirmed" => {
-                            println!("FactoryAI: Update [Shipping Complete]");
+                            say!("FactoryAI: Update [Shipping Complete]");
                         }
                         "EndSimulation" => {
-                            println!("FactoryAI: Simulation ending, shutting down...");
+                            say!("FactoryAI: Simulation ending, shutting down...");
+                            self.notify_end(&channel)?;
+                            consumer.ack(delivery)?;
                             break;
                         }
-                        _ => println!("FactoryAI: Unknown task request [{}]", body),
+                        _ => say!("FactoryAI: Unknown task request [{}]", body),
                     }
 
                     consumer.ack(delivery)?;
                 }
                 _ => {
-                    println!("FactoryAI: Consumer ended.");
+                    say!("FactoryAI: Consumer ended.");
                     break;
                 }
             }
@@ -124,7 +134,8 @@ impl FactoryAI {
             "RequestRestock".as_bytes(),
             "supplier_requests",
         ))?;
-        println!("FactoryAI: Notified Supplier [Resupply]");
+        say!("FactoryAI: Notified Supplier [Resupply]");
+        self.events.emit(Event::RestockRequested);
         self.resupply_pending = true; // Set pending flag to true
         Ok(())
     }
@@ -135,7 +146,20 @@ impl FactoryAI {
             format!("RequestShipment: {} grams", amount).as_bytes(),
             "shipment_requests",
         ))?;
-        println!("FactoryAI: Notified Retailer [Shipping]");
+        say!("FactoryAI: Notified Retailer [Shipping]");
+        Ok(())
+    }
+    // Releasing Supplier and Shipper once the factory is done
+    fn notify_end(&self, channel: &Channel) -> Result<()> {
+        let exchange = Exchange::direct(channel);
+        exchange.publish(Publish::new(
+            "EndSimulation".as_bytes(),
+            "supplier_requests",
+        ))?;
+        exchange.publish(Publish::new(
+            "EndSimulation".as_bytes(),
+            "shipment_requests",
+        ))?;
         Ok(())
     }
     // Publishing Factory Status Messages
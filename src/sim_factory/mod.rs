@@ -1,45 +1,316 @@
-mod factory;
-mod factory_ai;
-mod shipment;
-mod supplier;
+pub mod factory;
+pub mod factory_ai;
+pub mod shipment;
+pub mod supplier;
 
+use crate::clock::Clock;
+use crate::config::{check_chance, Config};
+use crate::events::{Event, EventLog};
+use crate::faults::{self, Faults};
+use crate::replay::Tape;
+use crate::rng::{RngSource, SimRng};
+use crate::sim_town::Links;
+use crate::simulation::{Outcome, Report, Simulation, Step};
+use crate::telemetry::Row;
+use anyhow::{anyhow, bail, Result};
 use factory::Factory;
 use factory_ai::FactoryAI;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use shipment::Shipment;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 use supplier::Supplier;
 
-pub fn run() {
-    // Clear relevant queues before starting
-    clear_queue("factory_status").unwrap();
-    clear_queue("supplier_requests").unwrap();
-    clear_queue("factory_ai").unwrap();
-    clear_queue("shipment_requests").unwrap();
-
-    // Start the Supplier in a separate thread
-    std::thread::spawn(move || {
-        let supplier = Supplier::new();
-        supplier.start().unwrap();
-    });
-
-    // Start the Shipment in a separate thread
-    std::thread::spawn(move || {
-        let shipment = Shipment::new();
-        shipment.start().unwrap();
-    });
-
-    // Start the FactoryAI in a separate thread
-    std::thread::spawn(move || {
-        let mut factory_ai = FactoryAI::new();
-        factory_ai.start_simulation().unwrap();
-    });
-
-    // Start the Factory process
-    let mut factory = Factory::new(5); // Set the number of cycles
-    factory.run().unwrap();
-
-    // Ensure that the threads are joined before the program exits
-    std::thread::sleep(std::time::Duration::from_secs(2)); // Allow time for other threads to finish
-    println!("Factory simulation terminated.");
+// Tunable parameters for a factory run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FactoryConfig {
+    pub max_cycles: i32, // Number of production cycles before the factory stops
+    pub initial_inventory_g: i32, // Beans in stock when the factory opens
+    pub batch_size_g: i32, // Beans used by one production cycle
+    pub shipment_threshold_g: i32, // Production that triggers a shipment to retail
+    pub restock_g: i32,  // Beans delivered by one restock
+    pub equipment_failure_chance: f64, // Chance per cycle of the production line breaking down
+    pub equipment_repair_secs: f64, // Time production is halted by a breakdown
+}
+
+impl Default for FactoryConfig {
+    fn default() -> Self {
+        FactoryConfig {
+            max_cycles: 5,
+            initial_inventory_g: 300,
+            batch_size_g: 100,
+            shipment_threshold_g: 200,
+            restock_g: 300,
+            equipment_failure_chance: 0.0,
+            equipment_repair_secs: 3.0,
+        }
+    }
+}
+
+impl FactoryConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_cycles < 0 {
+            bail!("factory.max_cycles must not be negative");
+        }
+        if self.batch_size_g <= 0 {
+            bail!("factory.batch_size_g must be positive");
+        }
+        if self.shipment_threshold_g <= 0 {
+            bail!("factory.shipment_threshold_g must be positive");
+        }
+        // FactoryAI only asks for a restock once the inventory is exactly empty
+        if self.initial_inventory_g <= 0 || self.initial_inventory_g % self.batch_size_g != 0 {
+            bail!(
+                "factory.initial_inventory_g must be a positive multiple of factory.batch_size_g"
+            );
+        }
+        if self.restock_g <= 0 || self.restock_g % self.batch_size_g != 0 {
+            bail!("factory.restock_g must be a positive multiple of factory.batch_size_g");
+        }
+        if !(self.equipment_repair_secs >= 0.0 && self.equipment_repair_secs.is_finite()) {
+            bail!("factory.equipment_repair_secs must be a non-negative number");
+        }
+        check_chance(
+            "factory.equipment_failure_chance",
+            self.equipment_failure_chance,
+        )
+    }
+}
+
+// Everything needed to carry on a factory run from where a snapshot left it
+#[derive(Serialize, Deserialize)]
+struct FactoryState {
+    elapsed_secs: f64,
+    inventory_g: i32,
+    produced_g: i32, // Produced since the last shipment
+    cycle: i32,
+    equipment_failures: u64,
+}
+
+pub struct FactorySimulation {
+    config: FactoryConfig,
+    restored: Option<FactoryState>, // Applied by the next `start`
+    faults: Faults,
+    rngs: RngSource,
+    fault_rng: SimRng,
+    failure_pending: bool, // An injected failure, taken at the start of the next cycle
+    failures: u64,
+    tape: Tape,
+    clock: Clock,
+    events: EventLog,
+    links: Links,
+    factory: Factory,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl FactorySimulation {
+    pub fn new() -> Self {
+        let config = FactoryConfig::default();
+        let rngs = RngSource::default();
+        FactorySimulation {
+            factory: Factory::new(
+                &config,
+                Clock::default(),
+                EventLog::default(),
+                Tape::default(),
+            ),
+            config,
+            restored: None,
+            faults: Faults::default(),
+            fault_rng: rngs.stream("faults"),
+            rngs,
+            failure_pending: false,
+            failures: 0,
+            tape: Tape::default(),
+            clock: Clock::default(),
+            events: EventLog::default(),
+            links: Links::default(),
+            workers: Vec::new(),
+        }
+    }
+
+    // Current production state, for embedders that drive the simulation step by step
+    pub fn factory(&self) -> &Factory {
+        &self.factory
+    }
+}
+
+impl Default for FactorySimulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulation for FactorySimulation {
+    fn name(&self) -> &'static str {
+        "factory"
+    }
+
+    fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<()> {
+        self.config = config.factory.clone();
+        self.faults = Faults::new(&config.faults);
+        self.rngs = RngSource::new(config.seed).with_tape(config.tape.clone());
+        self.tape = config.tape.clone();
+        self.clock = config.links.clock(config.clock);
+        self.events = config.events.scoped(self.name(), Some(self.clock.clone()));
+        self.links = config.links.clone();
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        // Clear relevant queues before starting
+        clear_queue("factory_status")?;
+        clear_queue("supplier_requests")?;
+        clear_queue("factory_ai")?;
+        clear_queue("shipment_requests")?;
+
+        // Start the Supplier in a separate thread
+        let tape = self.tape.clone();
+        self.workers.push(thread::spawn(move || {
+            let supplier = Supplier::new(tape);
+            supplier.start().unwrap();
+        }));
+
+        // Start the Shipment in a separate thread
+        let (events, tape) = (self.events.clone(), self.tape.clone());
+        self.workers.push(thread::spawn(move || {
+            let shipment = Shipment::new(events, tape);
+            shipment.start().unwrap();
+        }));
+
+        // Start the FactoryAI in a separate thread
+        let (events, tape) = (self.events.clone(), self.tape.clone());
+        self.workers.push(thread::spawn(move || {
+            let mut factory_ai = FactoryAI::new(events, tape);
+            factory_ai.start_simulation().unwrap();
+        }));
+
+        // Workers only react to messages, so only the factory itself paces the clock
+        self.factory = Factory::new(
+            &self.config,
+            self.clock.clone(),
+            self.events.clone(),
+            self.tape.clone(),
+        );
+        self.fault_rng = self.rngs.stream("faults");
+        self.failure_pending = false;
+        self.failures = 0;
+        if let Some(state) = self.restored.take() {
+            self.clock
+                .resume_at(Duration::from_secs_f64(state.elapsed_secs));
+            self.failures = state.equipment_failures;
+            self.factory
+                .resume(state.inventory_g, state.produced_g, state.cycle)?;
+        }
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<Step> {
+        // The line can break down before a cycle; production waits for the repair
+        let cycle = self.factory.current_cycle() as u64 + 1;
+        let chance = self.config.equipment_failure_chance;
+        let struck = self
+            .faults
+            .strikes("equipment_failure", cycle, chance, &mut self.fault_rng);
+        if (struck || self.failure_pending) && !self.factory.is_finished() {
+            self.failure_pending = false;
+            self.failures += 1;
+            say!(
+                "Factory: Equipment failure! Production halted for {}s of repairs.",
+                self.config.equipment_repair_secs
+            );
+            self.events.emit(Event::EquipmentFailure {
+                repair_secs: self.config.equipment_repair_secs,
+            });
+            self.clock
+                .sleep(Duration::from_secs_f64(self.config.equipment_repair_secs));
+        }
+
+        // Short of power, the line runs slower; the shortfall is taken as a pause before the cycle
+        let power = self.links.power_factor();
+        if power < 1.0 && !self.factory.is_finished() {
+            say!(
+                "Factory: Low power, running at {:.0}% speed.",
+                power * 100.0
+            );
+            self.clock.sleep(Duration::from_secs_f64(1.0 / power - 1.0));
+        }
+
+        if self.factory.run_cycle()? {
+            Ok(Step::Finished(Outcome::Completed))
+        } else {
+            Ok(Step::Continue)
+        }
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        // The end signal lets every worker leave its consumer loop; send it if the run was cut short
+        if !self.factory.is_finished() && !self.workers.is_empty() {
+            self.factory.send_end_signal()?;
+        }
+        for worker in self.workers.drain(..) {
+            worker
+                .join()
+                .map_err(|_| anyhow!("Factory worker thread panicked"))?;
+        }
+        say!("Factory simulation terminated.");
+        Ok(())
+    }
+
+    fn telemetry(&self) -> Result<Row> {
+        Ok(vec![
+            ("inventory_g", self.factory.inventory().to_string()),
+            ("produced_g", self.factory.total_produced().to_string()),
+            ("equipment_failures", self.failures.to_string()),
+        ])
+    }
+
+    fn inject(&mut self, event: &str) -> Result<()> {
+        match event {
+            "equipment_failure" => self.failure_pending = true,
+            _ => bail!(
+                "The factory simulation can inject {}, not '{}'",
+                faults::names(self.name()).join(", "),
+                event
+            ),
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Value> {
+        Ok(serde_json::to_value(FactoryState {
+            elapsed_secs: self.clock.now().as_secs_f64(),
+            inventory_g: self.factory.inventory(),
+            produced_g: self.factory.total_produced(),
+            cycle: self.factory.current_cycle(),
+            equipment_failures: self.failures,
+        })?)
+    }
+
+    fn restore(&mut self, state: &Value) -> Result<()> {
+        self.restored = Some(FactoryState::deserialize(state)?);
+        Ok(())
+    }
+
+    fn report(&self) -> Report {
+        Report {
+            simulation: self.name(),
+            outcome: Outcome::Completed,
+            seed: self.rngs.seed(),
+            ticks: self.factory.current_cycle() as u64,
+            elapsed_secs: self.clock.now().as_secs_f64(),
+            stats: vec![
+                ("inventory_g".into(), self.factory.inventory() as f64),
+                ("equipment_failures".into(), self.failures as f64),
+            ],
+        }
+    }
 }
 
 // Function to clear RabbitMQ queues
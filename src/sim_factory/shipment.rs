@@ -3,12 +3,18 @@ use amiquip::{
     Result,
 };
 
+use crate::events::{Event, EventLog};
+use crate::replay::Tape;
+
 //Struct for Shipment Function
-pub struct Shipment;
+pub struct Shipment {
+    events: EventLog,
+    tape: Tape,
+}
 
 impl Shipment {
-    pub fn new() -> Self {
-        Shipment {}
+    pub fn new(events: EventLog, tape: Tape) -> Self {
+        Shipment { events, tape }
     }
     // Start Listening for Shipping Request
     pub fn start(&self) -> Result<()> {
@@ -16,28 +22,33 @@ impl Shipment {
         let channel = connection.open_channel(None)?;
         let queue = channel.queue_declare("shipment_requests", QueueDeclareOptions::default())?;
         let consumer = queue.consume(ConsumerOptions::default())?;
-        println!("Shipment: Waiting for shipment requests...");
+        say!("Shipment: Waiting for shipment requests...");
 
         //Logic for Keyword
         for message in consumer.receiver().iter() {
             match message {
                 ConsumerMessage::Delivery(delivery) => {
-                    let body = String::from_utf8_lossy(&delivery.body);
+                    let body = self.tape.input("shipment_requests", || {
+                        String::from_utf8_lossy(&delivery.body).into_owned()
+                    });
                     if body.starts_with("RequestShipment:") {
                         let amount: i32 = body["RequestShipment: ".len()..body.len() - 6]
                             .parse()
                             .unwrap();
-                        println!("Shipment: Received Notification [Shipping]");
-                        println!("Shipment: Processing [Shipping] of {} grams...", amount);
+                        say!("Shipment: Received Notification [Shipping]");
+                        say!("Shipment: Processing [Shipping] of {} grams...", amount);
                         self.send_shipment_confirmation(&channel)?;
-                        println!("Shipment: Completed [Shipping]");
-                        break; // Exit after processing the shipment
+                        say!("Shipment: Completed [Shipping]");
+                        self.events.emit(Event::ShipmentCompleted { grams: amount });
+                    } else if body == "EndSimulation" {
+                        consumer.ack(delivery)?;
+                        break; // Stop listening once the factory has finished
                     }
 
                     consumer.ack(delivery)?;
                 }
                 _ => {
-                    println!("Shipment: Consumer ended.");
+                    say!("Shipment: Consumer ended.");
                     break;
                 }
             }
@@ -2,12 +2,16 @@ use amiquip::{
     Connection, ConsumerMessage, ConsumerOptions, Exchange, Publish, QueueDeclareOptions, Result,
 };
 
+use crate::replay::Tape;
+
 // Struct for Supplier
-pub struct Supplier;
+pub struct Supplier {
+    tape: Tape,
+}
 
 impl Supplier {
-    pub fn new() -> Self {
-        Supplier {}
+    pub fn new(tape: Tape) -> Self {
+        Supplier { tape }
     }
 
     // Listening for Supply Requests + Timeout safety function
@@ -16,7 +20,7 @@ impl Supplier {
             match self.listen_for_restock_request() {
                 Ok(_) => break,
                 Err(e) => {
-                    println!("Supplier: Error encountered: {}. Retrying...", e);
+                    say!("Supplier: Error encountered: {}. Retrying...", e);
                     std::thread::sleep(std::time::Duration::from_secs(2));
                 }
             }
@@ -29,24 +33,28 @@ impl Supplier {
         let channel = connection.open_channel(None)?;
         let queue = channel.queue_declare("supplier_requests", QueueDeclareOptions::default())?;
         let consumer = queue.consume(ConsumerOptions::default())?;
-        println!("Supplier: Waiting for restock requests...");
+        say!("Supplier: Waiting for restock requests...");
 
         for message in consumer.receiver().iter() {
             match message {
                 ConsumerMessage::Delivery(delivery) => {
-                    let body = String::from_utf8_lossy(&delivery.body);
+                    let body = self.tape.input("supplier_requests", || {
+                        String::from_utf8_lossy(&delivery.body).into_owned()
+                    });
                     if body == "RequestRestock" {
-                        println!("Supplier: Received Notification [Resupply]");
-                        println!("Supplier: Processing [Resupply]...");
+                        say!("Supplier: Received Notification [Resupply]");
+                        say!("Supplier: Processing [Resupply]...");
                         self.send_supply_confirmation(&channel)?;
-                        println!("Supplier: Completed [Resupply]");
-                        break; // Exit the loop after processing the restock
+                        say!("Supplier: Completed [Resupply]");
+                    } else if body == "EndSimulation" {
+                        consumer.ack(delivery)?;
+                        break; // Stop listening once the factory has finished
                     }
 
                     consumer.ack(delivery)?;
                 }
                 _ => {
-                    println!("Supplier: Consumer ended.");
+                    say!("Supplier: Consumer ended.");
                     break;
                 }
             }
@@ -1,15 +1,46 @@
 extern crate anyhow;
 extern crate chrono;
 extern crate rand;
-extern crate scheduled_thread_pool;
 
-use anyhow::Result;
-use rand::Rng;
-use scheduled_thread_pool::ScheduledThreadPool;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use crate::clock::Clock;
+use crate::config::{check_chance, Config};
+use crate::events::{Event, EventLog};
+use crate::faults::{self, Faults};
+use crate::replay::Tape;
+use crate::rng::{RngSource, SimRng};
+use crate::sim_town::Links;
+use crate::simulation::{Outcome, Report, Simulation, Step};
+use crate::telemetry::Row;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::time::Duration;
 
-#[derive(Clone)]
+// Periodic tasks driven by the daily schedule
+const TASKS: [Task; 16] = [
+    Task::LightsOn,
+    Task::LightsOff,
+    Task::CoffeeMorning,
+    Task::CoffeeNoon,
+    Task::CoffeeEvening,
+    Task::ThermostatCoolMorning,
+    Task::ThermostatModerateNoon,
+    Task::ThermostatWarmEvening,
+    Task::ThermostatCoolNight,
+    Task::SprinklersMorning,
+    Task::SecurityArmedNight,
+    Task::SecurityDisarmedMorning,
+    Task::AIGoodMorning,
+    Task::AIGoodNight,
+    Task::PlayLofiMusic,
+    Task::PlayAmbientMusic,
+];
+
+// Real time covered by one simulation step
+const TICK: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
 pub enum Task {
     LightsOn,
     LightsOff,
@@ -55,6 +86,48 @@ impl Task {
         }
     }
 
+    // Simulated times (seconds since the start of day 0) at which this task runs within [from, to)
+    pub fn firings(&self, from: f64, to: f64) -> Vec<f64> {
+        let (start_time, interval) = self.schedule();
+        if interval <= 0.0 {
+            return Vec::new(); // Aperiodic tasks are never scheduled
+        }
+
+        let first = if from <= start_time {
+            0
+        } else {
+            ((from - start_time) / interval).ceil() as u64
+        };
+        (first..)
+            .map(|k| start_time + k as f64 * interval)
+            .take_while(|time| *time < to)
+            .collect()
+    }
+
+    // The device a task acts on and the state it leaves it in
+    pub fn device_state(&self) -> (&'static str, &'static str) {
+        match *self {
+            Task::LightsOn => ("Lights", "On"),
+            Task::LightsOff => ("Lights", "Off"),
+            Task::CoffeeMorning => ("Coffee machine", "Morning brew"),
+            Task::CoffeeNoon => ("Coffee machine", "Noon brew"),
+            Task::CoffeeEvening => ("Coffee machine", "Evening brew"),
+            Task::ThermostatCoolMorning => ("Thermostat", "Cool (65°F)"),
+            Task::ThermostatModerateNoon => ("Thermostat", "Moderate (72°F)"),
+            Task::ThermostatWarmEvening => ("Thermostat", "Warm (75°F)"),
+            Task::ThermostatCoolNight => ("Thermostat", "Cool (68°F)"),
+            Task::SprinklersMorning => ("Sprinklers", "Watering"),
+            Task::SecurityArmedNight => ("Security", "Armed"),
+            Task::SecurityDisarmedMorning => ("Security", "Disarmed"),
+            Task::AIGoodMorning => ("AI assistant", "Good morning!"),
+            Task::AIGoodNight => ("AI assistant", "Good night!"),
+            Task::PlayLofiMusic => ("Music", "Lofi"),
+            Task::PlayAmbientMusic => ("Music", "Ambient"),
+            Task::IntrusionDetected => ("Security", "Intruder detected"),
+            Task::DeadlyWeatherWarning => ("Power", "Emergency only"),
+        }
+    }
+
     pub fn description(&self) -> &'static str {
         match *self {
             Task::LightsOn => "6 PM: Lights turned ON",
@@ -82,21 +155,24 @@ impl Task {
 }
 
 pub struct LastEvent {
-    pub time: Instant,
+    pub time: Option<Duration>, // Clock time of the last event; None allows an immediate event
     pub cooldown_duration: Duration,
 }
 
 impl LastEvent {
     pub fn new(cooldown: u64) -> Self {
         LastEvent {
-            time: Instant::now() - Duration::from_secs(cooldown), // Initialize to allow immediate event
+            time: None,
             cooldown_duration: Duration::from_secs(cooldown),
         }
     }
 
-    pub fn trigger(&mut self) -> bool {
-        if self.time.elapsed() >= self.cooldown_duration {
-            self.time = Instant::now();
+    pub fn trigger(&mut self, now: Duration) -> bool {
+        if self
+            .time
+            .is_none_or(|time| now.saturating_sub(time) >= self.cooldown_duration)
+        {
+            self.time = Some(now);
             true
         } else {
             false
@@ -104,118 +180,319 @@ impl LastEvent {
     }
 }
 
-pub fn schedule_task(
-    sched: &ScheduledThreadPool,
-    task: Task,
-    scale_factor: f64,
-    last_task_time: Arc<Mutex<(Instant, bool)>>,
-) -> Result<()> {
-    let (start_time, interval) = task.schedule();
-    let task_description = task.description();
-
-    sched.execute_at_fixed_rate(
-        Duration::from_secs_f64(start_time / scale_factor),
-        Duration::from_secs_f64(interval / scale_factor),
-        move || {
-            let mut last_time = last_task_time.lock().unwrap();
-            last_time.0 = Instant::now();
-            last_time.1 = false; // Reset idle state
-            println!("{}", task_description);
-        },
-    );
-
-    Ok(())
+// Tunable parameters for a smart home run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HomeConfig {
+    pub scale_factor: f64,                  // Simulated seconds per real second
+    pub duration_secs: u64,                 // Real seconds to keep the simulation running
+    pub intrusion_chance: f64,              // Chance of an intrusion per second
+    pub intrusion_cooldown_secs: u64,       // Minimum time between two intrusions
+    pub weather_warning_chance: f64,        // Chance of a deadly weather warning per second
+    pub weather_warning_cooldown_secs: u64, // Minimum time between two weather warnings
 }
 
-pub fn run() -> Result<()> {
-    let sched = ScheduledThreadPool::new(8);
-    let scale_factor = 2880.0; // Scale factor to simulate a day in 30 seconds
-    let last_task_time = Arc::new(Mutex::new((Instant::now(), false)));
-
-    let tasks = [
-        Task::LightsOn,
-        Task::LightsOff,
-        Task::CoffeeMorning,
-        Task::CoffeeNoon,
-        Task::CoffeeEvening,
-        Task::ThermostatCoolMorning,
-        Task::ThermostatModerateNoon,
-        Task::ThermostatWarmEvening,
-        Task::ThermostatCoolNight,
-        Task::SprinklersMorning,
-        Task::SecurityArmedNight,
-        Task::SecurityDisarmedMorning,
-        Task::AIGoodMorning,
-        Task::AIGoodNight,
-        Task::PlayLofiMusic,
-        Task::PlayAmbientMusic,
-    ];
-
-    // Schedule periodic tasks
-    for task in tasks.iter() {
-        schedule_task(
-            &sched,
-            task.clone(),
-            scale_factor,
-            Arc::clone(&last_task_time),
-        )?;
+impl Default for HomeConfig {
+    fn default() -> Self {
+        HomeConfig {
+            scale_factor: 2880.0, // Scale factor to simulate a day in 30 seconds
+            duration_secs: 30,    // 1 day * 30 seconds/day = 30 seconds
+            intrusion_chance: 0.2,
+            intrusion_cooldown_secs: 10,
+            weather_warning_chance: 0.05,
+            weather_warning_cooldown_secs: 20,
+        }
     }
+}
+
+impl HomeConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(self.scale_factor > 0.0 && self.scale_factor.is_finite()) {
+            bail!("home.scale_factor must be a positive number");
+        }
+        check_chance("home.intrusion_chance", self.intrusion_chance)?;
+        check_chance("home.weather_warning_chance", self.weather_warning_chance)
+    }
+}
+
+// Everything needed to carry on a smart home run from where a snapshot left it
+#[derive(Serialize, Deserialize)]
+struct HomeState {
+    elapsed_secs: f64,
+    ticks: u64,
+    idle: bool,
+    tasks_executed: u64,
+    intrusions: u64,
+    weather_warnings: u64,
+    last_intrusion_secs: Option<f64>,
+    last_weather_warning_secs: Option<f64>,
+    devices: BTreeMap<String, String>,
+}
 
+pub struct HomeSimulation {
+    config: HomeConfig,
+    restored: Option<HomeState>, // Applied by the next `start`
+    faults: Faults,
+    rngs: RngSource,
+    rng: SimRng, // Drives the sporadic intrusion and weather events
+    tape: Tape,
+    clock: Clock,
+    events: EventLog,
+    links: Links,
+    ticks: u64,
+    idle: bool, // Whether "IDLE" has been printed since the last task ran
+    tasks_executed: u64,
+    intrusions: u64,
+    weather_warnings: u64,
+    devices: BTreeMap<String, String>, // Latest state set by the schedule, by device
     // Separate cooldown mechanisms for aperiodic and sporadic events
-    let last_intrusion = Arc::new(Mutex::new(LastEvent::new(10))); // Cooldown of 10 seconds for intrusion
-    let last_weather_warning = Arc::new(Mutex::new(LastEvent::new(20))); // Cooldown of 20 seconds for weather warning
-
-    let sporadic_events = move || {
-        let mut rng = rand::thread_rng();
-        let mut intrusion_lock = last_intrusion.lock().unwrap();
-        let mut weather_lock = last_weather_warning.lock().unwrap();
-
-        if rng.gen_bool(0.2) && intrusion_lock.trigger() {
-            let task = Task::IntrusionDetected;
-            println!("{}", task.description());
-            println!("---------------------------------");
-            println!("Doors locked");
-            println!("Cameras recording for authorities");
-            println!("Message to intruder: You are trespassing!");
-            println!("Porch floodlights turned ON");
-            println!("---------------------------------");
-        } else if rng.gen_bool(0.05) && weather_lock.trigger() {
-            let task = Task::DeadlyWeatherWarning;
-            println!("{}", task.description());
-            println!("---------------------------------");
-            println!("Basement bunker lights ON");
-            println!("Switching electricity to emergency only");
-            println!("Message to all family members: Head to the bunker!");
-            println!("---------------------------------");
-        }
-    };
-
-    sched.execute_at_fixed_rate(
-        Duration::from_secs(1),
-        Duration::from_secs(1),
-        sporadic_events,
-    );
-
-    // Task to print "IDLE" if no task has run for the past second
-    sched.execute_at_fixed_rate(Duration::from_secs(1), Duration::from_secs(1), move || {
-        let mut last_time = last_task_time.lock().unwrap();
-        if last_time.0.elapsed() >= Duration::from_secs(1) && !last_time.1 {
-            println!("IDLE");
-            last_time.1 = true; // Set idle state to true
-        }
-    });
-
-    println!("Smart home simulation started.");
-
-    let start_time = Instant::now();
-    let duration = Duration::from_secs(30); // 1 day * 30 seconds/day = 30 seconds
-
-    // Main loop for running the simulation for 1 day (30 seconds)
-    while Instant::now().duration_since(start_time) < duration {
-        std::thread::sleep(Duration::from_secs(1));
-    }
-
-    println!("Smart home simulation ended.");
-
-    Ok(())
+    last_intrusion: LastEvent,
+    last_weather_warning: LastEvent,
+}
+
+impl HomeSimulation {
+    pub fn new() -> Self {
+        let rngs = RngSource::default();
+        HomeSimulation {
+            config: HomeConfig::default(),
+            restored: None,
+            faults: Faults::default(),
+            rng: rngs.stream("sporadic_events"),
+            rngs,
+            tape: Tape::default(),
+            clock: Clock::default(),
+            events: EventLog::default(),
+            links: Links::default(),
+            ticks: 0,
+            idle: false,
+            tasks_executed: 0,
+            intrusions: 0,
+            weather_warnings: 0,
+            devices: BTreeMap::new(),
+            last_intrusion: LastEvent::new(10), // Cooldown of 10 seconds for intrusion
+            last_weather_warning: LastEvent::new(20), // Cooldown of 20 seconds for weather warning
+        }
+    }
+
+    // Latest state the schedule has set for each device, for embedders
+    pub fn devices(&self) -> &BTreeMap<String, String> {
+        &self.devices
+    }
+
+    // Run every periodic task whose scheduled time falls within this step
+    fn run_scheduled_tasks(&mut self) -> bool {
+        let from = (self.ticks - 1) as f64 * self.config.scale_factor;
+        let to = self.ticks as f64 * self.config.scale_factor;
+
+        let mut due: Vec<(f64, &Task)> = TASKS
+            .iter()
+            .flat_map(|task| task.firings(from, to).into_iter().map(move |t| (t, task)))
+            .collect();
+        due.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        for (_, task) in &due {
+            say!("{}", task.description());
+            let (device, state) = task.device_state();
+            self.devices.insert(device.to_string(), state.to_string());
+            self.events.emit(Event::HomeTaskExecuted {
+                task: format!("{:?}", task),
+                device: device.to_string(),
+                state: state.to_string(),
+            });
+        }
+        self.tasks_executed += due.len() as u64;
+        !due.is_empty()
+    }
+
+    fn sporadic_events(&mut self) {
+        let now = self.tape.input("uptime", || self.clock.now());
+
+        // In a town the weather machine's catastrophes raise the warnings instead of chance
+        let tick = self.ticks;
+        let weather_warning_chance = if self.links.is_linked() {
+            0.0
+        } else {
+            self.config.weather_warning_chance
+        };
+        if self.faults.strikes(
+            "intrusion",
+            tick,
+            self.config.intrusion_chance,
+            &mut self.rng,
+        ) && self.last_intrusion.trigger(now)
+        {
+            self.intrusion();
+        } else if (self.faults.strikes(
+            "weather_warning",
+            tick,
+            weather_warning_chance,
+            &mut self.rng,
+        ) && self.last_weather_warning.trigger(now))
+            || self.links.take_weather_alert()
+        {
+            self.weather_warning();
+        }
+    }
+
+    fn intrusion(&mut self) {
+        let task = Task::IntrusionDetected;
+        say!("{}", task.description());
+        say!("---------------------------------");
+        say!("Doors locked");
+        say!("Cameras recording for authorities");
+        say!("Message to intruder: You are trespassing!");
+        say!("Porch floodlights turned ON");
+        say!("---------------------------------");
+        self.intrusions += 1;
+        self.events.emit(Event::Intrusion);
+    }
+
+    fn weather_warning(&mut self) {
+        let task = Task::DeadlyWeatherWarning;
+        say!("{}", task.description());
+        say!("---------------------------------");
+        say!("Basement bunker lights ON");
+        say!("Switching electricity to emergency only");
+        say!("Message to all family members: Head to the bunker!");
+        say!("---------------------------------");
+        self.weather_warnings += 1;
+        self.events.emit(Event::DeadlyWeatherWarning);
+    }
+}
+
+impl Default for HomeSimulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulation for HomeSimulation {
+    fn name(&self) -> &'static str {
+        "home"
+    }
+
+    fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<()> {
+        self.config = config.home.clone();
+        self.faults = Faults::new(&config.faults);
+        self.rngs = RngSource::new(config.seed).with_tape(config.tape.clone());
+        self.tape = config.tape.clone();
+        self.clock = config.links.clock(config.clock);
+        self.events = config.events.scoped(self.name(), Some(self.clock.clone()));
+        self.links = config.links.clone();
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.rng = self.rngs.stream("sporadic_events");
+        self.ticks = 0;
+        self.idle = false;
+        self.tasks_executed = 0;
+        self.intrusions = 0;
+        self.weather_warnings = 0;
+        self.last_intrusion = LastEvent::new(self.config.intrusion_cooldown_secs);
+        self.last_weather_warning = LastEvent::new(self.config.weather_warning_cooldown_secs);
+        self.devices.clear();
+        if let Some(state) = self.restored.take() {
+            self.ticks = state.ticks;
+            self.idle = state.idle;
+            self.tasks_executed = state.tasks_executed;
+            self.intrusions = state.intrusions;
+            self.weather_warnings = state.weather_warnings;
+            self.last_intrusion.time = state.last_intrusion_secs.map(Duration::from_secs_f64);
+            self.last_weather_warning.time =
+                state.last_weather_warning_secs.map(Duration::from_secs_f64);
+            self.devices = state.devices;
+            self.clock
+                .resume_at(Duration::from_secs_f64(state.elapsed_secs));
+        }
+        say!("Smart home simulation started.");
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<Step> {
+        if self.ticks >= self.config.duration_secs {
+            return Ok(Step::Finished(Outcome::Completed));
+        }
+
+        self.clock.sleep(TICK);
+        self.ticks += 1;
+
+        let ran_task = self.run_scheduled_tasks();
+        self.sporadic_events();
+
+        // Print "IDLE" if no task has run for the past second
+        if ran_task {
+            self.idle = false; // Reset idle state
+        } else if !self.idle {
+            say!("IDLE");
+            self.idle = true; // Set idle state to true
+        }
+
+        Ok(Step::Continue)
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        say!("Smart home simulation ended.");
+        Ok(())
+    }
+
+    fn telemetry(&self) -> Result<Row> {
+        Ok(vec![
+            ("tasks_executed", self.tasks_executed.to_string()),
+            ("intrusions", self.intrusions.to_string()),
+            ("weather_warnings", self.weather_warnings.to_string()),
+            ("idle", self.idle.to_string()),
+        ])
+    }
+
+    fn inject(&mut self, event: &str) -> Result<()> {
+        match event {
+            "intrusion" => self.intrusion(),
+            "weather_warning" => self.weather_warning(),
+            _ => bail!(
+                "The home simulation can inject {}, not '{}'",
+                faults::names(self.name()).join(", "),
+                event
+            ),
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Value> {
+        Ok(serde_json::to_value(HomeState {
+            elapsed_secs: self.clock.now().as_secs_f64(),
+            ticks: self.ticks,
+            idle: self.idle,
+            tasks_executed: self.tasks_executed,
+            intrusions: self.intrusions,
+            weather_warnings: self.weather_warnings,
+            last_intrusion_secs: self.last_intrusion.time.map(|t| t.as_secs_f64()),
+            last_weather_warning_secs: self.last_weather_warning.time.map(|t| t.as_secs_f64()),
+            devices: self.devices.clone(),
+        })?)
+    }
+
+    fn restore(&mut self, state: &Value) -> Result<()> {
+        self.restored = Some(HomeState::deserialize(state)?);
+        Ok(())
+    }
+
+    fn report(&self) -> Report {
+        Report {
+            simulation: self.name(),
+            outcome: Outcome::Completed,
+            seed: self.rngs.seed(),
+            ticks: self.ticks,
+            elapsed_secs: self.clock.now().as_secs_f64(),
+            stats: vec![
+                ("tasks_executed".into(), self.tasks_executed as f64),
+                ("intrusions".into(), self.intrusions as f64),
+                ("weather_warnings".into(), self.weather_warnings as f64),
+            ],
+        }
+    }
 }
@@ -1,127 +1,161 @@
 use super::reactor::Reactor; // Change from `crate::reactor` to `super::reactor`
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
-use tokio::sync::mpsc;
-use tokio::time::{sleep, Duration};
-
-pub struct Control;
+use super::NuclearConfig;
+use crate::clock::Clock;
+use crate::events::{Event, EventLog};
+use crate::simulation::{Outcome, Step};
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+// Range the control room keeps both temperature (°C) and power (w) within during normal operation
+pub const TARGET_BAND: RangeInclusive<i32> = 180..=220;
+
+pub struct Control {
+    clock: Clock,
+    events: EventLog,
+    scram_power: i32,
+    evacuation_radiation: i32,
+    previous_power: i32,
+    previous_temperature: i32,
+    actions: Vec<&'static str>, // Taken during the latest reading, for telemetry
+}
 
 impl Control {
-    pub fn new() -> Self {
-        Control
+    pub fn new(config: &NuclearConfig, reactor: &Reactor, clock: Clock, events: EventLog) -> Self {
+        Control {
+            clock,
+            events,
+            scram_power: config.scram_power_w,
+            evacuation_radiation: config.evacuation_radiation_bq,
+            previous_power: reactor.get_power_output(),
+            previous_temperature: reactor.get_temperature(),
+            actions: Vec::new(),
+        }
     }
 
-    pub async fn monitor_and_regulate(
-        &self,
-        reactor: &Reactor,
-        shutdown_flag: Arc<AtomicBool>,
-        mut control_rx: mpsc::Receiver<()>,
-    ) {
-        let mut previous_power = reactor.get_power_output();
-        let mut previous_temperature = reactor.get_temperature();
-
-        while (control_rx.recv().await).is_some() {
-            let temp = reactor.get_temperature();
-            let power = reactor.get_power_output();
-            let radiation = reactor.get_radiation_level();
-
-            println!(
-                "CtrlRoom: T: {}°C | P: {}w | R: {} Bq",
-                temp, power, radiation
+    // Inspect the latest reactor readings and regulate; Finished means the reactor was shut down
+    pub fn monitor_and_regulate(&mut self, reactor: &Reactor) -> Step {
+        let temp = reactor.get_temperature();
+        let power = reactor.get_power_output();
+        let radiation = reactor.get_radiation_level();
+        self.actions.clear();
+
+        say!(
+            "CtrlRoom: T: {}°C | P: {}w | R: {} Bq",
+            temp,
+            power,
+            radiation
+        );
+
+        // Detect power surge by comparing the current and previous power levels
+        let power_change = power - self.previous_power;
+
+        if power_change > 50 {
+            say!(
+                "\n* CtrlRoom: Power Surge Detected! Increase of {}w",
+                power_change
+            );
+
+            let reduction_amount = power_change.min(60); // Cap the reduction at 60w
+            reactor.decrease_power_output(reduction_amount);
+            self.actions.push("emergency_power_reduction");
+            self.events.emit(Event::EmergencyPowerReduction {
+                amount_w: reduction_amount,
+            });
+            say!(
+                " - Action: Emergency Power Reduction | Decreasing by {}w\n",
+                reduction_amount
+            );
+        }
+
+        // Detect temperature spike by comparing the current and previous temperature levels
+        let temp_change = temp - self.previous_temperature;
+
+        if temp_change > 50 {
+            say!(
+                "\n* CtrlRoom: Temperature Spike Detected! Increase of {}°C",
+                temp_change
+            );
+
+            let cooling_amount = temp_change.min(60); // Cap the cooling at 60°C
+            reactor.decrease_temperature(cooling_amount);
+            self.actions.push("emergency_cooling");
+            self.events.emit(Event::EmergencyCooling {
+                amount_c: cooling_amount,
+            });
+            say!(
+                " - Action: Emergency Cooling Activated | Reducing by {}°C\n",
+                cooling_amount
             );
+        }
+
+        // Detect radiation leak
+        if radiation > self.evacuation_radiation {
+            say!("\n==================== Critical Failure ====================\n");
+            say!(
+                "CtrlRoom: Major Radiation Leak Detected! Initiating Full Shutdown and Evacuation!\n"
+            );
+            reactor.initiate_shutdown(); // Trigger the reactor shutdown
+            self.initiate_evacuation();
+            self.actions.push("evacuation");
+            self.events.emit(Event::Evacuation);
+            return Step::Finished(Outcome::Evacuation);
+        }
+
+        // Check for critical conditions first
+        if power >= self.scram_power {
+            say!("\n==================== Critical Failure ====================\n");
+            say!("CtrlRoom: Critical P Reached! Initiating Shutdown!\n");
+            reactor.initiate_shutdown(); // Trigger the reactor shutdown
+            self.actions.push("scram");
+            self.events.emit(Event::Scram { power_w: power });
+            return Step::Finished(Outcome::Scram);
+        }
 
-            // Detect power surge by comparing the current and previous power levels
-            let power_change = power - previous_power;
-
-            if power_change > 50 {
-                println!(
-                    "\n* CtrlRoom: Power Surge Detected! Increase of {}w",
-                    power_change
-                );
-
-                let reduction_amount = power_change.min(60); // Cap the reduction at 60w
-                reactor.decrease_power_output(reduction_amount);
-                println!(
-                    " - Action: Emergency Power Reduction | Decreasing by {}w\n",
-                    reduction_amount
-                );
-            }
-
-            // Detect temperature spike by comparing the current and previous temperature levels
-            let temp_change = temp - previous_temperature;
-
-            if temp_change > 50 {
-                println!(
-                    "\n* CtrlRoom: Temperature Spike Detected! Increase of {}°C",
-                    temp_change
-                );
-
-                let cooling_amount = temp_change.min(60); // Cap the cooling at 60°C
-                reactor.decrease_temperature(cooling_amount);
-                println!(
-                    " - Action: Emergency Cooling Activated | Reducing by {}°C\n",
-                    cooling_amount
-                );
-            }
-
-            // Detect radiation leak
-            if radiation > 5000 {
-                println!("\n==================== Critical Failure ====================\n");
-                println!(
-                    "CtrlRoom: Major Radiation Leak Detected! Initiating Full Shutdown and Evacuation!\n"
-                );
-                reactor.initiate_shutdown(); // Trigger the reactor shutdown
-                shutdown_flag.store(true, Ordering::SeqCst);
-                self.initiate_evacuation().await;
-                break;
-            }
-
-            // Check for critical conditions first
-            if power >= 300 {
-                println!("\n==================== Critical Failure ====================\n");
-                println!("CtrlRoom: Critical P Reached! Initiating Shutdown!\n");
-                shutdown_flag.store(true, Ordering::SeqCst);
-                reactor.initiate_shutdown(); // Trigger the reactor shutdown
-                break;
-            }
-
-            // Normal regulation logic
-            if temp < 150 {
-                reactor.increase_temperature(20);
-                println!(" - Action: Temp Low | Increasing");
-            } else if temp > 250 {
-                reactor.decrease_temperature(20);
-                println!(" - Action: Temp High | Decreasing");
-            } else if !(180..=220).contains(&temp) {
-                reactor.increase_temperature(5);
-                println!(" - Status: Keeping T Stable");
-            }
-
-            if power < 150 {
-                reactor.increase_power_output(20);
-                println!(" - Action: Power Low | Increasing");
-            } else if power > 250 && power < 300 {
-                reactor.decrease_power_output(20);
-                println!(" - Action: Power High | Decreasing");
-            } else if !(180..=220).contains(&power) {
-                reactor.increase_power_output(5);
-                println!(" - Status: Keeping P Stable");
-            }
-
-            previous_power = power;
-            previous_temperature = temp;
+        // Normal regulation logic
+        if temp < 150 {
+            reactor.increase_temperature(20);
+            self.actions.push("increase_temperature");
+            say!(" - Action: Temp Low | Increasing");
+        } else if temp > 250 {
+            reactor.decrease_temperature(20);
+            self.actions.push("decrease_temperature");
+            say!(" - Action: Temp High | Decreasing");
+        } else if !TARGET_BAND.contains(&temp) {
+            reactor.increase_temperature(5);
+            self.actions.push("stabilise_temperature");
+            say!(" - Status: Keeping T Stable");
         }
+
+        if power < 150 {
+            reactor.increase_power_output(20);
+            self.actions.push("increase_power");
+            say!(" - Action: Power Low | Increasing");
+        } else if power > 250 && power < 300 {
+            reactor.decrease_power_output(20);
+            self.actions.push("decrease_power");
+            say!(" - Action: Power High | Decreasing");
+        } else if !TARGET_BAND.contains(&power) {
+            reactor.increase_power_output(5);
+            self.actions.push("stabilise_power");
+            say!(" - Status: Keeping P Stable");
+        }
+
+        self.previous_power = power;
+        self.previous_temperature = temp;
+        Step::Continue
+    }
+
+    // Actions taken during the latest reading, in order
+    pub fn actions(&self) -> &[&'static str] {
+        &self.actions
     }
 
-    async fn initiate_evacuation(&self) {
-        println!("\n==================== Evacuation ====================\n");
+    fn initiate_evacuation(&self) {
+        say!("\n==================== Evacuation ====================\n");
         for i in (1..=10).rev() {
-            println!("Evacuation in progress... {} seconds remaining!", i);
-            sleep(Duration::from_secs(1)).await;
+            say!("Evacuation in progress... {} seconds remaining!", i);
+            self.clock.sleep(Duration::from_secs(1));
         }
-        println!("\nEvacuation complete. Shutting down the reactor.\n");
-        std::process::exit(0); // This exits the program after the evacuation
+        say!("\nEvacuation complete. Shutting down the reactor.\n");
     }
 }
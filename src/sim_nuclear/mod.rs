@@ -1,41 +1,286 @@
-mod control;
-mod reactor;
+pub mod control;
+pub mod reactor;
 
+use crate::clock::Clock;
+use crate::config::{check_chance, Config};
+use crate::events::{Event, EventLog};
+use crate::faults::{self, Faults};
+use crate::replay::Tape;
+use crate::rng::RngSource;
+use crate::simulation::{Outcome, Report, Simulation, Step};
+use crate::telemetry::Row;
+use anyhow::{bail, Result};
 use control::Control;
 use reactor::Reactor;
-use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
 
-// The main simulation entry point
-pub async fn run() {
-    let reactor = Arc::new(Reactor::new());
-    let control = Control::new();
+// Time between two reactor readings
+const TICK: Duration = Duration::from_secs(2);
 
-    // Shared flag to signal shutdown
-    let shutdown_flag = Arc::new(AtomicBool::new(false));
+// Tunable parameters for a reactor run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NuclearConfig {
+    pub duration_secs: Option<u64>, // Stop after this many seconds; None runs until a critical failure
+    pub scram_power_w: i32,         // Power at which the control room shuts the reactor down
+    pub evacuation_radiation_bq: i32, // Radiation above which the site is evacuated
+    pub leak_arming_delay_secs: u64, // Uptime before a radiation leak becomes possible
+    pub coolant_failure_chance: f64, // Chance of a temperature spike per reading
+    pub power_surge_chance: f64,    // Chance of a power surge per reading
+    pub radiation_leak_chance: f64, // Chance of a radiation leak per reading once armed
+}
+
+impl Default for NuclearConfig {
+    fn default() -> Self {
+        NuclearConfig {
+            duration_secs: None,
+            scram_power_w: 300,
+            evacuation_radiation_bq: 5000,
+            leak_arming_delay_secs: 10,
+            coolant_failure_chance: 0.95,
+            power_surge_chance: 0.15,
+            radiation_leak_chance: 0.10,
+        }
+    }
+}
+
+impl NuclearConfig {
+    pub fn validate(&self) -> Result<()> {
+        // Power readings are clamped to 0-300, so a higher threshold could never trigger and one
+        // of 0 would shut the reactor down straight away
+        if !(1..=300).contains(&self.scram_power_w) {
+            bail!("nuclear.scram_power_w must be between 1 and 300");
+        }
+        if self.evacuation_radiation_bq < 0 {
+            bail!("nuclear.evacuation_radiation_bq must not be negative");
+        }
+        check_chance(
+            "nuclear.coolant_failure_chance",
+            self.coolant_failure_chance,
+        )?;
+        check_chance("nuclear.power_surge_chance", self.power_surge_chance)?;
+        check_chance("nuclear.radiation_leak_chance", self.radiation_leak_chance)
+    }
+}
+
+// Everything needed to carry on a reactor run from where a snapshot left it
+#[derive(Serialize, Deserialize)]
+struct NuclearState {
+    elapsed_secs: f64,
+    ticks: u64,
+    temperature_c: i32,
+    power_w: i32,
+    radiation_bq: i32,
+    shutdown: bool,
+    leak_allowed: bool,
+}
+
+pub struct NuclearSimulation {
+    config: NuclearConfig,
+    restored: Option<NuclearState>, // Applied by the next `start`
+    faults: Faults,
+    rngs: RngSource,
+    tape: Tape,
+    clock: Clock,
+    events: EventLog,
+    reactor: Reactor,
+    control: Option<Control>,
+    ticks: u64,
+    outcome: Option<Outcome>,
+}
+
+impl NuclearSimulation {
+    pub fn new() -> Self {
+        let rngs = RngSource::default();
+        NuclearSimulation {
+            config: NuclearConfig::default(),
+            restored: None,
+            faults: Faults::default(),
+            reactor: Reactor::new(
+                NuclearConfig::default(),
+                Faults::default(),
+                rngs.stream("reactor"),
+                EventLog::default(),
+            ),
+            rngs,
+            tape: Tape::default(),
+            clock: Clock::default(),
+            events: EventLog::default(),
+            control: None,
+            ticks: 0,
+            outcome: None,
+        }
+    }
+}
+
+impl NuclearSimulation {
+    // Current reactor state, for embedders that drive the simulation step by step
+    pub fn reactor(&self) -> &Reactor {
+        &self.reactor
+    }
+
+    fn finish(&mut self, outcome: Outcome) -> Step {
+        self.outcome = Some(outcome.clone());
+        Step::Finished(outcome)
+    }
+}
+
+impl Default for NuclearSimulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulation for NuclearSimulation {
+    fn name(&self) -> &'static str {
+        "nuclear"
+    }
+
+    fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<()> {
+        self.config = config.nuclear.clone();
+        self.faults = Faults::new(&config.faults);
+        self.rngs = RngSource::new(config.seed).with_tape(config.tape.clone());
+        self.tape = config.tape.clone();
+        self.clock = config.links.clock(config.clock);
+        self.events = config.events.scoped(self.name(), Some(self.clock.clone()));
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.reactor = Reactor::new(
+            self.config.clone(),
+            self.faults.clone(),
+            self.rngs.stream("reactor"),
+            self.events.clone(),
+        );
+        self.ticks = 0;
+        if let Some(state) = self.restored.take() {
+            *self.reactor.temperature.lock().unwrap() = state.temperature_c;
+            *self.reactor.power_output.lock().unwrap() = state.power_w;
+            *self.reactor.radiation_level.lock().unwrap() = state.radiation_bq;
+            *self.reactor.shutdown.lock().unwrap() = state.shutdown;
+            *self.reactor.allow_radiation_leak.lock().unwrap() = state.leak_allowed;
+            self.clock
+                .resume_at(Duration::from_secs_f64(state.elapsed_secs));
+            self.ticks = state.ticks;
+        }
+        self.reactor.startup();
+        // The control room takes its first readings from the reactor, restored or not
+        self.control = Some(Control::new(
+            &self.config,
+            &self.reactor,
+            self.clock.clone(),
+            self.events.clone(),
+        ));
+        self.outcome = None;
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<Step> {
+        let uptime = self.tape.input("uptime", || self.clock.now());
+        if let Some(secs) = self.config.duration_secs {
+            if uptime >= Duration::from_secs(secs) {
+                say!("\nCtrlRoom: Run time limit reached. Initiating Controlled Shutdown.");
+                self.reactor.initiate_shutdown();
+                return Ok(self.finish(Outcome::Completed));
+            }
+        }
+
+        // The reactor fluctuates, then the control room reacts to the new readings
+        self.reactor.update_arming(uptime);
+        self.ticks += 1;
+        self.reactor.tick(self.ticks);
 
-    // Create a channel for communication between reactor and control room
-    let (control_tx, control_rx) = mpsc::channel(32);
+        let control = self.control.as_mut().expect("simulation not started");
+        let step = control.monitor_and_regulate(&self.reactor);
+        self.events.emit(Event::ReactorReading {
+            temperature_c: self.reactor.get_temperature(),
+            power_w: self.reactor.get_power_output(),
+            radiation_bq: self.reactor.get_radiation_level(),
+        });
+        match step {
+            Step::Continue => {
+                self.clock.sleep(TICK);
+                Ok(Step::Continue)
+            }
+            Step::Finished(outcome) => Ok(self.finish(outcome)),
+        }
+    }
 
-    // Start the reactor
-    reactor.startup().await;
+    fn stop(&mut self) -> Result<()> {
+        self.reactor.initiate_shutdown();
+        say!("Nuclear reactor simulation ended.");
+        Ok(())
+    }
 
-    // Clone the Arc to share the reactor with the spawned task
-    let reactor_for_task = Arc::clone(&reactor);
+    fn telemetry(&self) -> Result<Row> {
+        let actions = self.control.as_ref().map(|c| c.actions().join(";"));
+        Ok(vec![
+            ("temperature_c", self.reactor.get_temperature().to_string()),
+            ("power_w", self.reactor.get_power_output().to_string()),
+            (
+                "radiation_bq",
+                self.reactor.get_radiation_level().to_string(),
+            ),
+            ("actions", actions.unwrap_or_default()),
+        ])
+    }
 
-    // Run the reactor in a separate task
-    let reactor_task = tokio::spawn(async move {
-        reactor_for_task.run(control_tx).await;
-    });
+    fn inject(&mut self, event: &str) -> Result<()> {
+        match event {
+            "coolant_failure" => self.reactor.inject_coolant_failure(),
+            "power_surge" => self.reactor.inject_power_surge(),
+            "radiation_leak" => self.reactor.leak_radiation(),
+            _ => bail!(
+                "The nuclear simulation can inject {}, not '{}'",
+                faults::names(self.name()).join(", "),
+                event
+            ),
+        }
+        Ok(())
+    }
 
-    // Run the control room in the main task
-    control
-        .monitor_and_regulate(&reactor, Arc::clone(&shutdown_flag), control_rx)
-        .await;
+    fn snapshot(&self) -> Result<Value> {
+        Ok(serde_json::to_value(NuclearState {
+            elapsed_secs: self.clock.now().as_secs_f64(),
+            ticks: self.ticks,
+            temperature_c: self.reactor.get_temperature(),
+            power_w: self.reactor.get_power_output(),
+            radiation_bq: self.reactor.get_radiation_level(),
+            shutdown: *self.reactor.shutdown.lock().unwrap(),
+            leak_allowed: *self.reactor.allow_radiation_leak.lock().unwrap(),
+        })?)
+    }
 
-    // Wait for the reactor task to finish (if ever)
-    reactor_task.await.unwrap();
+    fn restore(&mut self, state: &Value) -> Result<()> {
+        self.restored = Some(NuclearState::deserialize(state)?);
+        Ok(())
+    }
 
-    println!("Simulation ended due to critical failure.");
+    fn report(&self) -> Report {
+        Report {
+            simulation: self.name(),
+            outcome: self.outcome.clone().unwrap_or(Outcome::Completed),
+            seed: self.rngs.seed(),
+            ticks: self.ticks,
+            elapsed_secs: self.clock.now().as_secs_f64(),
+            stats: vec![
+                (
+                    "temperature_c".into(),
+                    self.reactor.get_temperature() as f64,
+                ),
+                ("power_w".into(), self.reactor.get_power_output() as f64),
+                (
+                    "radiation_bq".into(),
+                    self.reactor.get_radiation_level() as f64,
+                ),
+            ],
+        }
+    }
 }
@@ -1,7 +1,10 @@
+use super::NuclearConfig;
+use crate::events::{Event, EventLog};
+use crate::faults::Faults;
+use crate::rng::SimRng;
 use rand::Rng;
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
-use tokio::time::{sleep, Duration};
+use std::time::Duration;
 
 pub struct Reactor {
     pub temperature: Arc<Mutex<i32>>,     // Shared state for temperature
@@ -9,101 +12,163 @@ pub struct Reactor {
     pub radiation_level: Arc<Mutex<i32>>, // Shared state for radiation level (Bq)
     pub shutdown: Arc<Mutex<bool>>,       // Shutdown flag
     pub allow_radiation_leak: Arc<Mutex<bool>>, // Flag to allow radiation leak
+    rng: Mutex<SimRng>,                   // Source of every random fluctuation and failure
+    config: NuclearConfig,
+    faults: Faults,
+    events: EventLog,
 }
 
 impl Reactor {
-    pub fn new() -> Self {
+    pub fn new(config: NuclearConfig, faults: Faults, rng: SimRng, events: EventLog) -> Self {
         Reactor {
             temperature: Arc::new(Mutex::new(100)), // Initial temperature: 100°C
             power_output: Arc::new(Mutex::new(100)), // Initial power output: 100 watts
             radiation_level: Arc::new(Mutex::new(0)), // Initial radiation level: 0 Bq
             shutdown: Arc::new(Mutex::new(false)),  // Initial shutdown state: false
             allow_radiation_leak: Arc::new(Mutex::new(false)), // Radiation leak not allowed initially
+            rng: Mutex::new(rng),
+            config,
+            faults,
+            events,
         }
     }
 
-    pub async fn startup(&self) {
-        println!("========================= Reactor Initialization =========================");
-        println!("Reactor: Startup Success");
-        println!(
+    pub fn startup(&self) {
+        say!("========================= Reactor Initialization =========================");
+        say!("Reactor: Startup Success");
+        say!(
             "Reactor: Initial Temperature: {}°C",
             *self.temperature.lock().unwrap()
         );
-        println!(
+        say!(
             "Reactor: Initial Power Output: {}w",
             *self.power_output.lock().unwrap()
         );
-        println!(
+        say!(
             "Reactor: Initial Radiation Reading: {}Bq",
             *self.radiation_level.lock().unwrap()
         );
 
-        println!("\n========================= Efficiency Targets =========================");
-        println!("Target Levels: 200w | 200°C | 0 Bq");
-        println!("-----------------------------------------------------------------------");
-        println!("Temperature (T) | Power (P) | Radiation (R)\n");
-
-        // Enable radiation leak possibility after 15 seconds
-        let allow_leak = Arc::clone(&self.allow_radiation_leak);
-        tokio::spawn(async move {
-            sleep(Duration::from_secs(10)).await;
-            let mut allow_leak = allow_leak.lock().unwrap();
-            *allow_leak = true;
-        });
+        say!("\n========================= Efficiency Targets =========================");
+        say!("Target Levels: 200w | 200°C | 0 Bq");
+        say!("-----------------------------------------------------------------------");
+        say!("Temperature (T) | Power (P) | Radiation (R)\n");
+    }
+
+    // Enable radiation leak possibility once the arming delay has passed
+    pub fn update_arming(&self, uptime: Duration) {
+        if uptime >= Duration::from_secs(self.config.leak_arming_delay_secs) {
+            *self.allow_radiation_leak.lock().unwrap() = true;
+        }
     }
 
-    pub async fn fluctuate_temperature(&self) {
+    pub fn fluctuate_temperature(&self, tick: u64) {
         if *self.shutdown.lock().unwrap() {
             return; // Do nothing if the reactor is shutting down
         }
 
-        let mut rng = rand::thread_rng();
+        let mut rng = self.rng.lock().unwrap();
         let mut fluctuation = rng.gen_range(-30..=30); // Make fluctuation mutable
 
         // Introduce a random coolant pump failure (15% chance)
-        if rng.gen_bool(0.95) {
-            println!("\nReactor: Coolant pump failure detected! Temperature spike occurring.\n");
-            fluctuation += rng.gen_range(50..=100); // Spike between 50 and 100°C
+        if self.faults.strikes(
+            "coolant_failure",
+            tick,
+            self.config.coolant_failure_chance,
+            &mut *rng,
+        ) {
+            fluctuation += self.coolant_failure(&mut rng);
         }
+        drop(rng);
 
-        let mut temp = self.temperature.lock().unwrap();
-        *temp = (*temp + fluctuation).clamp(100, 300); // Clamping between 100°C and 300°C
+        self.change_temperature(fluctuation);
     }
 
-    pub async fn fluctuate_power_output(&self) {
+    pub fn fluctuate_power_output(&self, tick: u64) {
         if *self.shutdown.lock().unwrap() {
             return; // Do nothing if the reactor is shutting down
         }
 
-        let mut rng = rand::thread_rng();
+        let mut rng = self.rng.lock().unwrap();
         let mut fluctuation = rng.gen_range(-30..=30); // Make fluctuation mutable
 
         // Introduce a random power surge (15% chance)
-        if rng.gen_bool(0.15) {
-            println!("\nReactor: Electrical malfunction detected! Power surge occurring.\n");
-            fluctuation += rng.gen_range(500..=1000); // Surge between 50 and 100 watts
+        if self.faults.strikes(
+            "power_surge",
+            tick,
+            self.config.power_surge_chance,
+            &mut *rng,
+        ) {
+            fluctuation += self.power_surge(&mut rng);
         }
+        drop(rng);
 
-        let mut power = self.power_output.lock().unwrap();
-        *power = (*power + fluctuation).clamp(00, 300); // Clamping between 100 watts and 300 watts
+        self.change_power_output(fluctuation);
     }
 
-    pub async fn maybe_cause_radiation_leak(&self) {
-        if *self.shutdown.lock().unwrap() || !*self.allow_radiation_leak.lock().unwrap() {
-            return; // Do nothing if the reactor is shutting down or radiation leaks are not allowed yet
+    pub fn maybe_cause_radiation_leak(&self, tick: u64) {
+        if *self.shutdown.lock().unwrap() {
+            return; // Do nothing if the reactor is shutting down
         }
 
-        let mut rng = rand::thread_rng();
-        if rng.gen_bool(0.10) {
-            // 10% chance of causing a radiation leak
-            println!(
-                "\nReactor: Severe mechanical failure detected! Major radiation leak occurring.\n"
+        // Leaks only happen by chance once allowed, but a scheduled one strikes regardless
+        let leak = *self.allow_radiation_leak.lock().unwrap()
+            && self.faults.by_chance(
+                "radiation_leak",
+                self.config.radiation_leak_chance,
+                &mut *self.rng.lock().unwrap(),
             );
-            let mut radiation = self.radiation_level.lock().unwrap();
-            *radiation = 10000; // Arbitrary high radiation level to simulate a major leak
+        if leak || self.faults.scheduled("radiation_leak", tick) {
+            self.leak_radiation();
         }
     }
 
+    // Faults triggered on demand rather than by chance, e.g. through the remote control API
+    pub fn inject_coolant_failure(&self) {
+        let spike = self.coolant_failure(&mut self.rng.lock().unwrap());
+        self.change_temperature(spike);
+    }
+
+    pub fn inject_power_surge(&self) {
+        let surge = self.power_surge(&mut self.rng.lock().unwrap());
+        self.change_power_output(surge);
+    }
+
+    // Announce a coolant pump failure and draw how far it heats the reactor
+    fn coolant_failure(&self, rng: &mut SimRng) -> i32 {
+        say!("\nReactor: Coolant pump failure detected! Temperature spike occurring.\n");
+        let spike = rng.gen_range(50..=100); // Spike between 50 and 100°C
+        self.events.emit(Event::CoolantFailure { spike_c: spike });
+        spike
+    }
+
+    // Announce an electrical malfunction and draw how much power it surges by
+    fn power_surge(&self, rng: &mut SimRng) -> i32 {
+        say!("\nReactor: Electrical malfunction detected! Power surge occurring.\n");
+        let surge = rng.gen_range(500..=1000); // Surge between 500 and 1000 watts
+        self.events.emit(Event::PowerSurge { surge_w: surge });
+        surge
+    }
+
+    fn change_temperature(&self, change: i32) {
+        let mut temp = self.temperature.lock().unwrap();
+        *temp = (*temp + change).clamp(100, 300); // Clamping between 100°C and 300°C
+    }
+
+    fn change_power_output(&self, change: i32) {
+        let mut power = self.power_output.lock().unwrap();
+        *power = (*power + change).clamp(0, 300); // Clamping between 0 watts and 300 watts
+    }
+
+    pub fn leak_radiation(&self) {
+        say!("\nReactor: Severe mechanical failure detected! Major radiation leak occurring.\n");
+        let mut radiation = self.radiation_level.lock().unwrap();
+        *radiation = 10000; // Arbitrary high radiation level to simulate a major leak
+        self.events.emit(Event::RadiationLeak {
+            level_bq: *radiation,
+        });
+    }
+
     pub fn get_temperature(&self) -> i32 {
         *self.temperature.lock().unwrap()
     }
@@ -132,31 +197,61 @@ impl Reactor {
     }
 
     pub fn increase_power_output(&self, amount: i32) {
+        if *self.shutdown.lock().unwrap() {
+            return; // Do nothing if the reactor is shutting down
+        }
+
         let mut power = self.power_output.lock().unwrap();
         *power = (*power + amount).min(300); // Ensure it doesn't go above 300 watts
     }
 
-    pub async fn run(&self, control_tx: mpsc::Sender<()>) {
-        loop {
-            if *self.shutdown.lock().unwrap() {
-                break; // Exit the loop if the reactor is shutting down
-            }
+    // Apply one round of fluctuations and failures; ticks count from 1
+    pub fn tick(&self, tick: u64) {
+        self.fluctuate_temperature(tick);
+        self.fluctuate_power_output(tick);
+        self.maybe_cause_radiation_leak(tick);
+    }
+
+    pub fn initiate_shutdown(&self) {
+        let mut shutdown = self.shutdown.lock().unwrap();
+        *shutdown = true; // Set the shutdown flag
+    }
+}
 
-            self.fluctuate_temperature().await;
-            self.fluctuate_power_output().await;
-            self.maybe_cause_radiation_leak().await;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::RngSource;
 
-            if let Err(e) = control_tx.send(()).await {
-                eprintln!("\nFailed to send message to control room: {}\n", e);
-                break;
-            }
+    fn reactor() -> Reactor {
+        Reactor::new(
+            NuclearConfig::default(),
+            Faults::default(),
+            RngSource::new(Some(1)).stream("reactor"),
+            EventLog::new(),
+        )
+    }
 
-            sleep(Duration::from_secs(2)).await;
+    #[test]
+    fn injected_faults_stay_within_the_reading_range() {
+        let reactor = reactor();
+        // Four spikes of at least 50°C take the reactor from 100°C to the top of the range
+        for _ in 0..4 {
+            reactor.inject_coolant_failure();
+            reactor.inject_power_surge();
         }
+        assert_eq!(reactor.get_temperature(), 300);
+        assert_eq!(reactor.get_power_output(), 300);
     }
 
-    pub fn initiate_shutdown(&self) {
-        let mut shutdown = self.shutdown.lock().unwrap();
-        *shutdown = true; // Set the shutdown flag
+    #[test]
+    fn power_cannot_be_raised_after_a_shutdown() {
+        let reactor = reactor();
+        reactor.increase_power_output(50);
+        assert_eq!(reactor.get_power_output(), 150);
+
+        reactor.initiate_shutdown();
+        reactor.increase_power_output(50);
+        assert_eq!(reactor.get_power_output(), 150);
     }
 }
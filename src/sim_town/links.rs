@@ -0,0 +1,229 @@
+use parking_lot::Mutex;
+use std::fmt;
+use std::sync::Arc;
+
+use super::TownConfig;
+use crate::clock::{Clock, ClockMode};
+use crate::events::{Event, EventSink, Record};
+
+// What ties the simulations of a town together. A standalone run gets a detached handle, which
+// gives it a clock of its own, full power and an endless supply of beans.
+#[derive(Clone, Default)]
+pub struct Links {
+    town: Option<Arc<Town>>,
+}
+
+struct Town {
+    clock: Clock, // Shared by every simulation in the town
+    rated_power_w: i32,
+    backup_power: f64, // Fraction of full speed kept while the reactor is down
+    state: Mutex<State>,
+}
+
+struct State {
+    power_w: Option<i32>, // Latest reactor output; None until the first reading
+    reactor_down: bool,
+    weather_alerts: u64,    // Catastrophic events not yet passed on to the home
+    beans_g: i32,           // Beans left at the cafe
+    beans_delivered_g: i32, // Beans shipped from the factory to the cafe
+    deliveries: bool,       // Whether the factory is running; without it the cafe never runs out
+    closed: bool,           // The town is shutting down, so nobody should wait for deliveries
+}
+
+impl Links {
+    pub fn new(config: &TownConfig, clock: Clock) -> Self {
+        Links {
+            town: Some(Arc::new(Town {
+                clock,
+                rated_power_w: config.rated_power_w,
+                backup_power: config.backup_power_pct as f64 / 100.0,
+                state: Mutex::new(State {
+                    power_w: None,
+                    reactor_down: false,
+                    weather_alerts: 0,
+                    beans_g: config.cafe_beans_g,
+                    beans_delivered_g: 0,
+                    deliveries: config.simulations.iter().any(|name| name == "factory"),
+                    closed: false,
+                }),
+            })),
+        }
+    }
+
+    // Whether the simulation is running as part of a town
+    pub fn is_linked(&self) -> bool {
+        self.town.is_some()
+    }
+
+    // The town's clock, or a fresh one for a standalone run
+    pub fn clock(&self, mode: ClockMode) -> Clock {
+        match &self.town {
+            Some(town) => town.clock.clone(),
+            None => Clock::new(mode),
+        }
+    }
+
+    // Share of full speed the reactor's output allows the factory and cafe to work at
+    pub fn power_factor(&self) -> f64 {
+        let Some(town) = &self.town else {
+            return 1.0;
+        };
+        let state = town.state.lock();
+        if state.reactor_down {
+            return town.backup_power;
+        }
+        match state.power_w {
+            Some(power) => (power as f64 / town.rated_power_w as f64).clamp(town.backup_power, 1.0),
+            None => 1.0,
+        }
+    }
+
+    // Whether the weather machine has hit a catastrophe the home has not yet warned about
+    pub fn take_weather_alert(&self) -> bool {
+        let Some(town) = &self.town else {
+            return false;
+        };
+        let mut state = town.state.lock();
+        if state.weather_alerts == 0 {
+            return false;
+        }
+        state.weather_alerts -= 1;
+        true
+    }
+
+    // Take the beans for one item; false if the cafe has to wait for a delivery first
+    pub fn take_beans(&self, grams: u32) -> bool {
+        let Some(town) = &self.town else {
+            return true;
+        };
+        let mut state = town.state.lock();
+        if state.closed || !state.deliveries {
+            return true;
+        }
+        let grams = grams as i32;
+        if state.beans_g < grams {
+            return false;
+        }
+        state.beans_g -= grams;
+        true
+    }
+
+    pub fn reactor_power_w(&self) -> i32 {
+        self.town.as_ref().map_or(0, |town| {
+            let state = town.state.lock();
+            if state.reactor_down {
+                0
+            } else {
+                state.power_w.unwrap_or_default()
+            }
+        })
+    }
+
+    pub fn beans_g(&self) -> i32 {
+        self.town
+            .as_ref()
+            .map_or(0, |town| town.state.lock().beans_g)
+    }
+
+    pub fn beans_delivered_g(&self) -> i32 {
+        self.town
+            .as_ref()
+            .map_or(0, |town| town.state.lock().beans_delivered_g)
+    }
+
+    // A simulation of the town has ended, or failed to start. Once the factory is gone no more
+    // beans are coming, so the cafe stops waiting for them.
+    pub fn leave(&self, simulation: &str) {
+        let Some(town) = &self.town else {
+            return;
+        };
+        if simulation == "factory" {
+            town.state.lock().deliveries = false;
+            town.clock.notify();
+        }
+    }
+
+    // Release anyone waiting on the town before its simulations are stopped
+    pub fn close(&self) {
+        if let Some(town) = &self.town {
+            town.state.lock().closed = true;
+            town.clock.notify();
+        }
+    }
+}
+
+impl fmt::Debug for Links {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Links")
+            .field("linked", &self.is_linked())
+            .finish()
+    }
+}
+
+// Passes what happens in one simulation on to the others, by watching their events
+impl EventSink for Links {
+    fn record(&self, record: &Record) {
+        let Some(town) = &self.town else {
+            return;
+        };
+        let mut state = town.state.lock();
+        match (record.simulation.as_str(), &record.event) {
+            ("nuclear", Event::ReactorReading { power_w, .. }) => state.power_w = Some(*power_w),
+            ("nuclear", Event::Scram { .. })
+            | ("nuclear", Event::Evacuation)
+            | ("nuclear", Event::SimulationFinished { .. }) => {
+                if !state.reactor_down {
+                    say!("Town: The reactor is down, the town is running on backup power.");
+                }
+                state.reactor_down = true;
+            }
+            ("weather", Event::CatastrophicEvent { .. }) => state.weather_alerts += 1,
+            ("factory", Event::ShipmentCompleted { grams }) => {
+                state.beans_g += grams;
+                state.beans_delivered_g += grams;
+                say!("Town: {} grams of beans delivered to the cafe.", grams);
+                drop(state);
+                town.clock.notify(); // Wake baristas waiting for beans
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn town(simulations: &[&str]) -> Links {
+        let config = TownConfig {
+            simulations: simulations.iter().map(|name| name.to_string()).collect(),
+            cafe_beans_g: 20,
+            ..TownConfig::default()
+        };
+        Links::new(&config, Clock::new(ClockMode::Virtual))
+    }
+
+    #[test]
+    fn without_a_factory_the_beans_never_run_out() {
+        let links = town(&["cafe"]);
+        for _ in 0..10 {
+            assert!(links.take_beans(18));
+        }
+    }
+
+    #[test]
+    fn the_cafe_stops_waiting_for_beans_once_the_factory_is_gone() {
+        let links = town(&["cafe", "factory"]);
+        assert!(links.take_beans(18));
+        assert!(!links.take_beans(18));
+
+        // Another member ending changes nothing
+        links.leave("weather");
+        assert!(!links.take_beans(18));
+
+        // The factory failing to start or stopping means no delivery is coming
+        links.leave("factory");
+        assert!(links.take_beans(18));
+        assert_eq!(links.beans_delivered_g(), 0);
+    }
+}
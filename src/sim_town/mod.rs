@@ -0,0 +1,306 @@
+pub mod links;
+
+use crate::cancel::{Cancel, RunGuard};
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::replay::Tape;
+use crate::simulation::{self, Outcome, Report, Simulation, Step};
+use anyhow::{bail, Result};
+pub use links::Links;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// Interval at which the town checks on its simulations
+const TICK: Duration = Duration::from_secs(1);
+
+// Simulations that can take part in a town
+const MEMBERS: [&str; 5] = ["cafe", "factory", "home", "nuclear", "weather"];
+
+// Tunable parameters for a town run; each member still reads its own section of the configuration
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TownConfig {
+    pub duration_secs: u64, // How long the town runs before every simulation is stopped
+    pub simulations: Vec<String>, // Simulations taking part; the factory needs RabbitMQ
+    pub rated_power_w: i32, // Reactor output at which the factory and cafe work at full speed
+    pub backup_power_pct: i32, // Speed the factory and cafe keep while the reactor is down
+    pub cafe_beans_g: i32,  // Beans the cafe opens with
+}
+
+impl Default for TownConfig {
+    fn default() -> Self {
+        TownConfig {
+            duration_secs: 60,
+            simulations: MEMBERS.iter().map(|name| name.to_string()).collect(),
+            rated_power_w: 200,
+            backup_power_pct: 25,
+            cafe_beans_g: 200,
+        }
+    }
+}
+
+impl TownConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.simulations.is_empty() {
+            bail!("town.simulations must name at least one simulation");
+        }
+        for (i, name) in self.simulations.iter().enumerate() {
+            if !MEMBERS.contains(&name.as_str()) {
+                bail!(
+                    "town.simulations can only name {}, not '{}'",
+                    MEMBERS.join(", "),
+                    name
+                );
+            }
+            if self.simulations[..i].contains(name) {
+                bail!("town.simulations names '{}' more than once", name);
+            }
+        }
+        if self.rated_power_w <= 0 {
+            bail!("town.rated_power_w must be positive");
+        }
+        // Without any backup power the factory and cafe would stop for good once the reactor is down
+        if !(1..=100).contains(&self.backup_power_pct) {
+            bail!("town.backup_power_pct must be between 1 and 100");
+        }
+        if self.cafe_beans_g < 0 {
+            bail!("town.cafe_beans_g must not be negative");
+        }
+        Ok(())
+    }
+}
+
+// A simulation of the town running on its own thread
+struct Member {
+    name: &'static str,
+    thread: JoinHandle<Result<Report>>,
+}
+
+// Runs the other simulations side by side on one clock, passing power, weather and deliveries
+// between them through `Links`
+pub struct TownSimulation {
+    config: TownConfig,
+    members_config: Config,
+    clock: Clock,
+    links: Links,
+    cancel: Cancel,            // Stops the members when the town stops
+    running: Option<RunGuard>, // Keeps `cancel` effective even before every member has started
+    members: Vec<Member>,
+    done: Arc<AtomicUsize>, // Members whose run has returned; counted before their thread leaves the clock
+    finished: Vec<(&'static str, Result<Report>)>,
+    ticks: u64,
+}
+
+impl TownSimulation {
+    pub fn new() -> Self {
+        TownSimulation {
+            config: TownConfig::default(),
+            members_config: Config::default(),
+            clock: Clock::default(),
+            links: Links::default(),
+            cancel: Cancel::new(),
+            running: None,
+            members: Vec::new(),
+            done: Arc::new(AtomicUsize::new(0)),
+            finished: Vec::new(),
+            ticks: 0,
+        }
+    }
+
+    // Collect the results of members that have ended, or of all of them once `all` are done
+    fn collect(&mut self, all: bool) {
+        let mut still_running = Vec::new();
+        for member in self.members.drain(..) {
+            if !all && !member.thread.is_finished() {
+                still_running.push(member);
+                continue;
+            }
+            let result = member
+                .thread
+                .join()
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("{} simulation panicked", member.name)));
+            match &result {
+                Ok(report) => say!(
+                    "Town: The {} simulation ended ({}) after {} ticks.",
+                    member.name,
+                    report.outcome,
+                    report.ticks
+                ),
+                Err(e) => say!("Town: The {} simulation failed: {:#}", member.name, e),
+            }
+            self.finished.push((member.name, result));
+        }
+        self.members = still_running;
+    }
+}
+
+impl Default for TownSimulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulation for TownSimulation {
+    fn name(&self) -> &'static str {
+        "town"
+    }
+
+    fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<()> {
+        self.config = config.town.clone();
+        self.clock = Clock::new(config.clock);
+        self.links = Links::new(&self.config, self.clock.clone());
+        self.cancel = Cancel::new();
+        // The members share the town's clock and links, and report to the same sinks as the town.
+        // Remote requests, checkpoints and file outputs apply to the town as a whole.
+        self.members_config = Config {
+            seed: Some(config.seed.unwrap_or_else(rand::random)),
+            events: config.events.with_sink(Arc::new(self.links.clone())),
+            tape: Tape::default(),
+            remote: Default::default(),
+            cancel: self.cancel.clone(),
+            checkpoint: Default::default(),
+            telemetry: None,
+            chart: None,
+            links: self.links.clone(),
+            ..config.clone()
+        };
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        say!(
+            "Welcome to town! Starting {}.",
+            self.config.simulations.join(", ")
+        );
+        self.ticks = 0;
+        self.finished.clear();
+        self.running = Some(self.cancel.enter());
+        self.done = Arc::new(AtomicUsize::new(0));
+        for name in &self.config.simulations {
+            let name = MEMBERS
+                .into_iter()
+                .find(|member| member == name)
+                .expect("validated town member");
+            let config = self.members_config.clone();
+            let done = self.done.clone();
+            let links = self.links.clone();
+            let thread = self.clock.spawn(move || {
+                let mut simulation = simulation::create(name).expect("known simulation");
+                let result = simulation::run(simulation.as_mut(), &config);
+                links.leave(name);
+                done.fetch_add(1, Ordering::SeqCst);
+                result
+            });
+            self.members.push(Member { name, thread });
+        }
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<Step> {
+        self.clock.sleep(TICK);
+        self.ticks += 1;
+        self.collect(false);
+
+        let uptime = self.clock.now();
+        if self.members.is_empty() || uptime >= Duration::from_secs(self.config.duration_secs) {
+            Ok(Step::Finished(Outcome::Completed))
+        } else {
+            Ok(Step::Continue)
+        }
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        // Nobody may be left waiting for a delivery or a reading that will never come
+        self.links.close();
+        self.cancel.cancel();
+        self.running = None;
+        let (done, members) = (&self.done, self.config.simulations.len());
+        self.clock
+            .wait_until(None, || done.load(Ordering::SeqCst) == members);
+        self.collect(true);
+        say!("The town is quiet again.");
+        Ok(())
+    }
+
+    fn report(&self) -> Report {
+        let ended = |ok: bool| {
+            self.finished
+                .iter()
+                .filter(|(_, result)| result.is_ok() == ok)
+                .count() as f64
+        };
+        Report {
+            simulation: self.name(),
+            outcome: Outcome::Completed,
+            seed: self.members_config.seed.unwrap_or_default(),
+            ticks: self.ticks,
+            elapsed_secs: self.clock.now().as_secs_f64(),
+            stats: vec![
+                ("simulations_ended".into(), ended(true)),
+                ("simulations_failed".into(), ended(false)),
+                (
+                    "reactor_power_w".into(),
+                    self.links.reactor_power_w() as f64,
+                ),
+                ("power_pct".into(), 100.0 * self.links.power_factor()),
+                ("cafe_beans_g".into(), self.links.beans_g() as f64),
+                (
+                    "beans_delivered_g".into(),
+                    self.links.beans_delivered_g() as f64,
+                ),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejection(change: impl FnOnce(&mut TownConfig)) -> String {
+        let mut config = TownConfig::default();
+        change(&mut config);
+        format!(
+            "{:#}",
+            config.validate().expect_err("config should be rejected")
+        )
+    }
+
+    #[test]
+    fn the_defaults_are_valid() {
+        TownConfig::default().validate().unwrap();
+    }
+
+    #[test]
+    fn simulations_must_be_known_members_named_once() {
+        assert!(rejection(|config| config.simulations.clear()).contains("at least one"));
+        assert!(
+            rejection(|config| config.simulations.push("zoo".to_string())).contains("not 'zoo'")
+        );
+        assert!(
+            rejection(|config| config.simulations.push("cafe".to_string()))
+                .contains("names 'cafe' more than once")
+        );
+
+        let config = TownConfig {
+            simulations: vec!["nuclear".to_string(), "cafe".to_string()],
+            ..TownConfig::default()
+        };
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn power_and_beans_must_be_in_range() {
+        assert!(rejection(|config| config.rated_power_w = 0).contains("town.rated_power_w"));
+        assert!(rejection(|config| config.backup_power_pct = 0).contains("town.backup_power_pct"));
+        assert!(rejection(|config| config.backup_power_pct = 101).contains("town.backup_power_pct"));
+        assert!(rejection(|config| config.cafe_beans_g = -1).contains("town.cafe_beans_g"));
+    }
+}
@@ -1,79 +1,105 @@
 use super::weather_machine::WeatherMachine;
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
-use tokio::sync::mpsc;
+use super::WeatherConfig;
+use crate::events::{Event, EventLog};
+use crate::simulation::{Outcome, Step};
 
-pub struct Control;
+pub struct Control {
+    is_first_run: bool,
+    repairs: u64,
+    repaired_pct: i32, // Structural health restored during the latest round, for telemetry
+    repair_threshold: i32,
+    repair_amount: i32,
+    events: EventLog,
+}
 
 impl Control {
-    pub fn new() -> Self {
-        Control
+    pub fn new(config: &WeatherConfig, events: EventLog) -> Self {
+        Control {
+            is_first_run: true,
+            repairs: 0,
+            repaired_pct: 0,
+            repair_threshold: config.repair_threshold_pct,
+            repair_amount: config.repair_amount_pct,
+            events,
+        }
     }
 
-    pub async fn monitor_and_regulate(
-        &self,
-        weather_machine: &WeatherMachine,
-        shutdown_flag: Arc<AtomicBool>,
-        mut control_rx: mpsc::Receiver<()>,
-    ) {
-        let mut is_first_run = true;
-
-        while (control_rx.recv().await).is_some() {
-            // Check if the system is in shutdown mode
-            if shutdown_flag.load(Ordering::SeqCst) {
-                break;
-            }
-
-            // Check for catastrophic event
-            if let Some(event) = weather_machine.get_catastrophic_event() {
-                let temp = weather_machine.get_temperature();
-                let wind_speed = weather_machine.get_wind_speed();
-                let damage_amount = 100; // Set an immense damage amount
-
-                println!("Warning: Catastrophic event detected: {}!", event);
-                println!(
-                    "Last recorded data - Temp: {}°C, Wind Speed: {} km/h",
-                    temp, wind_speed
-                );
-                println!(
-                    "Incoming damage: {}%. Activating Bunker Mode for survival.",
-                    damage_amount
-                );
+    // Carry on a run that has already been monitored and repaired
+    pub fn resume(&mut self, repairs: u64) {
+        self.is_first_run = false;
+        self.repairs = repairs;
+    }
 
-                std::process::exit(0); // Immediately exit the program
-            }
+    // Inspect the latest conditions and regulate the machine; Finished means the run is over
+    pub fn monitor_and_regulate(&mut self, weather_machine: &WeatherMachine) -> Step {
+        self.repaired_pct = 0;
 
+        // Check for catastrophic event
+        if let Some(event) = weather_machine.get_catastrophic_event() {
             let temp = weather_machine.get_temperature();
             let wind_speed = weather_machine.get_wind_speed();
-            let structural_health = weather_machine.get_structural_health();
-
-            if is_first_run {
-                println!("Monitoring Atmospheric Conditions\n");
-                is_first_run = false;
-            }
+            let damage_amount = 100; // Set an immense damage amount
 
-            println!(
-                "Temp: {}°C\nWind Speed: {} km/h\nMachine Structural Health: {}%\n",
-                temp, wind_speed, structural_health
+            say!("Warning: Catastrophic event detected: {}!", event);
+            say!(
+                "Last recorded data - Temp: {}°C, Wind Speed: {} km/h",
+                temp,
+                wind_speed
+            );
+            say!(
+                "Incoming damage: {}%. Activating Bunker Mode for survival.",
+                damage_amount
             );
 
-            // Trigger healing mode if structural health drops below 70%
-            if structural_health < 70 {
-                println!("Warning: Structural health critical! Initiating repair protocol.");
-                weather_machine.repair_structural_health(20); // Repair by 20%
-                println!("Repair complete. Structural health restored.\n");
-            }
+            self.events.emit(Event::CatastrophicEvent {
+                kind: event.clone(),
+            });
+            return Step::Finished(Outcome::CatastrophicEvent(event));
+        }
 
-            // Simulate a shutdown condition if structural health drops to 0
-            if structural_health == 0 {
-                println!("Critical failure: Structural health at 0%! Shutting down.");
-                std::process::exit(0); // Immediately exit the program
-            }
+        let temp = weather_machine.get_temperature();
+        let wind_speed = weather_machine.get_wind_speed();
+        let structural_health = weather_machine.get_structural_health();
 
-            // Sleep for a brief moment to simulate real-time monitoring
-            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        if self.is_first_run {
+            say!("Monitoring Atmospheric Conditions\n");
+            self.is_first_run = false;
         }
+
+        say!(
+            "Temp: {}°C\nWind Speed: {} km/h\nMachine Structural Health: {}%\n",
+            temp,
+            wind_speed,
+            structural_health
+        );
+
+        // Trigger healing mode if structural health drops below the repair threshold
+        if structural_health < self.repair_threshold {
+            say!("Warning: Structural health critical! Initiating repair protocol.");
+            weather_machine.repair_structural_health(self.repair_amount);
+            self.repairs += 1;
+            self.repaired_pct = self.repair_amount;
+            self.events.emit(Event::Repair {
+                amount_pct: self.repair_amount,
+            });
+            say!("Repair complete. Structural health restored.\n");
+        }
+
+        // Simulate a shutdown condition if structural health drops to 0
+        if structural_health == 0 {
+            say!("Critical failure: Structural health at 0%! Shutting down.");
+            self.events.emit(Event::StructuralFailure);
+            return Step::Finished(Outcome::StructuralFailure);
+        }
+
+        Step::Continue
+    }
+
+    pub fn repairs(&self) -> u64 {
+        self.repairs
+    }
+
+    pub fn repaired_pct(&self) -> i32 {
+        self.repaired_pct
     }
 }
@@ -1,40 +1,278 @@
-mod control;
-mod weather_machine;
+pub mod control;
+pub mod weather_machine;
 
+use crate::clock::Clock;
+use crate::config::{check_chance, Config};
+use crate::events::{Event, EventLog};
+use crate::faults::{self, Faults};
+use crate::replay::Tape;
+use crate::rng::RngSource;
+use crate::simulation::{Outcome, Report, Simulation, Step};
+use crate::telemetry::Row;
+use anyhow::{bail, Result};
 use control::Control;
-use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
 use weather_machine::WeatherMachine;
 
-pub async fn run() {
-    let weather_machine = Arc::new(WeatherMachine::new()); // Use Arc to allow sharing
-    let control = Control::new();
+// Time between two rounds of atmospheric monitoring
+const TICK: Duration = Duration::from_secs(2);
 
-    // Shared flag to signal shutdown
-    let shutdown_flag = Arc::new(AtomicBool::new(false));
+// Tunable parameters for a weather machine run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WeatherConfig {
+    pub duration_secs: Option<u64>, // Stop after this many seconds; None runs until a failure
+    pub repair_threshold_pct: i32,  // Structural health below which the machine repairs itself
+    pub repair_amount_pct: i32,     // Structural health restored by one repair
+    pub wind_spike_chance: f64,     // Chance of a damaging wind spike per round
+    pub catastrophe_chance: f64,    // Chance of a catastrophic event per round
+}
+
+impl Default for WeatherConfig {
+    fn default() -> Self {
+        WeatherConfig {
+            duration_secs: None,
+            repair_threshold_pct: 70,
+            repair_amount_pct: 20,
+            wind_spike_chance: 0.2,
+            catastrophe_chance: 0.05,
+        }
+    }
+}
+
+impl WeatherConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(0..=100).contains(&self.repair_threshold_pct) {
+            bail!("weather.repair_threshold_pct must be between 0 and 100");
+        }
+        if !(0..=100).contains(&self.repair_amount_pct) {
+            bail!("weather.repair_amount_pct must be between 0 and 100");
+        }
+        check_chance("weather.wind_spike_chance", self.wind_spike_chance)?;
+        check_chance("weather.catastrophe_chance", self.catastrophe_chance)
+    }
+}
+
+// Everything needed to carry on a weather machine run from where a snapshot left it
+#[derive(Serialize, Deserialize)]
+struct WeatherState {
+    elapsed_secs: f64,
+    ticks: u64,
+    temperature_c: i32,
+    wind_speed_kmh: i32,
+    structural_health_pct: i32,
+    repairs: u64,
+}
+
+pub struct WeatherSimulation {
+    config: WeatherConfig,
+    restored: Option<WeatherState>, // Applied by the next `start`
+    faults: Faults,
+    rngs: RngSource,
+    tape: Tape,
+    clock: Clock,
+    events: EventLog,
+    weather_machine: WeatherMachine,
+    control: Control,
+    ticks: u64,
+    outcome: Option<Outcome>,
+}
+
+impl WeatherSimulation {
+    pub fn new() -> Self {
+        let rngs = RngSource::default();
+        WeatherSimulation {
+            config: WeatherConfig::default(),
+            restored: None,
+            faults: Faults::default(),
+            weather_machine: WeatherMachine::new(
+                WeatherConfig::default(),
+                Faults::default(),
+                rngs.stream("weather_machine"),
+                EventLog::default(),
+            ),
+            rngs,
+            tape: Tape::default(),
+            clock: Clock::default(),
+            events: EventLog::default(),
+            control: Control::new(&WeatherConfig::default(), EventLog::default()),
+            ticks: 0,
+            outcome: None,
+        }
+    }
+}
+
+impl WeatherSimulation {
+    // Current machine state, for embedders that drive the simulation step by step
+    pub fn weather_machine(&self) -> &WeatherMachine {
+        &self.weather_machine
+    }
+
+    fn finish(&mut self, outcome: Outcome) -> Step {
+        self.outcome = Some(outcome.clone());
+        Step::Finished(outcome)
+    }
+}
+
+impl Default for WeatherSimulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulation for WeatherSimulation {
+    fn name(&self) -> &'static str {
+        "weather"
+    }
+
+    fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<()> {
+        self.config = config.weather.clone();
+        self.faults = Faults::new(&config.faults);
+        self.rngs = RngSource::new(config.seed).with_tape(config.tape.clone());
+        self.tape = config.tape.clone();
+        self.clock = config.links.clock(config.clock);
+        self.events = config.events.scoped(self.name(), Some(self.clock.clone()));
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.weather_machine = WeatherMachine::new(
+            self.config.clone(),
+            self.faults.clone(),
+            self.rngs.stream("weather_machine"),
+            self.events.clone(),
+        );
+        self.control = Control::new(&self.config, self.events.clone());
+        self.weather_machine.startup();
+        self.ticks = 0;
+        if let Some(state) = self.restored.take() {
+            *self.weather_machine.temperature.lock().unwrap() = state.temperature_c;
+            *self.weather_machine.wind_speed.lock().unwrap() = state.wind_speed_kmh;
+            *self.weather_machine.structural_health.lock().unwrap() = state.structural_health_pct;
+            self.control.resume(state.repairs);
+            self.clock
+                .resume_at(Duration::from_secs_f64(state.elapsed_secs));
+            self.ticks = state.ticks;
+        }
+        self.outcome = None;
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<Step> {
+        let uptime = self.tape.input("uptime", || self.clock.now());
+        if let Some(secs) = self.config.duration_secs {
+            if uptime >= Duration::from_secs(secs) {
+                say!("Run time limit reached. Powering down the weather machine.");
+                return Ok(self.finish(Outcome::Completed));
+            }
+        }
+
+        // The machine fluctuates, then the control system reacts to the new conditions
+        self.ticks += 1;
+        self.weather_machine.fluctuate_conditions(self.ticks);
 
-    // Create a channel for communication between weather machine and control system
-    let (control_tx, control_rx) = mpsc::channel(32);
+        let step = self.control.monitor_and_regulate(&self.weather_machine);
+        self.events.emit(Event::WeatherReading {
+            temperature_c: self.weather_machine.get_temperature(),
+            wind_speed_kmh: self.weather_machine.get_wind_speed(),
+            structural_health_pct: self.weather_machine.get_structural_health(),
+        });
+        match step {
+            Step::Continue => {
+                // Sleep for a brief moment to simulate real-time monitoring
+                self.clock.sleep(TICK);
+                Ok(Step::Continue)
+            }
+            Step::Finished(outcome) => Ok(self.finish(outcome)),
+        }
+    }
 
-    // Start the weather machine
-    weather_machine.startup().await;
+    fn stop(&mut self) -> Result<()> {
+        say!("Weather machine simulation ended.");
+        Ok(())
+    }
 
-    // Clone the Arc to share the weather machine with the spawned task
-    let weather_machine_for_task = Arc::clone(&weather_machine);
+    fn telemetry(&self) -> Result<Row> {
+        let catastrophe = match &self.outcome {
+            Some(Outcome::CatastrophicEvent(kind)) => kind.clone(),
+            _ => String::new(),
+        };
+        Ok(vec![
+            (
+                "temperature_c",
+                self.weather_machine.get_temperature().to_string(),
+            ),
+            (
+                "wind_speed_kmh",
+                self.weather_machine.get_wind_speed().to_string(),
+            ),
+            (
+                "structural_health_pct",
+                self.weather_machine.get_structural_health().to_string(),
+            ),
+            ("repaired_pct", self.control.repaired_pct().to_string()),
+            ("repairs", self.control.repairs().to_string()),
+            ("catastrophe", catastrophe),
+        ])
+    }
 
-    // Run the weather machine in a separate task
-    let weather_machine_task = tokio::spawn(async move {
-        weather_machine_for_task.run(control_tx).await;
-    });
+    fn inject(&mut self, event: &str) -> Result<()> {
+        match event {
+            "wind_spike" => self.weather_machine.inject_wind_spike(),
+            "catastrophe" => self.weather_machine.inject_catastrophe(),
+            _ => bail!(
+                "The weather simulation can inject {}, not '{}'",
+                faults::names(self.name()).join(", "),
+                event
+            ),
+        }
+        Ok(())
+    }
 
-    // Run the control system in the main task
-    control
-        .monitor_and_regulate(&weather_machine, Arc::clone(&shutdown_flag), control_rx)
-        .await;
+    fn snapshot(&self) -> Result<Value> {
+        Ok(serde_json::to_value(WeatherState {
+            elapsed_secs: self.clock.now().as_secs_f64(),
+            ticks: self.ticks,
+            temperature_c: self.weather_machine.get_temperature(),
+            wind_speed_kmh: self.weather_machine.get_wind_speed(),
+            structural_health_pct: self.weather_machine.get_structural_health(),
+            repairs: self.control.repairs(),
+        })?)
+    }
 
-    // Wait for the weather machine task to finish (if ever)
-    weather_machine_task.await.unwrap();
+    fn restore(&mut self, state: &Value) -> Result<()> {
+        self.restored = Some(WeatherState::deserialize(state)?);
+        Ok(())
+    }
 
-    println!("Weather machine simulation ended.");
+    fn report(&self) -> Report {
+        Report {
+            simulation: self.name(),
+            outcome: self.outcome.clone().unwrap_or(Outcome::Completed),
+            seed: self.rngs.seed(),
+            ticks: self.ticks,
+            elapsed_secs: self.clock.now().as_secs_f64(),
+            stats: vec![
+                (
+                    "temperature_c".into(),
+                    self.weather_machine.get_temperature() as f64,
+                ),
+                (
+                    "wind_speed_kmh".into(),
+                    self.weather_machine.get_wind_speed() as f64,
+                ),
+                (
+                    "structural_health_pct".into(),
+                    self.weather_machine.get_structural_health() as f64,
+                ),
+                ("repairs".into(), self.control.repairs() as f64),
+            ],
+        }
+    }
 }
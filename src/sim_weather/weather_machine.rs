@@ -1,81 +1,120 @@
+use super::WeatherConfig;
+use crate::events::{Event, EventLog};
+use crate::faults::Faults;
+use crate::rng::SimRng;
 use rand::Rng;
 use std::sync::{Arc, Mutex};
-use tokio::time::{sleep, Duration};
 
 pub struct WeatherMachine {
     pub temperature: Arc<Mutex<i32>>, // Shared state for temperature
     pub wind_speed: Arc<Mutex<i32>>,  // Shared state for wind speed
     pub structural_health: Arc<Mutex<i32>>, // Shared state for structural health
     pub catastrophic_event: Arc<Mutex<Option<String>>>, // Flag for catastrophic event with event type
+    rng: Mutex<SimRng>, // Source of every random fluctuation and event
+    config: WeatherConfig,
+    faults: Faults,
+    events: EventLog,
 }
 
 impl WeatherMachine {
-    pub fn new() -> Self {
+    pub fn new(config: WeatherConfig, faults: Faults, rng: SimRng, events: EventLog) -> Self {
         WeatherMachine {
             temperature: Arc::new(Mutex::new(20)), // Initial temperature: 20°C
             wind_speed: Arc::new(Mutex::new(10)),  // Initial wind speed: 10 km/h
             structural_health: Arc::new(Mutex::new(100)), // Initial structural health: 100%
             catastrophic_event: Arc::new(Mutex::new(None)), // Initially, no catastrophic event
+            rng: Mutex::new(rng),
+            config,
+            faults,
+            events,
         }
     }
 
-    pub async fn startup(&self) {
-        println!("Weather Machine Initialized");
-        println!("All Systems Ready\n");
+    pub fn startup(&self) {
+        say!("Weather Machine Initialized");
+        say!("All Systems Ready\n");
     }
 
-    pub async fn fluctuate_conditions(&self) {
-        let mut rng = rand::thread_rng();
+    // Ticks count from 1
+    pub fn fluctuate_conditions(&self, tick: u64) {
+        let mut rng = self.rng.lock().unwrap();
 
         // Simulate wind speed fluctuations with a wider range
         let wind_fluctuation = rng.gen_range(-10..=30);
-        let mut wind_speed = self.wind_speed.lock().unwrap();
-        *wind_speed = (*wind_speed + wind_fluctuation).clamp(0, 300); // Clamp wind speed between 0 and 300 km/h
-
-        // Adjust temperature inversely proportional to wind speed
-        let base_temp = 25; // Base temperature when wind speed is 0
-        let temp_decrease = (*wind_speed / 4).clamp(0, 20); // Decrease temp as wind increases
-        let mut temp = self.temperature.lock().unwrap();
-        *temp = (base_temp - temp_decrease).clamp(0, 50); // Clamp temperature between 0°C and 50°C
+        {
+            let mut wind_speed = self.wind_speed.lock().unwrap();
+            *wind_speed = (*wind_speed + wind_fluctuation).clamp(0, 300); // Clamp wind speed between 0 and 300 km/h
+
+            // Adjust temperature inversely proportional to wind speed
+            let base_temp = 25; // Base temperature when wind speed is 0
+            let temp_decrease = (*wind_speed / 4).clamp(0, 20); // Decrease temp as wind increases
+            let mut temp = self.temperature.lock().unwrap();
+            *temp = (base_temp - temp_decrease).clamp(0, 50); // Clamp temperature between 0°C and 50°C
+        }
 
         // Apply random damage if wind speed spike occurs
-        if rng.gen_bool(0.2) {
-            // 20% chance for a wind speed spike
-            println!("Warning: Wind speed spike detected! Incurring Structural Damage.");
-            let damage_amount = if rng.gen_bool(0.5) { 10 } else { 30 }; // Randomly choose 10% or 30% damage
-            let mut health = self.structural_health.lock().unwrap();
-            *health = (*health - damage_amount).max(0); // Apply damage, ensuring health doesn't drop below 0%
-            println!("Structural health decreased by {}%", damage_amount);
+        if self
+            .faults
+            .strikes("wind_spike", tick, self.config.wind_spike_chance, &mut *rng)
+        {
+            let damage_amount = spike_damage(&mut rng);
+            self.wind_spike(damage_amount);
         }
 
         // Check for a catastrophic event
-        if rng.gen_bool(0.05) {
-            // 5% chance for a catastrophic event
-            let event_type = match rng.gen_range(0..3) {
-                0 => "Volcano Eruption",
-                1 => "Tornado",
-                _ => "Earthquake",
-            };
-
-            let mut catastrophic_event = self.catastrophic_event.lock().unwrap();
-            *catastrophic_event = Some(event_type.to_string());
-
-            // Set extreme conditions based on the event type
-            match event_type {
-                "Volcano Eruption" => {
-                    *temp = 100; // Extreme temperature due to volcanic heat
-                    *wind_speed = 300; // Max wind speed
-                }
-                "Tornado" => {
-                    *temp = 0; // Sudden drop in temperature
-                    *wind_speed = 300; // Max wind speed
-                }
-                "Earthquake" => {
-                    *temp = 50; // Moderate temperature increase due to fires
-                    *wind_speed = 200; // High wind speed from shockwaves
-                }
-                _ => {}
+        if self.faults.strikes(
+            "catastrophe",
+            tick,
+            self.config.catastrophe_chance,
+            &mut *rng,
+        ) {
+            let event_type = catastrophe_kind(&mut rng);
+            self.catastrophe(event_type);
+        }
+    }
+
+    // Faults triggered on demand rather than by chance, e.g. through the remote control API
+    pub fn inject_wind_spike(&self) {
+        let damage_amount = spike_damage(&mut self.rng.lock().unwrap());
+        self.wind_spike(damage_amount);
+    }
+
+    pub fn inject_catastrophe(&self) {
+        let event_type = catastrophe_kind(&mut self.rng.lock().unwrap());
+        self.catastrophe(event_type);
+    }
+
+    fn wind_spike(&self, damage_amount: i32) {
+        say!("Warning: Wind speed spike detected! Incurring Structural Damage.");
+        let mut health = self.structural_health.lock().unwrap();
+        *health = (*health - damage_amount).max(0); // Apply damage, ensuring health doesn't drop below 0%
+        say!("Structural health decreased by {}%", damage_amount);
+        self.events.emit(Event::WindSpike {
+            damage_pct: damage_amount,
+        });
+    }
+
+    fn catastrophe(&self, event_type: &str) {
+        let mut catastrophic_event = self.catastrophic_event.lock().unwrap();
+        *catastrophic_event = Some(event_type.to_string());
+
+        // Set extreme conditions based on the event type
+        let mut temp = self.temperature.lock().unwrap();
+        let mut wind_speed = self.wind_speed.lock().unwrap();
+        match event_type {
+            "Volcano Eruption" => {
+                *temp = 100; // Extreme temperature due to volcanic heat
+                *wind_speed = 300; // Max wind speed
+            }
+            "Tornado" => {
+                *temp = 0; // Sudden drop in temperature
+                *wind_speed = 300; // Max wind speed
+            }
+            "Earthquake" => {
+                *temp = 50; // Moderate temperature increase due to fires
+                *wind_speed = 200; // High wind speed from shockwaves
             }
+            _ => {}
         }
     }
 
@@ -99,18 +138,21 @@ impl WeatherMachine {
         let mut health = self.structural_health.lock().unwrap();
         *health = (*health + amount).min(100); // Repair up to 100% health
     }
+}
 
-    pub async fn run(&self, control_tx: tokio::sync::mpsc::Sender<()>) {
-        loop {
-            self.fluctuate_conditions().await;
-
-            // Notify the control system about the current state, including potential catastrophic events
-            if let Err(e) = control_tx.send(()).await {
-                eprintln!("Failed to send message to control system: {}", e);
-                break;
-            }
+// Randomly choose 10% or 30% damage
+fn spike_damage(rng: &mut SimRng) -> i32 {
+    if rng.gen_bool(0.5) {
+        10
+    } else {
+        30
+    }
+}
 
-            sleep(Duration::from_secs(2)).await;
-        }
+fn catastrophe_kind(rng: &mut SimRng) -> &'static str {
+    match rng.gen_range(0..3) {
+        0 => "Volcano Eruption",
+        1 => "Tornado",
+        _ => "Earthquake",
     }
 }
@@ -0,0 +1,243 @@
+use anyhow::{bail, Context, Result};
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::events::Event;
+use crate::sim_cafe::CafeSimulation;
+use crate::sim_factory::FactorySimulation;
+use crate::sim_home::HomeSimulation;
+use crate::sim_nuclear::NuclearSimulation;
+use crate::sim_town::TownSimulation;
+use crate::sim_weather::WeatherSimulation;
+use crate::snapshot::Snapshot;
+use crate::telemetry::{Row, TelemetryWriter};
+
+// Result of advancing a simulation by one tick
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    Continue,
+    Finished(Outcome),
+}
+
+// How a run ended
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Completed,                 // Ran for its configured duration or number of cycles
+    Scram,                     // Reactor shut down after reaching critical power
+    Evacuation,                // Reactor shut down and site evacuated after a radiation leak
+    CatastrophicEvent(String), // Weather machine hit by a catastrophic event
+    StructuralFailure,         // Weather machine structural health reached 0%
+    Stopped,                   // Stopped on request before finishing by itself
+}
+
+impl Outcome {
+    // Name of the kind of outcome, without any details
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Outcome::Completed => "completed",
+            Outcome::Scram => "scram",
+            Outcome::Evacuation => "evacuation",
+            Outcome::CatastrophicEvent(_) => "catastrophic_event",
+            Outcome::StructuralFailure => "structural_failure",
+            Outcome::Stopped => "stopped",
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Completed => write!(f, "completed"),
+            Outcome::Scram => write!(f, "SCRAM"),
+            Outcome::Evacuation => write!(f, "evacuation"),
+            Outcome::CatastrophicEvent(event) => write!(f, "catastrophic event ({})", event),
+            Outcome::StructuralFailure => write!(f, "structural failure"),
+            Outcome::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+// Final summary of a run, produced after the simulation has stopped
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    pub simulation: &'static str,
+    pub outcome: Outcome,
+    pub seed: u64,
+    pub ticks: u64,
+    pub elapsed_secs: f64, // Simulated time at the end of the run
+    #[serde(serialize_with = "serialize_stats")]
+    pub stats: Vec<(Cow<'static, str>, f64)>,
+}
+
+// Stats serialize as an object keyed by stat name
+fn serialize_stats<S: Serializer>(
+    stats: &[(Cow<'static, str>, f64)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(stats.iter().map(|(name, value)| (name, value)))
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "=== {} report ===", self.simulation)?;
+        writeln!(f, "outcome: {}", self.outcome)?;
+        writeln!(f, "seed: {}", self.seed)?;
+        writeln!(f, "ticks: {}", self.ticks)?;
+        writeln!(f, "elapsed_secs: {}", self.elapsed_secs)?;
+        for (name, value) in &self.stats {
+            writeln!(f, "{}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+// Lifecycle shared by every simulation so callers can drive them uniformly
+pub trait Simulation {
+    fn name(&self) -> &'static str;
+
+    // Clock the simulation runs on; a deadlock on it fails the run
+    fn clock(&self) -> &Clock;
+
+    // Pick up this simulation's section of the configuration; called before `start`
+    fn configure(&mut self, config: &Config) -> Result<()>;
+
+    // Initialise state and spawn any worker threads
+    fn start(&mut self) -> Result<()>;
+
+    // Advance by one tick, blocking for as long as the tick takes
+    fn step(&mut self) -> Result<Step>;
+
+    // Release resources and join worker threads
+    fn stop(&mut self) -> Result<()>;
+
+    fn report(&self) -> Report;
+
+    // Readings and actions of the latest tick, written as one CSV row per tick
+    fn telemetry(&self) -> Result<Row> {
+        bail!("The {} simulation has no telemetry", self.name())
+    }
+
+    // Trigger a named event on demand, e.g. a coolant failure requested through the remote API
+    fn inject(&mut self, event: &str) -> Result<()> {
+        bail!("The {} simulation cannot inject '{}'", self.name(), event)
+    }
+
+    // Complete state of the running simulation, taken between steps
+    fn snapshot(&self) -> Result<Value> {
+        bail!("The {} simulation cannot be snapshotted", self.name())
+    }
+
+    // Carry on from a snapshot instead of starting afresh; called after `configure`, before `start`
+    fn restore(&mut self, state: &Value) -> Result<()> {
+        let _ = state;
+        bail!("The {} simulation cannot be restored", self.name())
+    }
+}
+
+// Create a simulation by name
+pub fn create(name: &str) -> Option<Box<dyn Simulation>> {
+    match name {
+        "cafe" => Some(Box::new(CafeSimulation::new())),
+        "factory" => Some(Box::new(FactorySimulation::new())),
+        "home" => Some(Box::new(HomeSimulation::new())),
+        "nuclear" => Some(Box::new(NuclearSimulation::new())),
+        "weather" => Some(Box::new(WeatherSimulation::new())),
+        "town" => Some(Box::new(TownSimulation::new())),
+        _ => None,
+    }
+}
+
+// Drive a simulation through its whole lifecycle
+pub fn run(simulation: &mut dyn Simulation, config: &Config) -> Result<Report> {
+    drive(simulation, config, None)
+}
+
+// Drive a simulation from the state saved in a snapshot to the end of its run
+pub fn resume(simulation: &mut dyn Simulation, config: &Config, state: &Value) -> Result<Report> {
+    drive(simulation, config, Some(state))
+}
+
+fn drive(
+    simulation: &mut dyn Simulation,
+    config: &Config,
+    restore: Option<&Value>,
+) -> Result<Report> {
+    let name = simulation.name();
+    let _running = config.cancel.enter();
+    let mut telemetry = match &config.telemetry {
+        Some(path) => Some(TelemetryWriter::create(path)?),
+        None => None,
+    };
+    simulation
+        .configure(config)
+        .with_context(|| format!("Failed to configure {} simulation", name))?;
+    if let Some(state) = restore {
+        simulation
+            .restore(state)
+            .with_context(|| format!("Failed to restore {} simulation", name))?;
+    }
+    simulation
+        .start()
+        .with_context(|| format!("Failed to start {} simulation", name))?;
+    let events = config.events.scoped(name, None);
+    events.emit(Event::SimulationStarted);
+
+    // Always stop, even if a step failed, so worker threads are not left behind.
+    // Cancellation, checkpoints and remote requests are handled between steps, telemetry after each.
+    let mut stop_requested = false;
+    let at_tick = config.checkpoint.at_tick;
+    let stepped = loop {
+        if config.cancel.is_cancelled()
+            || config.remote.handle(simulation, config)
+            || at_tick.is_some_and(|tick| simulation.report().ticks >= tick)
+        {
+            stop_requested = true;
+            break Ok(());
+        }
+        let step = simulation
+            .step()
+            .and_then(|step| simulation.clock().check().map(|()| step));
+        if let (Ok(_), Some(writer)) = (&step, &mut telemetry) {
+            let report = simulation.report();
+            let written = simulation
+                .telemetry()
+                .and_then(|row| writer.write(report.ticks, report.elapsed_secs, row))
+                .context("Failed to write telemetry");
+            if let Err(e) = written {
+                break Err(e);
+            }
+        }
+        match step {
+            Ok(Step::Continue) => continue,
+            Ok(Step::Finished(_)) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+
+    // Saved before stopping, which shuts reactors down and serves every waiting customer. A run
+    // that finished by itself has nothing left to resume, so it is not saved.
+    let saved = match (&stepped, &config.checkpoint.path) {
+        (Ok(()), Some(path)) if stop_requested => {
+            Snapshot::take(simulation, config).and_then(|s| s.save(path))
+        }
+        _ => Ok(()),
+    };
+    let stopped = simulation.stop().and_then(|()| simulation.clock().check());
+    stepped.with_context(|| format!("{} simulation failed", name))?;
+    stopped.with_context(|| format!("Failed to stop {} simulation", name))?;
+    saved.with_context(|| format!("Failed to save a snapshot of the {} simulation", name))?;
+
+    let mut report = simulation.report();
+    if stop_requested {
+        report.outcome = Outcome::Stopped;
+    }
+    events.emit(Event::SimulationFinished {
+        outcome: report.outcome.clone(),
+    });
+    Ok(report)
+}
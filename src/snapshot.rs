@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::simulation::Simulation;
+
+// When to save a snapshot of a run, and where
+#[derive(Clone, Debug, Default)]
+pub struct Checkpoint {
+    pub path: Option<PathBuf>, // Saved when the run ends, just before the simulation is stopped
+    pub at_tick: Option<u64>,  // End the run once it has taken this many ticks
+}
+
+// Complete state of a running simulation, from which it can be resumed any number of times
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub simulation: String,
+    pub config: Config,
+    pub state: Value, // Simulation-specific, as returned by `Simulation::snapshot`
+}
+
+impl Snapshot {
+    pub fn take(simulation: &dyn Simulation, config: &Config) -> Result<Snapshot> {
+        Ok(Snapshot {
+            simulation: simulation.name().to_string(),
+            config: config.clone(),
+            state: simulation.snapshot()?,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create snapshot {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Snapshot> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open snapshot {}", path.display()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Failed to read snapshot {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ClockMode;
+    use crate::faults::FaultConfig;
+    use crate::simulation::{self, Outcome, Step};
+
+    // A snapshot file in the temp directory, removed when dropped
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            TempPath(std::env::temp_dir().join(format!("{}-{}", std::process::id(), name)))
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn config(seed: u64, faults: &[(&str, FaultConfig)]) -> Config {
+        Config {
+            seed: Some(seed),
+            clock: ClockMode::Virtual,
+            faults: faults
+                .iter()
+                .map(|(name, fault)| (name.to_string(), fault.clone()))
+                .collect(),
+            ..Config::default()
+        }
+    }
+
+    fn disabled() -> FaultConfig {
+        FaultConfig {
+            disabled: true,
+            ..FaultConfig::default()
+        }
+    }
+
+    fn at_tick(tick: u64) -> FaultConfig {
+        FaultConfig {
+            at_ticks: vec![tick],
+            ..disabled()
+        }
+    }
+
+    // Run until `at_tick`, save the snapshot and load it back
+    fn checkpoint(name: &str, config: &Config, at_tick: u64, path: &TempPath) -> Snapshot {
+        let config = Config {
+            checkpoint: Checkpoint {
+                path: Some(path.0.clone()),
+                at_tick: Some(at_tick),
+            },
+            ..config.clone()
+        };
+        let mut simulation = simulation::create(name).unwrap();
+        let report = simulation::run(simulation.as_mut(), &config).unwrap();
+        assert_eq!(report.outcome, Outcome::Stopped);
+        assert_eq!(report.ticks, at_tick);
+        Snapshot::load(&path.0).unwrap()
+    }
+
+    // Restore a simulation from a snapshot, ready to step on
+    fn restored(snapshot: &Snapshot) -> Box<dyn Simulation> {
+        let mut simulation = simulation::create(&snapshot.simulation).unwrap();
+        simulation.configure(&snapshot.config).unwrap();
+        simulation.restore(&snapshot.state).unwrap();
+        simulation.start().unwrap();
+        simulation
+    }
+
+    #[test]
+    fn a_reactor_carries_on_from_its_snapshot() {
+        let faults = [
+            ("coolant_failure", disabled()),
+            ("power_surge", disabled()),
+            ("radiation_leak", disabled()),
+        ];
+        let path = TempPath::new("reactor.json");
+        let snapshot = checkpoint("nuclear", &config(4, &faults), 5, &path);
+        assert_eq!(snapshot.simulation, "nuclear");
+
+        let mut simulation = restored(&snapshot);
+        assert_eq!(simulation.snapshot().unwrap(), snapshot.state);
+        assert_eq!(simulation.report().ticks, 5);
+        assert!(matches!(simulation.step().unwrap(), Step::Continue));
+        assert_eq!(simulation.report().ticks, 6);
+        simulation.stop().unwrap();
+    }
+
+    #[test]
+    fn a_cafe_carries_on_from_its_snapshot() {
+        let path = TempPath::new("cafe.json");
+        let snapshot = checkpoint("cafe", &config(3, &[]), 4, &path);
+        let placed = snapshot.state["next_ticket"].as_u64().unwrap() - 1;
+        assert!(placed > 0);
+
+        let mut simulation = restored(&snapshot);
+        assert_eq!(simulation.report().ticks, 4);
+        while let Step::Continue = simulation.step().unwrap() {}
+        simulation.stop().unwrap();
+
+        let report = simulation.report();
+        let stat = |name: &str| {
+            report
+                .stats
+                .iter()
+                .find(|(stat, _)| *stat == name)
+                .unwrap()
+                .1
+        };
+        assert!(report.ticks > 4);
+        assert!(stat("orders_placed") >= placed as f64);
+        assert_eq!(
+            stat("orders_placed"),
+            stat("orders_served") + stat("orders_cancelled")
+        );
+    }
+
+    #[test]
+    fn a_run_that_finished_by_itself_is_not_saved() {
+        let finished = [
+            ("nuclear", config(7, &[("power_surge", at_tick(3))])),
+            ("weather", config(3, &[("catastrophe", at_tick(2))])),
+        ];
+        for (name, config) in finished {
+            let path = TempPath::new(&format!("{}-finished.json", name));
+            let config = Config {
+                checkpoint: Checkpoint {
+                    path: Some(path.0.clone()),
+                    at_tick: Some(1000),
+                },
+                ..config
+            };
+            let mut simulation = simulation::create(name).unwrap();
+            let report = simulation::run(simulation.as_mut(), &config).unwrap();
+            assert_ne!(
+                report.outcome,
+                Outcome::Stopped,
+                "{} should have finished",
+                name
+            );
+            assert!(!path.0.exists(), "{} was saved after it finished", name);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+// Summary of a set of samples
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Distribution {
+    pub count: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+    // All zero when there are no samples
+    pub fn new(samples: &[f64]) -> Self {
+        if samples.is_empty() {
+            return Distribution::default();
+        }
+
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let mean = mean(&sorted);
+        let variance = sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / sorted.len() as f64;
+
+        Distribution {
+            count: sorted.len(),
+            mean,
+            std_dev: variance.sqrt(),
+            min: sorted[0],
+            p50: percentile(&sorted, 50.0),
+            p90: percentile(&sorted, 90.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+pub fn mean(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        0.0
+    } else {
+        samples.iter().sum::<f64>() / samples.len() as f64
+    }
+}
+
+// Nearest-rank percentile of samples that are already sorted
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let samples: Vec<f64> = (1..=10).rev().map(f64::from).collect();
+        let spread = Distribution::new(&samples);
+        assert_eq!(spread.count, 10);
+        assert_eq!(spread.min, 1.0);
+        assert_eq!(spread.p50, 5.0);
+        assert_eq!(spread.p90, 9.0);
+        assert_eq!(spread.p95, 10.0);
+        assert_eq!(spread.p99, 10.0);
+        assert_eq!(spread.max, 10.0);
+        assert_eq!(spread.mean, 5.5);
+
+        let hundred: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&hundred, 50.0), 50.0);
+        assert_eq!(percentile(&hundred, 90.0), 90.0);
+        assert_eq!(percentile(&hundred, 95.0), 95.0);
+        assert_eq!(percentile(&hundred, 99.0), 99.0);
+        assert_eq!(percentile(&hundred, 0.0), 1.0);
+    }
+
+    #[test]
+    fn no_samples_give_all_zeros() {
+        assert_eq!(Distribution::new(&[]), Distribution::default());
+        assert_eq!(percentile(&[], 50.0), 0.0);
+        assert_eq!(mean(&[]), 0.0);
+    }
+
+    #[test]
+    fn a_single_sample_is_every_percentile() {
+        let spread = Distribution::new(&[4.0]);
+        assert_eq!(spread.count, 1);
+        assert_eq!(spread.std_dev, 0.0);
+        for value in [
+            spread.min, spread.p50, spread.p90, spread.p95, spread.p99, spread.max,
+        ] {
+            assert_eq!(value, 4.0);
+        }
+    }
+}
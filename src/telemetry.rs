@@ -0,0 +1,82 @@
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// One tick of a simulation's telemetry: column names and their values
+pub type Row = Vec<(&'static str, String)>;
+
+// Writes a row per tick as CSV, with a header taken from the first row
+pub struct TelemetryWriter {
+    writer: BufWriter<File>,
+    columns: Option<Vec<&'static str>>,
+    last_tick: Option<u64>,
+}
+
+impl TelemetryWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create telemetry file {}", path.display()))?;
+        Ok(TelemetryWriter {
+            writer: BufWriter::new(file),
+            columns: None,
+            last_tick: None,
+        })
+    }
+
+    // Every row is flushed so the file is usable even if the run is cut short.
+    // A step that ends the run without taking another tick adds no row.
+    pub fn write(&mut self, tick: u64, sim_time_secs: f64, row: Row) -> Result<()> {
+        if self.last_tick == Some(tick) {
+            return Ok(());
+        }
+        self.last_tick = Some(tick);
+
+        let columns: Vec<&'static str> = row.iter().map(|(column, _)| *column).collect();
+        match &self.columns {
+            None => {
+                let header: Vec<String> = ["tick", "sim_time_secs"]
+                    .iter()
+                    .chain(&columns)
+                    .map(|column| csv_field(column))
+                    .collect();
+                writeln!(self.writer, "{}", header.join(","))?;
+                self.columns = Some(columns);
+            }
+            Some(header) if *header != columns => {
+                bail!("Telemetry columns changed during the run")
+            }
+            Some(_) => {}
+        }
+
+        let mut fields = vec![tick.to_string(), sim_time_secs.to_string()];
+        fields.extend(row.iter().map(|(_, value)| csv_field(value)));
+        writeln!(self.writer, "{}", fields.join(","))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Quote a CSV field when it contains a separator, quote or line break; shared by every CSV writer
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("carriage\rreturn"), "\"carriage\rreturn\"");
+    }
+}